@@ -1,8 +1,13 @@
-use rs_vm::{script::Script, vm::{BANNER, VERSION, Vm}};
+use rs_vm::{loader::MemoryLoader, script::Script, vm::{BANNER, VERSION, Vm}};
 
 fn main() {
   println!("{} v{}", BANNER, VERSION);
   let mut vm = Vm::default();
+  vm.set_loader(
+    MemoryLoader::new()
+      .with("virtual://test_script", "import { greet } from \"./greet\";\ngreet(\"memory\");")
+      .with("virtual://greet", "export function greet(name) {\n  println(\"Hello from \" + name + \"!\");\n}"),
+  );
 
   vm.scripts_mut().push(Script::new("virtual://test_script", Some("test_script"), None));
 
@@ -10,5 +15,8 @@ fn main() {
     println!(" - {}", it)
   }
 
-  vm.run().unwrap();
+  if let Err(e) = vm.run() {
+    eprint!("{}", vm.render_error(&e));
+    std::process::exit(1);
+  }
 }
@@ -29,5 +29,8 @@ fn main() {
     println!(" - {}", it)
   }
 
-  vm.run().unwrap();
+  if let Err(e) = vm.run() {
+    eprint!("{}", vm.render_error(&e));
+    std::process::exit(1);
+  }
 }
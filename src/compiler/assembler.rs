@@ -0,0 +1,459 @@
+use std::rc::Rc;
+
+use crate::error::Error;
+use crate::location::Location;
+use crate::parser::{Enum, OpCode, Value, Visibility};
+use crate::result::Result;
+
+use super::{Chunk, ClassProto, FunctionProto};
+
+/// A block being assembled.
+enum Pending {
+  /// A chunk, along with the function it belongs to if any.
+  Chunk(Chunk, Option<(usize, String, Vec<String>)>),
+  /// A class and the members and methods read so far.
+  Class(usize, ClassProto),
+}
+
+/// Parses a listing produced by `Disassembler` back into a `Chunk`.
+///
+/// Indentation and `; comments` are ignored, and locations are optional.
+pub struct Assembler {
+  line: u64,
+  pending: Vec<Pending>,
+  done: Option<Chunk>,
+}
+
+impl Assembler {
+  pub fn assemble<S: AsRef<str>>(text: S) -> Result<Chunk> {
+    let mut a = Assembler {
+      line: 0,
+      pending: vec![],
+      done: None,
+    };
+    for line in text.as_ref().lines() {
+      a.line += 1;
+      a.parse_line(line.trim())?;
+    }
+    if !a.pending.is_empty() {
+      return Err(a.error("unterminated chunk"));
+    }
+    a.done
+      .ok_or_else(|| Error::Syntax("missing chunk".into(), Location::new("assembly", 0, 1, 1).into()))
+  }
+
+  fn error<S: AsRef<str>>(&self, msg: S) -> Error {
+    Error::Syntax(
+      String::from(msg.as_ref()),
+      Location::new("assembly", 0, self.line, 1).into(),
+    )
+  }
+
+  fn current(&mut self) -> Result<&mut Chunk> {
+    let line = self.line;
+    match self.pending.last_mut() {
+      Some(Pending::Chunk(chunk, _)) => Ok(chunk),
+      Some(Pending::Class(..)) => Err(Error::Syntax(
+        "classes may only contain members and functions".into(),
+        Location::new("assembly", 0, line, 1).into(),
+      )),
+      None => Err(Error::Syntax(
+        "expected 'chunk' first".into(),
+        Location::new("assembly", 0, line, 1).into(),
+      )),
+    }
+  }
+
+  fn parse_line(&mut self, line: &str) -> Result<()> {
+    if line.is_empty() || line.starts_with(';') || line == "constants:" || line == "code:" {
+      return Ok(());
+    }
+    if let Some(Pending::Class(_, class)) = self.pending.last_mut() {
+      // `<visibility> <name>`
+      let member = line
+        .split_once(' ')
+        .and_then(|(v, name)| Some((name.trim().to_string(), Visibility::parse(v)?)));
+      if let Some(member) = member {
+        class.members_mut().push(member);
+        return Ok(());
+      }
+    }
+    if let Some(rest) = line.strip_prefix("chunk ") {
+      if self.done.is_some() || !self.pending.is_empty() {
+        return Err(self.error("only one top-level chunk is allowed"));
+      }
+      let name = rest
+        .strip_suffix('{')
+        .map(str::trim)
+        .ok_or_else(|| self.error("expected '{' after chunk name"))?;
+      let name = match self.parse_litteral(name)? {
+        Value::String(name) => name,
+        _ => return Err(self.error("chunk name must be a string")),
+      };
+      self.pending.push(Pending::Chunk(Chunk::new(name), None));
+    } else if let Some(rest) = line.strip_prefix("function ") {
+      if self.pending.is_empty() {
+        self.current()?;
+      }
+      let function = self.parse_function(rest)?;
+      self.pending.push(Pending::Chunk(Chunk::new(&function.1), Some(function)));
+    } else if let Some(rest) = line.strip_prefix("class ") {
+      self.current()?;
+      let (idx, name) = rest
+        .strip_suffix('{')
+        .and_then(|t| t.trim().strip_prefix('#'))
+        .and_then(|t| t.split_once(' '))
+        .ok_or_else(|| self.error("expected '#<index> <name> {' after 'class'"))?;
+      let idx = self.parse_number(idx)?;
+      let (name, superclass) = match name.split_once(" extends ") {
+        Some((name, base)) => (name, Some(base.trim().to_string())),
+        None => (name, None),
+      };
+      let class = ClassProto::new(name.trim().to_string(), superclass, vec![]);
+      self.pending.push(Pending::Class(idx, class));
+    } else if line == "}" {
+      let p = self
+        .pending
+        .pop()
+        .ok_or_else(|| self.error("unexpected '}'"))?;
+      self.close(p)?;
+    } else if let Some(rest) = line.strip_prefix('#') {
+      let (idx, value) = rest
+        .split_once('=')
+        .ok_or_else(|| self.error("expected '=' after constant index"))?;
+      let idx: usize = self.parse_number(idx.trim())?;
+      let value = self.parse_litteral(value.trim())?;
+      let chunk = self.current()?;
+      if idx != chunk.constants().len() {
+        return Err(self.error(format!("constant #{} is out of order", idx)));
+      }
+      chunk.constants_mut().push(value);
+    } else {
+      self.parse_instruction(line)?;
+    }
+    Ok(())
+  }
+
+  /// Add a finished block to the one enclosing it.
+  fn close(&mut self, p: Pending) -> Result<()> {
+    match p {
+      Pending::Chunk(chunk, None) => self.done = Some(chunk),
+      Pending::Chunk(chunk, Some((idx, name, params))) => {
+        let f = FunctionProto::new(name, params, chunk);
+        let expected = match self.pending.last_mut() {
+          Some(Pending::Class(_, class)) => {
+            class.methods_mut().push(Rc::new(f));
+            class.methods().len() - 1
+          }
+          _ => self.current()?.add_function(f),
+        };
+        if idx != expected {
+          return Err(self.error(format!("function #{} is out of order", idx)));
+        }
+      }
+      Pending::Class(idx, class) => {
+        if idx != self.current()?.add_class(class) {
+          return Err(self.error(format!("class #{} is out of order", idx)));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// `#<idx> <name>(<params>) {`
+  fn parse_function(&self, text: &str) -> Result<(usize, String, Vec<String>)> {
+    let text = text
+      .strip_suffix('{')
+      .ok_or_else(|| self.error("expected '{' after function"))?;
+    let (idx, signature) = text
+      .trim()
+      .strip_prefix('#')
+      .and_then(|t| t.split_once(' '))
+      .ok_or_else(|| self.error("expected '#<index>' after 'function'"))?;
+    let (name, params) = signature
+      .trim()
+      .strip_suffix(')')
+      .and_then(|s| s.split_once('('))
+      .ok_or_else(|| self.error("expected function parameters"))?;
+    let params = params
+      .split(',')
+      .map(str::trim)
+      .filter(|p| !p.is_empty())
+      .map(String::from)
+      .collect();
+    Ok((self.parse_number(idx)?, name.trim().to_string(), params))
+  }
+
+  /// `<offset> <mnemonic> <operands...> [@ <location>] [; <comment>]`
+  fn parse_instruction(&mut self, line: &str) -> Result<()> {
+    let (instr, loc) = match line.split_once('@') {
+      Some((instr, rest)) => {
+        let loc = rest.split(" ;").next().unwrap_or_default().trim();
+        (instr, Some(self.parse_location(loc)?))
+      }
+      None => (line.split(';').next().unwrap_or_default(), None),
+    };
+    let mut words = instr.split_whitespace();
+    let offset: usize = self.parse_number(words.next().unwrap_or_default())?;
+    let mnemonic = words
+      .next()
+      .ok_or_else(|| self.error("expected an instruction"))?;
+    let operands: Vec<&str> = words.collect();
+    let op = self.parse_op(mnemonic, &operands)?;
+    let chunk = self.current()?;
+    if offset != chunk.code().len() {
+      let expected = chunk.code().len();
+      return Err(self.error(format!(
+        "instruction at offset {} found where {} was expected",
+        offset, expected
+      )));
+    }
+    chunk.push(op, loc.unwrap_or_default());
+    Ok(())
+  }
+
+  fn parse_op(&self, mnemonic: &str, operands: &[&str]) -> Result<OpCode> {
+    let expected = match mnemonic {
+      "call_function" | "invoke" | "invoke_super" => 2,
+      "constant" | "declare_function" | "jump" | "jump_if_false" | "jump_if_true" | "enter_try"
+      | "declare_variable" | "declare_constant" | "init_variable" | "load_variable"
+      | "assign_variable" | "class" | "get_property" | "set_property" | "new" | "array"
+      | "object" | "closure" | "call" | "import" | "import_name" | "export" => 1,
+      _ => 0,
+    };
+    if operands.len() != expected {
+      return Err(self.error(format!(
+        "'{}' expects {} operand(s) but got {}",
+        mnemonic,
+        expected,
+        operands.len()
+      )));
+    }
+    let name = || operands[0].to_string();
+    Ok(match mnemonic {
+      "constant" => OpCode::Constant(self.parse_number(operands[0])?),
+      "pop" => OpCode::Pop,
+      "dup" => OpCode::Dup,
+      "dup2" => OpCode::Dup2,
+      "declare_variable" => OpCode::DeclareVariable(name()),
+      "declare_constant" => OpCode::DeclareConstant(name()),
+      "init_variable" => OpCode::InitVariable(name()),
+      "load_variable" => OpCode::LoadVariable(name()),
+      "assign_variable" => OpCode::AssignVariable(name()),
+      "push_scope" => OpCode::PushScope,
+      "pop_scope" => OpCode::PopScope,
+      "add" => OpCode::Add,
+      "subtract" => OpCode::Subtract,
+      "multiply" => OpCode::Multiply,
+      "divide" => OpCode::Divide,
+      "modulo" => OpCode::Modulo,
+      "negate" => OpCode::Negate,
+      "not" => OpCode::Not,
+      "equal" => OpCode::Equal,
+      "not_equal" => OpCode::NotEqual,
+      "less" => OpCode::Less,
+      "less_equal" => OpCode::LessEqual,
+      "greater" => OpCode::Greater,
+      "greater_equal" => OpCode::GreaterEqual,
+      "instance_of" => OpCode::InstanceOf,
+      "jump" => OpCode::Jump(self.parse_number(operands[0])?),
+      "jump_if_false" => OpCode::JumpIfFalse(self.parse_number(operands[0])?),
+      "jump_if_true" => OpCode::JumpIfTrue(self.parse_number(operands[0])?),
+      "enter_try" => OpCode::EnterTry(self.parse_number(operands[0])?),
+      "leave_try" => OpCode::LeaveTry,
+      "throw" => OpCode::Throw,
+      "declare_function" => OpCode::DeclareFunction(self.parse_number(operands[0])?),
+      "closure" => OpCode::Closure(self.parse_number(operands[0])?),
+      "call_function" => OpCode::CallFunction(name(), self.parse_number(operands[1])?),
+      "call" => OpCode::Call(self.parse_number(operands[0])?),
+      "return_value" => OpCode::ReturnValue,
+      "class" => OpCode::Class(self.parse_number(operands[0])?),
+      "get_property" => OpCode::GetProperty(name()),
+      "set_property" => OpCode::SetProperty(name()),
+      "invoke" => OpCode::Invoke(name(), self.parse_number(operands[1])?),
+      "invoke_super" => OpCode::InvokeSuper(name(), self.parse_number(operands[1])?),
+      "forward_super" => OpCode::ForwardSuper,
+      "array" => OpCode::Array(self.parse_number(operands[0])?),
+      "object" => OpCode::Object(self.parse_number(operands[0])?),
+      "get_index" => OpCode::GetIndex,
+      "set_index" => OpCode::SetIndex,
+      "new" => OpCode::New(self.parse_number(operands[0])?),
+      "import" => OpCode::Import(self.parse_number(operands[0])?),
+      "import_name" => OpCode::ImportName(name()),
+      "export" => OpCode::Export(name()),
+      _ => return Err(self.error(format!("unknown instruction '{}'", mnemonic))),
+    })
+  }
+
+  fn parse_number<T: std::str::FromStr>(&self, text: &str) -> Result<T> {
+    text
+      .parse::<T>()
+      .map_err(|_| self.error(format!("expected a number but found '{}'", text)))
+  }
+
+  /// `<file>:<line>:<column>+<offset>`
+  fn parse_location(&self, text: &str) -> Result<Location> {
+    let invalid = || self.error(format!("invalid location '{}'", text));
+    let (pos, offset) = text.rsplit_once('+').ok_or_else(invalid)?;
+    let mut parts = pos.rsplitn(3, ':');
+    let column = parts.next().ok_or_else(invalid)?;
+    let line = parts.next().ok_or_else(invalid)?;
+    let file = parts.next().ok_or_else(invalid)?;
+    Ok(Location::new(
+      file,
+      self.parse_number(offset)?,
+      self.parse_number(line)?,
+      self.parse_number(column)?,
+    ))
+  }
+
+  fn parse_litteral(&self, text: &str) -> Result<Value> {
+    if let Some(quoted) = text.strip_prefix('"') {
+      let quoted = quoted
+        .strip_suffix('"')
+        .ok_or_else(|| self.error("unterminated string"))?;
+      return self.unescape(quoted).map(Value::String);
+    }
+    // enum Name { A = 0, B = 5 }
+    if let Some(rest) = text.strip_prefix("enum ") {
+      let (name, body) = rest
+        .strip_suffix('}')
+        .and_then(|t| t.split_once('{'))
+        .ok_or_else(|| self.error("expected 'enum <name> { <variants> }'"))?;
+      let mut variants = vec![];
+      for variant in body.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let (variant, v) = variant
+          .split_once('=')
+          .ok_or_else(|| self.error(format!("expected '=' after variant '{}'", variant)))?;
+        variants.push((variant.trim().to_string(), self.parse_number(v.trim())?));
+      }
+      return Ok(Value::Enum(Rc::new(Enum::new(name.trim().to_string(), variants))));
+    }
+    Ok(match text {
+      "true" => Value::Boolean(true),
+      "false" => Value::Boolean(false),
+      "none" => Value::None,
+      _ => match text.parse::<i64>() {
+        Ok(i) => Value::Integer(i),
+        Err(_) => Value::Double(
+          text
+            .parse::<f64>()
+            .map_err(|_| self.error(format!("unsupported constant '{}'", text)))?,
+        ),
+      },
+    })
+  }
+
+  fn unescape(&self, text: &str) -> Result<String> {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+      if ch != '\\' {
+        out.push(ch);
+        continue;
+      }
+      let escaped = match chars.next() {
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some('r') => '\r',
+        Some('0') => '\0',
+        Some('u') => {
+          let code: String = chars.by_ref().take_while(|ch| *ch != '}').collect();
+          code
+            .strip_prefix('{')
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error(format!("invalid escape '\\u{}}}'", code)))?
+        }
+        Some(ch) => ch,
+        None => return Err(self.error("unterminated escape")),
+      };
+      out.push(escaped);
+    }
+    Ok(out)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+  use std::rc::Rc;
+
+  use super::*;
+  use crate::compiler::{Compiler, Disassembler};
+  use crate::environment::Environment;
+  use crate::parser::Parser;
+  use crate::script::Script;
+  use crate::vm::Vm;
+
+  fn compile(src: &str) -> Chunk {
+    let mut script = Script::new(PathBuf::from("virtual://test"), Some("test"), Some(src));
+    let ast = Parser::default().parse(&mut script).unwrap();
+    Compiler::compile(&ast, "test").unwrap()
+  }
+
+  #[test]
+  fn listings_round_trip() {
+    let chunk = compile(
+      "function f(a, b) { if (a > b) { return a; } return \"b\\n\\\"\" + b; }
+      let x = 1.0; const y = -2.5;
+      while (x < 10 || false) { x += f(x, none); }
+      class P { private v = 1; constructor(v) { this.v += v; } get() { return this.v; } }
+      class Q extends P { constructor() { super(3); } get() { return super.get() * 2; } }
+      class R extends P {}
+      enum Dir { Up, Down = 4, Left }
+      out(new P(2).get(), new Q().get(), new R(5).get(), new Q() instanceof P, Dir.Left);
+      out(((a) => [a, { k: a }])(1)[1][\"k\"]);
+      let arr = [1]; arr[0] += 2;
+      import { g as h } from \"./lib\"; import * as lib from \"./lib\"; export const z = h;",
+    );
+    let listing = Disassembler::disassemble(&chunk);
+    assert!(listing.contains("function #0 f(a, b) {"));
+    assert!(listing.contains("0000  declare_function 0"));
+    assert!(listing.contains("class #0 P {"));
+    assert!(listing.contains("class #1 Q extends P {"));
+    assert!(listing.contains("private v"));
+    let assembled = Assembler::assemble(&listing).unwrap();
+    assert_eq!(assembled, chunk);
+    assert_eq!(Disassembler::disassemble(&assembled), listing);
+  }
+
+  #[test]
+  fn hand_written_bytecode_runs() {
+    let chunk = Assembler::assemble(
+      "chunk \"manual\" {
+        constants:
+          #0 = 20
+          #1 = 2
+        code:
+          0000 constant 0
+          0001 constant 1
+          0002 call_function twice 1   ; comments are ignored
+          0003 add
+          0004 return_value
+        function #0 twice(n) {
+          0000 load_variable n
+          0001 dup
+          0002 add
+          0003 return_value
+        }
+      }",
+    )
+    .unwrap();
+    let mut vm = Vm::default();
+    let proto = chunk.functions()[0].clone();
+    Environment::declare_function(vm.globals(), proto);
+    assert_eq!(vm.execute(Rc::new(chunk)).unwrap(), Value::Integer(24));
+  }
+
+  #[test]
+  fn assembly_errors_are_located() {
+    match Assembler::assemble("chunk \"x\" {\n  0000 constant\n}") {
+      Err(Error::Syntax(msg, span)) => {
+        assert!(msg.contains("expects 1 operand"));
+        assert_eq!(*span.start().line(), 2);
+      }
+      other => panic!("expected a syntax error, got {:?}", other),
+    }
+  }
+}
@@ -0,0 +1,519 @@
+use std::rc::Rc;
+
+use crate::error::Error;
+use crate::location::Location;
+use crate::parser::{Enum, OpCode, Value, Visibility};
+use crate::result::Result;
+use crate::vm::VERSION;
+
+use super::{Chunk, ClassProto, FunctionProto};
+
+/// Leading bytes of every precompiled file.
+pub const MAGIC: &[u8; 4] = b"RSVM";
+
+/// Bumped whenever the layout below changes.
+pub const FORMAT_REVISION: u32 = 4;
+
+/// FNV-1a hash of a script source, used to tell whether a precompiled chunk is stale.
+pub fn source_hash<S: AsRef<str>>(source: S) -> u64 {
+  source.as_ref().bytes().fold(0xcbf29ce484222325, |hash, b| {
+    (hash ^ b as u64).wrapping_mul(0x100000001b3)
+  })
+}
+
+/// A compiled chunk along with what it was compiled from and by.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Precompiled {
+  version: String,
+  source_hash: u64,
+  chunk: Chunk,
+}
+
+impl Precompiled {
+  pub fn new<S: AsRef<str>>(chunk: Chunk, source: S) -> Precompiled {
+    Precompiled {
+      version: String::from(VERSION),
+      source_hash: source_hash(source),
+      chunk,
+    }
+  }
+
+  pub fn version(&self) -> &String {
+    &self.version
+  }
+
+  pub fn source_hash(&self) -> u64 {
+    self.source_hash
+  }
+
+  pub fn chunk(&self) -> &Chunk {
+    &self.chunk
+  }
+
+  pub fn into_chunk(self) -> Chunk {
+    self.chunk
+  }
+
+  /// Whether this was compiled from `source`.
+  pub fn is_compiled_from<S: AsRef<str>>(&self, source: S) -> bool {
+    self.source_hash == source_hash(source)
+  }
+
+  pub fn to_bytes(&self) -> Result<Vec<u8>> {
+    let mut w = Writer { out: vec![] };
+    w.out.extend_from_slice(MAGIC);
+    w.u32(FORMAT_REVISION);
+    w.string(&self.version);
+    w.u64(self.source_hash);
+    w.chunk(&self.chunk)?;
+    Ok(w.out)
+  }
+
+  /// Decode precompiled data, rejecting anything not produced by this version.
+  pub fn from_bytes(bytes: &[u8]) -> Result<Precompiled> {
+    let mut r = Reader { bytes, pos: 0 };
+    if r.take(MAGIC.len())? != MAGIC {
+      return Err(Error::Format("not a precompiled script".into()));
+    }
+    let revision = r.u32()?;
+    if revision != FORMAT_REVISION {
+      return Err(Error::Format(format!(
+        "unsupported format revision {}, expected {}",
+        revision, FORMAT_REVISION
+      )));
+    }
+    let version = r.string()?;
+    if version != VERSION {
+      return Err(Error::Format(format!(
+        "compiled by version {}, expected {}",
+        version, VERSION
+      )));
+    }
+    let source_hash = r.u64()?;
+    let chunk = r.chunk()?;
+    if r.pos != bytes.len() {
+      return Err(Error::Format("trailing data".into()));
+    }
+    Ok(Precompiled {
+      version,
+      source_hash,
+      chunk,
+    })
+  }
+}
+
+struct Writer {
+  out: Vec<u8>,
+}
+
+impl Writer {
+  fn u8(&mut self, v: u8) {
+    self.out.push(v);
+  }
+
+  fn u32(&mut self, v: u32) {
+    self.out.extend_from_slice(&v.to_le_bytes());
+  }
+
+  fn u64(&mut self, v: u64) {
+    self.out.extend_from_slice(&v.to_le_bytes());
+  }
+
+  fn len(&mut self, v: usize) {
+    self.u32(v as u32);
+  }
+
+  fn string(&mut self, s: &str) {
+    self.len(s.len());
+    self.out.extend_from_slice(s.as_bytes());
+  }
+
+  fn chunk(&mut self, chunk: &Chunk) -> Result<()> {
+    self.string(chunk.name());
+    self.len(chunk.constants().len());
+    for c in chunk.constants() {
+      self.value(c)?;
+    }
+    self.len(chunk.code().len());
+    for (op, loc) in chunk.code().iter().zip(chunk.locations()) {
+      self.op(op);
+      self.location(loc);
+    }
+    self.len(chunk.functions().len());
+    for f in chunk.functions() {
+      self.function(f)?;
+    }
+    self.len(chunk.classes().len());
+    for c in chunk.classes() {
+      self.string(c.name());
+      match c.superclass() {
+        Some(s) => {
+          self.u8(1);
+          self.string(s);
+        }
+        None => self.u8(0),
+      }
+      self.len(c.members().len());
+      for (name, visibility) in c.members() {
+        self.string(name);
+        self.u8(match visibility {
+          Visibility::Public => 0,
+          Visibility::Protected => 1,
+          Visibility::Private => 2,
+          Visibility::Package => 3,
+        });
+      }
+      self.len(c.methods().len());
+      for m in c.methods() {
+        self.function(m)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn function(&mut self, f: &FunctionProto) -> Result<()> {
+    self.string(f.name());
+    self.len(f.params().len());
+    for p in f.params() {
+      self.string(p);
+    }
+    self.chunk(f.chunk())
+  }
+
+  fn value(&mut self, v: &Value) -> Result<()> {
+    match v {
+      Value::None => self.u8(0),
+      Value::Boolean(b) => {
+        self.u8(1);
+        self.u8(*b as u8);
+      }
+      Value::Integer(i) => {
+        self.u8(2);
+        self.u64(*i as u64);
+      }
+      Value::Double(d) => {
+        self.u8(3);
+        self.u64(d.to_bits());
+      }
+      Value::String(s) => {
+        self.u8(4);
+        self.string(s);
+      }
+      Value::Enum(e) => {
+        self.u8(5);
+        self.string(e.name());
+        self.len(e.variants().len());
+        for (name, v) in e.variants() {
+          self.string(name);
+          self.u64(*v as u64);
+        }
+      }
+      _ => {
+        return Err(Error::Format(format!(
+          "cannot serialize a constant of type {}",
+          v.type_name()
+        )))
+      }
+    }
+    Ok(())
+  }
+
+  fn location(&mut self, loc: &Location) {
+    self.string(loc.file());
+    self.u64(*loc.offset());
+    self.u64(*loc.line());
+    self.u64(*loc.column());
+  }
+
+  fn op(&mut self, op: &OpCode) {
+    let (tag, operand) = match op {
+      OpCode::Constant(idx) => (0, Some(*idx)),
+      OpCode::Pop => (1, None),
+      OpCode::Dup => (2, None),
+      OpCode::DeclareVariable(_) => (3, None),
+      OpCode::DeclareConstant(_) => (4, None),
+      OpCode::InitVariable(_) => (5, None),
+      OpCode::LoadVariable(_) => (6, None),
+      OpCode::AssignVariable(_) => (7, None),
+      OpCode::PushScope => (8, None),
+      OpCode::PopScope => (9, None),
+      OpCode::Add => (10, None),
+      OpCode::Subtract => (11, None),
+      OpCode::Multiply => (12, None),
+      OpCode::Divide => (13, None),
+      OpCode::Modulo => (14, None),
+      OpCode::Negate => (15, None),
+      OpCode::Not => (16, None),
+      OpCode::Equal => (17, None),
+      OpCode::NotEqual => (18, None),
+      OpCode::Less => (19, None),
+      OpCode::LessEqual => (20, None),
+      OpCode::Greater => (21, None),
+      OpCode::GreaterEqual => (22, None),
+      OpCode::InstanceOf => (34, None),
+      OpCode::Jump(target) => (23, Some(*target)),
+      OpCode::JumpIfFalse(target) => (24, Some(*target)),
+      OpCode::JumpIfTrue(target) => (25, Some(*target)),
+      OpCode::DeclareFunction(idx) => (26, Some(*idx)),
+      OpCode::CallFunction(_, argc) => (27, Some(*argc)),
+      OpCode::ReturnValue => (28, None),
+      OpCode::Class(idx) => (29, Some(*idx)),
+      OpCode::GetProperty(_) => (30, None),
+      OpCode::SetProperty(_) => (31, None),
+      OpCode::Invoke(_, argc) => (32, Some(*argc)),
+      OpCode::New(argc) => (33, Some(*argc)),
+      OpCode::InvokeSuper(_, argc) => (35, Some(*argc)),
+      OpCode::EnterTry(target) => (36, Some(*target)),
+      OpCode::LeaveTry => (37, None),
+      OpCode::Throw => (38, None),
+      OpCode::Array(len) => (39, Some(*len)),
+      OpCode::Object(len) => (40, Some(*len)),
+      OpCode::GetIndex => (41, None),
+      OpCode::SetIndex => (42, None),
+      OpCode::Closure(idx) => (43, Some(*idx)),
+      OpCode::Call(argc) => (44, Some(*argc)),
+      OpCode::Import(idx) => (45, Some(*idx)),
+      OpCode::ImportName(_) => (46, None),
+      OpCode::Export(_) => (47, None),
+      OpCode::Dup2 => (48, None),
+      OpCode::ForwardSuper => (49, None),
+    };
+    self.u8(tag);
+    match op {
+      OpCode::DeclareVariable(name)
+      | OpCode::DeclareConstant(name)
+      | OpCode::InitVariable(name)
+      | OpCode::LoadVariable(name)
+      | OpCode::AssignVariable(name)
+      | OpCode::GetProperty(name)
+      | OpCode::SetProperty(name)
+      | OpCode::ImportName(name)
+      | OpCode::Export(name)
+      | OpCode::Invoke(name, _)
+      | OpCode::InvokeSuper(name, _)
+      | OpCode::CallFunction(name, _) => self.string(name),
+      _ => {}
+    }
+    if let Some(operand) = operand {
+      self.len(operand);
+    }
+  }
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+}
+
+impl Reader<'_> {
+  fn take(&mut self, n: usize) -> Result<&[u8]> {
+    let end = self.pos + n;
+    let slice = self
+      .bytes
+      .get(self.pos..end)
+      .ok_or_else(|| Error::Format("unexpected end of data".into()))?;
+    self.pos = end;
+    Ok(slice)
+  }
+
+  fn u8(&mut self) -> Result<u8> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u32(&mut self) -> Result<u32> {
+    Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn u64(&mut self) -> Result<u64> {
+    Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  fn len(&mut self) -> Result<usize> {
+    self.u32().map(|v| v as usize)
+  }
+
+  fn string(&mut self) -> Result<String> {
+    let len = self.len()?;
+    String::from_utf8(self.take(len)?.to_vec())
+      .map_err(|e| Error::Format(format!("invalid string: {}", e)))
+  }
+
+  fn chunk(&mut self) -> Result<Chunk> {
+    let mut chunk = Chunk::new(self.string()?);
+    for _ in 0..self.len()? {
+      let v = self.value()?;
+      chunk.constants_mut().push(v);
+    }
+    for _ in 0..self.len()? {
+      let op = self.op()?;
+      let loc = self.location()?;
+      chunk.push(op, loc);
+    }
+    for _ in 0..self.len()? {
+      let f = self.function()?;
+      chunk.functions_mut().push(f);
+    }
+    for _ in 0..self.len()? {
+      let name = self.string()?;
+      let superclass = match self.u8()? {
+        0 => None,
+        _ => Some(self.string()?),
+      };
+      let mut class = ClassProto::new(name, superclass, vec![]);
+      for _ in 0..self.len()? {
+        let name = self.string()?;
+        let visibility = match self.u8()? {
+          0 => Visibility::Public,
+          1 => Visibility::Protected,
+          2 => Visibility::Private,
+          3 => Visibility::Package,
+          tag => return Err(Error::Format(format!("invalid visibility tag {}", tag))),
+        };
+        class.members_mut().push((name, visibility));
+      }
+      for _ in 0..self.len()? {
+        let m = self.function()?;
+        class.methods_mut().push(m);
+      }
+      chunk.add_class(class);
+    }
+    Ok(chunk)
+  }
+
+  fn function(&mut self) -> Result<Rc<FunctionProto>> {
+    let name = self.string()?;
+    let mut params = vec![];
+    for _ in 0..self.len()? {
+      params.push(self.string()?);
+    }
+    Ok(Rc::new(FunctionProto::new(name, params, self.chunk()?)))
+  }
+
+  fn value(&mut self) -> Result<Value> {
+    Ok(match self.u8()? {
+      0 => Value::None,
+      1 => Value::Boolean(self.u8()? != 0),
+      2 => Value::Integer(self.u64()? as i64),
+      3 => Value::Double(f64::from_bits(self.u64()?)),
+      4 => Value::String(self.string()?),
+      5 => {
+        let name = self.string()?;
+        let mut variants = vec![];
+        for _ in 0..self.len()? {
+          variants.push((self.string()?, self.u64()? as i64));
+        }
+        Value::Enum(Rc::new(Enum::new(name, variants)))
+      }
+      tag => return Err(Error::Format(format!("invalid constant tag {}", tag))),
+    })
+  }
+
+  fn location(&mut self) -> Result<Location> {
+    let file = self.string()?;
+    Ok(Location::new(file, self.u64()?, self.u64()?, self.u64()?))
+  }
+
+  fn op(&mut self) -> Result<OpCode> {
+    Ok(match self.u8()? {
+      0 => OpCode::Constant(self.len()?),
+      1 => OpCode::Pop,
+      2 => OpCode::Dup,
+      3 => OpCode::DeclareVariable(self.string()?),
+      4 => OpCode::DeclareConstant(self.string()?),
+      5 => OpCode::InitVariable(self.string()?),
+      6 => OpCode::LoadVariable(self.string()?),
+      7 => OpCode::AssignVariable(self.string()?),
+      8 => OpCode::PushScope,
+      9 => OpCode::PopScope,
+      10 => OpCode::Add,
+      11 => OpCode::Subtract,
+      12 => OpCode::Multiply,
+      13 => OpCode::Divide,
+      14 => OpCode::Modulo,
+      15 => OpCode::Negate,
+      16 => OpCode::Not,
+      17 => OpCode::Equal,
+      18 => OpCode::NotEqual,
+      19 => OpCode::Less,
+      20 => OpCode::LessEqual,
+      21 => OpCode::Greater,
+      22 => OpCode::GreaterEqual,
+      23 => OpCode::Jump(self.len()?),
+      24 => OpCode::JumpIfFalse(self.len()?),
+      25 => OpCode::JumpIfTrue(self.len()?),
+      26 => OpCode::DeclareFunction(self.len()?),
+      27 => OpCode::CallFunction(self.string()?, self.len()?),
+      28 => OpCode::ReturnValue,
+      29 => OpCode::Class(self.len()?),
+      30 => OpCode::GetProperty(self.string()?),
+      31 => OpCode::SetProperty(self.string()?),
+      32 => OpCode::Invoke(self.string()?, self.len()?),
+      33 => OpCode::New(self.len()?),
+      34 => OpCode::InstanceOf,
+      35 => OpCode::InvokeSuper(self.string()?, self.len()?),
+      36 => OpCode::EnterTry(self.len()?),
+      37 => OpCode::LeaveTry,
+      38 => OpCode::Throw,
+      39 => OpCode::Array(self.len()?),
+      40 => OpCode::Object(self.len()?),
+      41 => OpCode::GetIndex,
+      42 => OpCode::SetIndex,
+      43 => OpCode::Closure(self.len()?),
+      44 => OpCode::Call(self.len()?),
+      45 => OpCode::Import(self.len()?),
+      46 => OpCode::ImportName(self.string()?),
+      47 => OpCode::Export(self.string()?),
+      48 => OpCode::Dup2,
+      49 => OpCode::ForwardSuper,
+      tag => return Err(Error::Format(format!("invalid instruction tag {}", tag))),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use super::*;
+  use crate::compiler::Compiler;
+  use crate::parser::Parser;
+  use crate::script::Script;
+
+  const SOURCE: &str = "function f(a) { return a * 2.5 + \"x\"; }
+    let i = 0; while (i < 3) { i += 1; if (i == 2) { continue; } f(i); }
+    class C { protected x = 1; m(a) { return this.x + a; } } new C().m(2);
+    class D extends C { m(a) { return super.m(a); } } new D() instanceof C;
+    enum E { A, B = -2, C } E.C;
+    try { throw 1; } catch (e) { f(e); } finally { f(2); }
+    let o = { a: [1, 2], \"b\": 3 }; o.a[0] = o[\"b\"]; o.a[1] += 1;
+    const g = (x) => y => x + y; g(1)(2);
+    import { h, k as l } from \"./lib\"; import * as lib from \"./lib\"; export let m = l;";
+
+  fn precompiled() -> Precompiled {
+    let mut script = Script::new(PathBuf::from("virtual://test"), Some("test"), Some(SOURCE));
+    let ast = Parser::default().parse(&mut script).unwrap();
+    Precompiled::new(Compiler::compile(&ast, "test").unwrap(), SOURCE)
+  }
+
+  #[test]
+  fn chunks_round_trip() {
+    let p = precompiled();
+    let decoded = Precompiled::from_bytes(&p.to_bytes().unwrap()).unwrap();
+    assert_eq!(decoded, p);
+    assert!(decoded.is_compiled_from(SOURCE));
+    assert!(!decoded.is_compiled_from("let i = 1;"));
+  }
+
+  #[test]
+  fn mismatched_versions_are_rejected() {
+    let mut p = precompiled();
+    p.version = "0.0.0-old".into();
+    match Precompiled::from_bytes(&p.to_bytes().unwrap()) {
+      Err(Error::Format(msg)) => assert!(msg.contains("0.0.0-old")),
+      other => panic!("expected a format error, got {:?}", other),
+    }
+    let bytes = precompiled().to_bytes().unwrap();
+    assert!(Precompiled::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Precompiled::from_bytes(b"nope").is_err());
+  }
+}
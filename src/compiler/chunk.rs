@@ -0,0 +1,221 @@
+use std::rc::Rc;
+
+use crate::location::Location;
+use crate::parser::{OpCode, Value, Visibility};
+
+/// A flat sequence of instructions along with the data they refer to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chunk {
+  name: String,
+  code: Vec<OpCode>,
+  locations: Vec<Location>,
+  constants: Vec<Value>,
+  functions: Vec<Rc<FunctionProto>>,
+  classes: Vec<Rc<ClassProto>>,
+}
+
+impl Chunk {
+  pub fn new<S: AsRef<str>>(name: S) -> Chunk {
+    Chunk {
+      name: String::from(name.as_ref()),
+      ..Default::default()
+    }
+  }
+
+  pub fn name(&self) -> &String {
+    &self.name
+  }
+
+  pub fn name_mut(&mut self) -> &mut String {
+    &mut self.name
+  }
+
+  pub fn code(&self) -> &Vec<OpCode> {
+    &self.code
+  }
+
+  pub fn code_mut(&mut self) -> &mut Vec<OpCode> {
+    &mut self.code
+  }
+
+  /// Source location of each instruction, indexed like `code`.
+  pub fn locations(&self) -> &Vec<Location> {
+    &self.locations
+  }
+
+  pub fn locations_mut(&mut self) -> &mut Vec<Location> {
+    &mut self.locations
+  }
+
+  pub fn constants(&self) -> &Vec<Value> {
+    &self.constants
+  }
+
+  pub fn constants_mut(&mut self) -> &mut Vec<Value> {
+    &mut self.constants
+  }
+
+  pub fn functions(&self) -> &Vec<Rc<FunctionProto>> {
+    &self.functions
+  }
+
+  pub fn functions_mut(&mut self) -> &mut Vec<Rc<FunctionProto>> {
+    &mut self.functions
+  }
+
+  pub fn classes(&self) -> &Vec<Rc<ClassProto>> {
+    &self.classes
+  }
+
+  pub fn classes_mut(&mut self) -> &mut Vec<Rc<ClassProto>> {
+    &mut self.classes
+  }
+
+  /// Append an instruction, returning its offset.
+  pub fn push(&mut self, op: OpCode, loc: Location) -> usize {
+    self.code.push(op);
+    self.locations.push(loc);
+    self.code.len() - 1
+  }
+
+  /// Add `v` to the constant pool, reusing an identical entry if there is one.
+  pub fn add_constant(&mut self, v: Value) -> usize {
+    if let Some(idx) = self.constants.iter().position(|c| *c == v) {
+      return idx;
+    }
+    self.constants.push(v);
+    self.constants.len() - 1
+  }
+
+  pub fn add_function(&mut self, f: FunctionProto) -> usize {
+    self.functions.push(Rc::new(f));
+    self.functions.len() - 1
+  }
+
+  pub fn add_class(&mut self, c: ClassProto) -> usize {
+    self.classes.push(Rc::new(c));
+    self.classes.len() - 1
+  }
+
+  pub fn location(&self, offset: usize) -> Option<&Location> {
+    self.locations.get(offset)
+  }
+
+  /// Functions declared at the top of the chunk, before any other instruction.
+  pub fn hoisted_functions(&self) -> Vec<Rc<FunctionProto>> {
+    self
+      .code
+      .iter()
+      .map_while(|op| match op {
+        OpCode::DeclareFunction(idx) => self.functions.get(*idx).cloned(),
+        _ => None,
+      })
+      .collect()
+  }
+}
+
+/// A compiled function declaration.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunctionProto {
+  name: String,
+  params: Vec<String>,
+  chunk: Rc<Chunk>,
+}
+
+impl FunctionProto {
+  /// The name of functions created by unnamed function expressions.
+  pub const ANONYMOUS: &'static str = "anonymous";
+  /// The parameter collecting every argument of a call into an array. Scripts can't
+  /// name it, only implicit constructors have it.
+  pub const ARGUMENTS: &'static str = "...args";
+
+  pub fn new(name: String, params: Vec<String>, chunk: Chunk) -> FunctionProto {
+    FunctionProto {
+      name,
+      params,
+      chunk: Rc::new(chunk),
+    }
+  }
+
+  pub fn name(&self) -> &String {
+    &self.name
+  }
+
+  pub fn params(&self) -> &Vec<String> {
+    &self.params
+  }
+
+  pub fn chunk(&self) -> &Rc<Chunk> {
+    &self.chunk
+  }
+}
+
+/// A compiled class declaration.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClassProto {
+  name: String,
+  superclass: Option<String>,
+  /// Fields and methods declared by the class, with their visibility.
+  members: Vec<(String, Visibility)>,
+  methods: Vec<Rc<FunctionProto>>,
+}
+
+impl ClassProto {
+  /// Name of the method run by `new`, field initializers included.
+  pub const CONSTRUCTOR: &'static str = "constructor";
+
+  pub fn new(
+    name: String,
+    superclass: Option<String>,
+    methods: Vec<Rc<FunctionProto>>,
+  ) -> ClassProto {
+    ClassProto {
+      name,
+      superclass,
+      members: vec![],
+      methods,
+    }
+  }
+
+  pub fn name(&self) -> &String {
+    &self.name
+  }
+
+  /// Name of the class extended, popped from the stack when the class is created.
+  pub fn superclass(&self) -> Option<&String> {
+    self.superclass.as_ref()
+  }
+
+  pub fn members(&self) -> &Vec<(String, Visibility)> {
+    &self.members
+  }
+
+  pub fn members_mut(&mut self) -> &mut Vec<(String, Visibility)> {
+    &mut self.members
+  }
+
+  /// Visibility of a member declared by this class, not by its superclasses.
+  pub fn visibility<S: AsRef<str>>(&self, name: S) -> Option<Visibility> {
+    self
+      .members
+      .iter()
+      .find(|(member, _)| member == name.as_ref())
+      .map(|(_, v)| *v)
+  }
+
+  pub fn methods(&self) -> &Vec<Rc<FunctionProto>> {
+    &self.methods
+  }
+
+  pub fn methods_mut(&mut self) -> &mut Vec<Rc<FunctionProto>> {
+    &mut self.methods
+  }
+
+  pub fn method<S: AsRef<str>>(&self, name: S) -> Option<&Rc<FunctionProto>> {
+    self.methods.iter().find(|m| m.name() == name.as_ref())
+  }
+
+  pub fn constructor(&self) -> Option<&Rc<FunctionProto>> {
+    self.method(Self::CONSTRUCTOR)
+  }
+}
@@ -0,0 +1,989 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::error::Error;
+use crate::location::Location;
+use crate::parser::{Enum, Keyword, NodeKind, NodePtr, OpCode, Value, Visibility, AST};
+use crate::result::Result;
+
+use super::{Chunk, ClassProto, FunctionProto};
+
+/// Jumps of a loop being compiled, patched once the loop is complete.
+struct LoopContext {
+  scope_depth: usize,
+  try_depth: usize,
+  breaks: Vec<usize>,
+  continues: Vec<usize>,
+}
+
+/// The class whose methods are being compiled.
+struct ClassContext {
+  name: String,
+  superclass: Option<String>,
+  fields: Vec<NodePtr>,
+}
+
+/// Lowers an `AST` into a `Chunk` of instructions.
+pub struct Compiler {
+  chunk: Chunk,
+  scope_depth: usize,
+  loops: Vec<LoopContext>,
+  /// The `finally` block, if any, of each protected block being compiled.
+  tries: Vec<Option<NodePtr>>,
+  /// Classes declared once in the script, whose members are checked statically.
+  classes: Rc<HashMap<String, NodePtr>>,
+  class: Option<Rc<ClassContext>>,
+}
+
+impl Compiler {
+  fn new<S: AsRef<str>>(name: S) -> Compiler {
+    Compiler {
+      chunk: Chunk::new(name),
+      scope_depth: 0,
+      loops: vec![],
+      tries: vec![],
+      classes: Default::default(),
+      class: None,
+    }
+  }
+
+  /// A compiler for a function nested in the one being compiled.
+  fn nested<S: AsRef<str>>(&self, name: S, class: Option<Rc<ClassContext>>) -> Compiler {
+    Compiler {
+      classes: self.classes.clone(),
+      class,
+      ..Compiler::new(name)
+    }
+  }
+
+  /// Compile a whole script, the resulting chunk returning `none` once done.
+  pub fn compile<S: AsRef<str>>(ast: &AST, name: S) -> Result<Chunk> {
+    let mut c = Compiler::new(name);
+    let root = ast.root().clone();
+    let mut classes = HashMap::new();
+    let mut ambiguous = vec![];
+    Self::collect_classes(&root, &mut classes, &mut ambiguous);
+    for name in ambiguous {
+      classes.remove(&name);
+    }
+    c.classes = Rc::new(classes);
+    let children = root.borrow().children().clone();
+    c.compile_statements(&children)?;
+    c.emit_return_none(root.borrow().location().clone());
+    Ok(c.chunk)
+  }
+
+  /// Index class declarations by name, a name declared twice being ambiguous.
+  fn collect_classes(
+    node: &NodePtr,
+    classes: &mut HashMap<String, NodePtr>,
+    ambiguous: &mut Vec<String>,
+  ) {
+    for child in node.borrow().children() {
+      if *child.borrow().kind() == NodeKind::Class {
+        let name = child.borrow().name().clone().unwrap_or_default();
+        if classes.insert(name.clone(), child.clone()).is_some() {
+          ambiguous.push(name);
+        }
+      }
+      Self::collect_classes(child, classes, ambiguous);
+    }
+  }
+
+  /// Compile a class, its field initializers running at the start of its constructor.
+  fn compile_class(&self, node: &NodePtr) -> Result<ClassProto> {
+    let class = Rc::new(ClassContext {
+      name: node.borrow().name().clone().unwrap_or_default(),
+      superclass: node
+        .borrow()
+        .child_by_kind(NodeKind::Identifier)
+        .and_then(|s| s.borrow().name().clone()),
+      fields: node.borrow().children_by_kind(NodeKind::Field),
+    });
+    let mut proto = ClassProto::new(class.name.clone(), class.superclass.clone(), vec![]);
+    let mut has_constructor = false;
+    for member in node.borrow().children() {
+      let member = member.borrow();
+      if matches!(*member.kind(), NodeKind::Field | NodeKind::Method) {
+        let name = member.name().clone().unwrap_or_default();
+        proto.members_mut().push((name, member.visibility()));
+      }
+    }
+    for method in node.borrow().children_by_kind(NodeKind::Method) {
+      has_constructor |= *method.borrow().name() == Some(ClassProto::CONSTRUCTOR.into());
+      let f = self.compile_function(&method, Some(&class))?;
+      proto.methods_mut().push(Rc::new(f));
+    }
+    // subclasses always need one to run the superclass constructor
+    if !has_constructor && (!class.fields.is_empty() || class.superclass.is_some()) {
+      let loc = node.borrow().location().clone();
+      // like `constructor(...args) { super(...args); }`
+      let params = match class.superclass {
+        Some(_) => vec![FunctionProto::ARGUMENTS.into()],
+        None => vec![],
+      };
+      let mut c = self.nested(ClassProto::CONSTRUCTOR, Some(class));
+      c.compile_constructor_prelude(None, &loc)?;
+      c.emit_return_none(loc);
+      proto.methods_mut().push(Rc::new(FunctionProto::new(
+        ClassProto::CONSTRUCTOR.into(),
+        params,
+        c.chunk,
+      )));
+    }
+    Ok(proto)
+  }
+
+  /// Run the superclass constructor, then initialize the fields.
+  ///
+  /// An explicit `super(...)` call must open the constructor `body`, otherwise
+  /// subclasses call it without arguments. Implicit constructors, without a `body`,
+  /// forward all of theirs. Returns how many statements of `body` were compiled.
+  fn compile_constructor_prelude(
+    &mut self,
+    body: Option<&[NodePtr]>,
+    loc: &Location,
+  ) -> Result<usize> {
+    let subclass = self.superclass().is_some();
+    let explicit = body.and_then(|b| b.first()).filter(|stmt| {
+      let stmt = stmt.borrow();
+      *stmt.kind() == NodeKind::Super && stmt.name().is_none()
+    });
+    match explicit {
+      Some(call) => self.compile_super(call)?,
+      None if subclass && body.is_none() => {
+        self.emit(OpCode::ForwardSuper, loc);
+      }
+      None if subclass => {
+        self.emit(OpCode::LoadVariable(Keyword::This.to_string()), loc);
+        self.emit(OpCode::InvokeSuper(ClassProto::CONSTRUCTOR.into(), 0), loc);
+      }
+      None => {}
+    }
+    if explicit.is_some() || subclass {
+      self.emit(OpCode::Pop, loc);
+    }
+    if let Some(class) = self.class.clone() {
+      self.compile_fields(&class.fields)?;
+    }
+    Ok(explicit.map_or(0, |_| 1))
+  }
+
+  /// `super(args...)` calls the superclass constructor, `super.name(args...)` one of its methods.
+  fn compile_super(&mut self, node: &NodePtr) -> Result<()> {
+    let loc = node.borrow().location().clone();
+    let superclass = self.superclass().cloned().ok_or_else(|| {
+      Error::Syntax(
+        "'super' can only be used in methods of a subclass".into(),
+        loc.clone().into(),
+      )
+    })?;
+    let name = node
+      .borrow()
+      .name()
+      .clone()
+      .unwrap_or_else(|| ClassProto::CONSTRUCTOR.into());
+    self.check_member(&superclass, &name, &loc)?;
+    let args = node.borrow().children().clone();
+    self.emit(OpCode::LoadVariable(Keyword::This.to_string()), &loc);
+    for arg in args.iter() {
+      self.compile_expr(arg)?;
+    }
+    self.emit(OpCode::InvokeSuper(name, args.len()), &loc);
+    Ok(())
+  }
+
+  fn superclass(&self) -> Option<&String> {
+    self.class.as_ref().and_then(|c| c.superclass.as_ref())
+  }
+
+  /// The class of the value `node` evaluates to, when known at compile time.
+  fn static_class(&self, node: &NodePtr) -> Option<String> {
+    let node = node.borrow();
+    match *node.kind() {
+      NodeKind::This => self.class.as_ref().map(|c| c.name.clone()),
+      NodeKind::New => node.children().first()?.borrow().name().clone(),
+      _ => None,
+    }
+  }
+
+  /// The class declaring `member` as seen from `class`, along with its visibility.
+  fn static_member(&self, class: &str, member: &str) -> Option<(String, Visibility)> {
+    let mut name = class.to_string();
+    // bounded, as nothing prevents classes from extending each other here
+    for _ in 0..=self.classes.len() {
+      let node = self.classes.get(&name)?.borrow();
+      let declared = node.children().iter().find_map(|m| {
+        let m = m.borrow();
+        let is_member = matches!(*m.kind(), NodeKind::Field | NodeKind::Method);
+        (is_member && m.name().as_deref() == Some(member)).then(|| m.visibility())
+      });
+      if let Some(visibility) = declared {
+        return Some((name, visibility));
+      }
+      name = node
+        .child_by_kind(NodeKind::Identifier)?
+        .borrow()
+        .name()
+        .clone()?;
+    }
+    None
+  }
+
+  /// Whether `class` is `base` or derives from it, if known at compile time.
+  fn static_derives(&self, class: &str, base: &str) -> Option<bool> {
+    let mut name = class.to_string();
+    for _ in 0..=self.classes.len() {
+      if name == base {
+        return Some(true);
+      }
+      let superclass = self
+        .classes
+        .get(&name)?
+        .borrow()
+        .child_by_kind(NodeKind::Identifier);
+      match superclass {
+        Some(s) => name = s.borrow().name().clone()?,
+        None => return Some(false),
+      }
+    }
+    None
+  }
+
+  /// Reject accesses to `member` of `class` that are known to be denied at run time.
+  fn check_member(&self, class: &str, member: &str, loc: &Location) -> Result<()> {
+    let (owner, visibility) = match self.static_member(class, member) {
+      Some(m) => m,
+      None => return Ok(()),
+    };
+    let accessor = self.class.as_ref().map(|c| c.name.as_str());
+    let allowed = match visibility {
+      Visibility::Public | Visibility::Package => Some(true),
+      Visibility::Private => Some(accessor == Some(owner.as_str())),
+      Visibility::Protected => accessor.map_or(Some(false), |a| self.static_derives(a, &owner)),
+    };
+    if allowed == Some(false) {
+      return Err(Error::Syntax(
+        format!("'{}' has {} access in {}", member, visibility, owner),
+        loc.clone().into(),
+      ));
+    }
+    Ok(())
+  }
+
+  /// Check an access to `member` of the object `node` evaluates to.
+  fn check_access(&self, node: &NodePtr, member: &str, loc: &Location) -> Result<()> {
+    match self.static_class(node) {
+      Some(class) => self.check_member(&class, member, loc),
+      None => Ok(()),
+    }
+  }
+
+  /// Set each field of `this` to its initial value.
+  fn compile_fields(&mut self, fields: &[NodePtr]) -> Result<()> {
+    for field in fields {
+      let loc = field.borrow().location().clone();
+      let name = field.borrow().name().clone().unwrap_or_default();
+      self.emit(OpCode::LoadVariable(Keyword::This.to_string()), &loc);
+      match field.borrow().children().first() {
+        Some(init) => self.compile_expr(init)?,
+        None => {
+          self.emit_constant(Value::None, &loc);
+        }
+      }
+      self.emit(OpCode::SetProperty(name), &loc);
+      self.emit(OpCode::Pop, &loc);
+    }
+    Ok(())
+  }
+
+  /// Compile a function, or a method of `class`.
+  fn compile_function(
+    &self,
+    node: &NodePtr,
+    class: Option<&Rc<ClassContext>>,
+  ) -> Result<FunctionProto> {
+    let name = node
+      .borrow()
+      .name()
+      .clone()
+      .unwrap_or_else(|| FunctionProto::ANONYMOUS.into());
+    let params: Vec<String> = node
+      .borrow()
+      .child_by_kind(NodeKind::FunctionParams)
+      .map(|p| {
+        p.borrow()
+          .children()
+          .iter()
+          .filter_map(|param| param.borrow().name().clone())
+          .collect()
+      })
+      .unwrap_or_default();
+    let mut c = self.nested(&name, class.cloned());
+    let loc = node.borrow().location().clone();
+    // the body shares the scope holding the parameters
+    let body = node
+      .borrow()
+      .child_by_kind(NodeKind::FunctionImpl)
+      .map(|body| body.borrow().children().clone())
+      .unwrap_or_default();
+    let mut skip = 0;
+    if *node.borrow().kind() == NodeKind::Method && name == ClassProto::CONSTRUCTOR {
+      skip = c.compile_constructor_prelude(Some(&body), &loc)?;
+    }
+    c.compile_statements(&body[skip..])?;
+    c.emit_return_none(loc);
+    Ok(FunctionProto::new(name, params, c.chunk))
+  }
+
+  fn emit(&mut self, op: OpCode, loc: &Location) -> usize {
+    self.chunk.push(op, loc.clone())
+  }
+
+  fn emit_constant(&mut self, v: Value, loc: &Location) -> usize {
+    let idx = self.chunk.add_constant(v);
+    self.emit(OpCode::Constant(idx), loc)
+  }
+
+  fn emit_return_none(&mut self, loc: Location) {
+    self.emit_constant(Value::None, &loc);
+    self.emit(OpCode::ReturnValue, &loc);
+  }
+
+  /// Point the jump at `offset` to the next instruction.
+  fn patch_jump(&mut self, offset: usize) {
+    let target = self.chunk.code().len();
+    self.patch_jump_to(offset, target);
+  }
+
+  fn patch_jump_to(&mut self, offset: usize, target: usize) {
+    match self.chunk.code_mut().get_mut(offset) {
+      Some(OpCode::Jump(t))
+      | Some(OpCode::JumpIfFalse(t))
+      | Some(OpCode::JumpIfTrue(t))
+      | Some(OpCode::EnterTry(t)) => *t = target,
+      _ => panic!("instruction at {} is not a jump", offset),
+    }
+  }
+
+  /// Compile the statements of a block, declaring its functions and variables up front.
+  fn compile_statements(&mut self, children: &[NodePtr]) -> Result<()> {
+    for child in children.iter() {
+      if *child.borrow().kind() == NodeKind::Function {
+        let loc = child.borrow().location().clone();
+        let idx = self.chunk.add_function(self.compile_function(child, None)?);
+        self.emit(OpCode::DeclareFunction(idx), &loc);
+      }
+    }
+    for child in children.iter() {
+      self.compile_hoisting(child);
+    }
+    // imports are bound before anything else runs
+    for child in children.iter() {
+      if *child.borrow().kind() == NodeKind::Import {
+        self.compile_import(child);
+      }
+    }
+    for child in children {
+      self.compile_statement(child)?;
+    }
+    Ok(())
+  }
+
+  fn compile_hoisting(&mut self, node: &NodePtr) {
+    let decl = node.borrow();
+    let name = decl.name().clone().unwrap_or_default();
+    match decl.kind() {
+      NodeKind::Variable => {
+        self.emit(OpCode::DeclareVariable(name.clone()), decl.location());
+      }
+      NodeKind::Constant | NodeKind::Class | NodeKind::Enum => {
+        self.emit(OpCode::DeclareConstant(name.clone()), decl.location());
+      }
+      NodeKind::Import => {
+        for binding in decl.children() {
+          let binding = binding.borrow();
+          let name = binding.name().clone().unwrap_or_default();
+          self.emit(OpCode::DeclareConstant(name), binding.location());
+        }
+      }
+      _ => {}
+    }
+    // only top-level declarations can be exported
+    if decl.exported() {
+      self.emit(OpCode::Export(name), decl.location());
+    }
+  }
+
+  /// Run the module imported by `node` and bind the names it imports.
+  fn compile_import(&mut self, node: &NodePtr) {
+    let node = node.borrow();
+    let loc = node.location().clone();
+    let path = self
+      .chunk
+      .add_constant(node.value().clone().unwrap_or(Value::None));
+    self.emit(OpCode::Import(path), &loc);
+    for binding in node.children() {
+      let binding = binding.borrow();
+      let name = binding.name().clone().unwrap_or_default();
+      self.emit(OpCode::Dup, binding.location());
+      // unvalued bindings get the whole namespace
+      if let Some(Value::String(imported)) = binding.value() {
+        self.emit(OpCode::ImportName(imported.clone()), binding.location());
+      }
+      self.emit(OpCode::InitVariable(name), binding.location());
+    }
+    self.emit(OpCode::Pop, &loc);
+  }
+
+  fn compile_scoped(&mut self, node: &NodePtr, loc: &Location) -> Result<()> {
+    self.emit(OpCode::PushScope, loc);
+    self.scope_depth += 1;
+    let children = node.borrow().children().clone();
+    self.compile_statements(&children)?;
+    self.scope_depth -= 1;
+    self.emit(OpCode::PopScope, loc);
+    Ok(())
+  }
+
+  fn compile_statement(&mut self, node: &NodePtr) -> Result<()> {
+    let kind = *node.borrow().kind();
+    let loc = node.borrow().location().clone();
+    let children = node.borrow().children().clone();
+    match kind {
+      // declared when entering the enclosing block
+      NodeKind::Function | NodeKind::Import => {}
+      NodeKind::Class => {
+        if let Some(superclass) = node.borrow().child_by_kind(NodeKind::Identifier) {
+          self.compile_expr(&superclass)?;
+        }
+        let idx = self.chunk.add_class(self.compile_class(node)?);
+        self.emit(OpCode::Class(idx), &loc);
+        let name = node.borrow().name().clone().unwrap_or_default();
+        self.emit(OpCode::InitVariable(name), &loc);
+      }
+      NodeKind::Enum => {
+        let name = node.borrow().name().clone().unwrap_or_default();
+        let variants = children
+          .iter()
+          .filter_map(|v| {
+            let v = v.borrow();
+            match v.value() {
+              Some(Value::Integer(i)) => Some((v.name().clone()?, *i)),
+              _ => None,
+            }
+          })
+          .collect();
+        self.emit_constant(Value::Enum(Rc::new(Enum::new(name.clone(), variants))), &loc);
+        self.emit(OpCode::InitVariable(name), &loc);
+      }
+      NodeKind::None => {}
+      NodeKind::Error => {
+        let msg = node.borrow().name().clone().unwrap_or_default();
+        return Err(Error::Syntax(msg, loc.into()));
+      }
+      NodeKind::Block => self.compile_scoped(node, &loc)?,
+      NodeKind::Variable | NodeKind::Constant => {
+        match children.first() {
+          Some(init) => self.compile_expr(init)?,
+          None => {
+            self.emit_constant(Value::None, &loc);
+          }
+        }
+        let name = node.borrow().name().clone().unwrap_or_default();
+        self.emit(OpCode::InitVariable(name), &loc);
+      }
+      NodeKind::Return => {
+        match children.first() {
+          Some(expr) => self.compile_expr(expr)?,
+          None => {
+            self.emit_constant(Value::None, &loc);
+          }
+        }
+        self.exit_tries(0, &loc)?;
+        self.emit(OpCode::ReturnValue, &loc);
+      }
+      NodeKind::Throw => {
+        self.compile_expr(&children[0])?;
+        self.emit(OpCode::Throw, &loc);
+      }
+      NodeKind::Try => self.compile_try(node, &loc)?,
+      NodeKind::If => {
+        self.compile_expr(&children[0])?;
+        let to_else = self.emit(OpCode::JumpIfFalse(0), &loc);
+        self.compile_statement(&children[1])?;
+        match children.get(2) {
+          Some(otherwise) => {
+            let to_end = self.emit(OpCode::Jump(0), &loc);
+            self.patch_jump(to_else);
+            self.compile_statement(otherwise)?;
+            self.patch_jump(to_end);
+          }
+          None => self.patch_jump(to_else),
+        }
+      }
+      NodeKind::While => {
+        let start = self.chunk.code().len();
+        self.compile_expr(&children[0])?;
+        let to_end = self.emit(OpCode::JumpIfFalse(0), &loc);
+        self.compile_loop_body(&children[1], Some(start))?;
+        self.emit(OpCode::Jump(start), &loc);
+        self.patch_jump(to_end);
+        self.end_loop(None);
+      }
+      NodeKind::For => {
+        let (init, cond, update, body) = (&children[0], &children[1], &children[2], &children[3]);
+        self.emit(OpCode::PushScope, &loc);
+        self.scope_depth += 1;
+        self.compile_hoisting(init);
+        self.compile_statement(init)?;
+        // each iteration gets its own copy of a `let` loop variable, for closures to capture
+        let var = match *init.borrow().kind() {
+          NodeKind::Variable => init.borrow().name().clone(),
+          _ => None,
+        };
+        let start = self.chunk.code().len();
+        if let Some(var) = &var {
+          self.emit(OpCode::LoadVariable(var.clone()), &loc);
+          self.emit(OpCode::PushScope, &loc);
+          self.scope_depth += 1;
+          self.emit(OpCode::DeclareVariable(var.clone()), &loc);
+          self.emit(OpCode::InitVariable(var.clone()), &loc);
+        }
+        let to_end = match *cond.borrow().kind() {
+          NodeKind::None => None,
+          _ => {
+            self.compile_expr(cond)?;
+            Some(self.emit(OpCode::JumpIfFalse(0), &loc))
+          }
+        };
+        self.compile_loop_body(body, None)?;
+        let continue_target = self.chunk.code().len();
+        // the update runs on the loop's variable, from where this iteration left it
+        if let Some(var) = &var {
+          self.emit(OpCode::LoadVariable(var.clone()), &loc);
+          self.emit(OpCode::PopScope, &loc);
+          self.emit(OpCode::AssignVariable(var.clone()), &loc);
+          self.emit(OpCode::Pop, &loc);
+        }
+        if *update.borrow().kind() != NodeKind::None {
+          self.compile_expr(update)?;
+          self.emit(OpCode::Pop, &loc);
+        }
+        self.emit(OpCode::Jump(start), &loc);
+        if let Some(to_end) = to_end {
+          self.patch_jump(to_end);
+        }
+        self.end_loop(Some(continue_target));
+        if var.is_some() {
+          self.scope_depth -= 1;
+          self.emit(OpCode::PopScope, &loc);
+        }
+        self.scope_depth -= 1;
+        self.emit(OpCode::PopScope, &loc);
+      }
+      NodeKind::Break | NodeKind::Continue => {
+        let (depth, try_depth) = match self.loops.last() {
+          Some(ctx) => (ctx.scope_depth, ctx.try_depth),
+          None => return Err(Error::Syntax(format!("{:?} outside of loop", kind), loc.into())),
+        };
+        self.exit_tries(try_depth, &loc)?;
+        // leave the scopes opened inside the loop body
+        for _ in depth..self.scope_depth {
+          self.emit(OpCode::PopScope, &loc);
+        }
+        let jump = self.emit(OpCode::Jump(0), &loc);
+        let ctx = self.loops.last_mut().unwrap();
+        if kind == NodeKind::Break {
+          ctx.breaks.push(jump);
+        } else {
+          ctx.continues.push(jump);
+        }
+      }
+      _ => {
+        self.compile_expr(node)?;
+        self.emit(OpCode::Pop, &loc);
+      }
+    }
+    Ok(())
+  }
+
+  /// Protect the `try` block with a handler resuming at the `catch` block, or at
+  /// the `finally` block which then throws the value again.
+  ///
+  /// `finally` blocks are copied at the end of the `try` and `catch` blocks, and
+  /// before any `return`, `break` or `continue` leaving them.
+  fn compile_try(&mut self, node: &NodePtr, loc: &Location) -> Result<()> {
+    let body = node.borrow().children()[0].clone();
+    let catch = node.borrow().child_by_kind(NodeKind::Catch);
+    let finally = node
+      .borrow()
+      .child_by_kind(NodeKind::Finally)
+      .map(|f| f.borrow().children()[0].clone());
+    let mut to_end = vec![];
+
+    let handler = self.emit(OpCode::EnterTry(0), loc);
+    self.compile_protected(&body, &finally, loc)?;
+    to_end.push(self.emit(OpCode::Jump(0), loc));
+
+    // the thrown value is on top of the stack
+    let to_rethrow = match catch {
+      Some(catch) => {
+        self.patch_jump(handler);
+        let rethrow = finally
+          .as_ref()
+          .map(|_| self.emit(OpCode::EnterTry(0), loc));
+        self.emit(OpCode::PushScope, loc);
+        self.scope_depth += 1;
+        match catch.borrow().name().clone() {
+          Some(name) => {
+            self.emit(OpCode::DeclareVariable(name.clone()), loc);
+            self.emit(OpCode::InitVariable(name), loc);
+          }
+          None => {
+            self.emit(OpCode::Pop, loc);
+          }
+        }
+        let block = catch.borrow().children()[0].clone();
+        let statements = block.borrow().children().clone();
+        if rethrow.is_some() {
+          self.tries.push(finally.clone());
+        }
+        self.compile_statements(&statements)?;
+        self.scope_depth -= 1;
+        self.emit(OpCode::PopScope, loc);
+        if rethrow.is_some() {
+          self.tries.pop();
+          self.emit(OpCode::LeaveTry, loc);
+        }
+        if let Some(finally) = &finally {
+          self.compile_statement(finally)?;
+        }
+        to_end.push(self.emit(OpCode::Jump(0), loc));
+        rethrow
+      }
+      None => Some(handler),
+    };
+    if let (Some(rethrow), Some(finally)) = (to_rethrow, &finally) {
+      self.patch_jump(rethrow);
+      self.compile_statement(finally)?;
+      self.emit(OpCode::Throw, loc);
+    }
+    for jump in to_end {
+      self.patch_jump(jump);
+    }
+    Ok(())
+  }
+
+  /// Compile `body` under the handler just entered, leaving it and running `finally` once done.
+  fn compile_protected(
+    &mut self,
+    body: &NodePtr,
+    finally: &Option<NodePtr>,
+    loc: &Location,
+  ) -> Result<()> {
+    self.tries.push(finally.clone());
+    self.compile_statement(body)?;
+    self.tries.pop();
+    self.emit(OpCode::LeaveTry, loc);
+    if let Some(finally) = finally {
+      self.compile_statement(finally)?;
+    }
+    Ok(())
+  }
+
+  /// Leave the protected blocks entered past `depth`, running their `finally` blocks.
+  fn exit_tries(&mut self, depth: usize, loc: &Location) -> Result<()> {
+    for idx in (depth..self.tries.len()).rev() {
+      self.emit(OpCode::LeaveTry, loc);
+      if let Some(finally) = self.tries[idx].clone() {
+        // the `finally` block runs outside of the blocks it protects
+        let inner = self.tries.split_off(idx);
+        self.compile_statement(&finally)?;
+        self.tries.extend(inner);
+      }
+    }
+    Ok(())
+  }
+
+  /// Compile a loop body, `continue` jumping to `start` if known or being patched by `end_loop`.
+  fn compile_loop_body(&mut self, body: &NodePtr, start: Option<usize>) -> Result<()> {
+    self.loops.push(LoopContext {
+      scope_depth: self.scope_depth,
+      try_depth: self.tries.len(),
+      breaks: vec![],
+      continues: vec![],
+    });
+    self.compile_statement(body)?;
+    if let Some(start) = start {
+      let continues = std::mem::take(&mut self.loops.last_mut().unwrap().continues);
+      for jump in continues {
+        self.patch_jump_to(jump, start);
+      }
+    }
+    Ok(())
+  }
+
+  /// Patch the pending jumps of the innermost loop, which ends at the next instruction.
+  fn end_loop(&mut self, continue_target: Option<usize>) {
+    let ctx = self.loops.pop().unwrap();
+    for jump in ctx.breaks {
+      self.patch_jump(jump);
+    }
+    if let Some(target) = continue_target {
+      for jump in ctx.continues {
+        self.patch_jump_to(jump, target);
+      }
+    }
+  }
+
+  fn compile_expr(&mut self, node: &NodePtr) -> Result<()> {
+    let kind = *node.borrow().kind();
+    let loc = node.borrow().location().clone();
+    let children = node.borrow().children().clone();
+    let name = node.borrow().name().clone().unwrap_or_default();
+    match kind {
+      NodeKind::Litteral => {
+        let v = node.borrow().value().clone().unwrap_or(Value::None);
+        self.emit_constant(v, &loc);
+      }
+      NodeKind::Identifier => {
+        self.emit(OpCode::LoadVariable(name), &loc);
+      }
+      NodeKind::This => {
+        self.emit(OpCode::LoadVariable(Keyword::This.to_string()), &loc);
+      }
+      NodeKind::Super if node.borrow().name().is_none() => {
+        return Err(Error::Syntax(
+          "'super(...)' must be the first statement of a constructor".into(),
+          loc.into(),
+        ))
+      }
+      NodeKind::Super => self.compile_super(node)?,
+      NodeKind::Assignment => {
+        let target = children[0].borrow();
+        let target_name = target.name().clone().unwrap_or_default();
+        match *target.kind() {
+          NodeKind::Property => {
+            self.check_access(&target.children()[0], &target_name, &loc)?;
+            self.compile_expr(&target.children()[0])?;
+            self.compile_expr(&children[1])?;
+            self.emit(OpCode::SetProperty(target_name), &loc);
+          }
+          NodeKind::Index => {
+            self.compile_expr(&target.children()[0])?;
+            self.compile_expr(&target.children()[1])?;
+            self.compile_expr(&children[1])?;
+            self.emit(OpCode::SetIndex, &loc);
+          }
+          _ => {
+            self.compile_expr(&children[1])?;
+            self.emit(OpCode::AssignVariable(target_name), target.location());
+          }
+        }
+      }
+      NodeKind::CompoundAssignment => {
+        let target = children[0].borrow();
+        let target_name = target.name().clone().unwrap_or_default();
+        let binary = children[1].borrow();
+        let op = OpCode::binary(*binary.kind()).ok_or_else(|| {
+          Error::Syntax("invalid compound assignment".into(), loc.clone().into())
+        })?;
+        // the target's object and index are evaluated once, then read and written
+        match *target.kind() {
+          NodeKind::Property => {
+            self.check_access(&target.children()[0], &target_name, &loc)?;
+            self.compile_expr(&target.children()[0])?;
+            self.emit(OpCode::Dup, &loc);
+            self.emit(OpCode::GetProperty(target_name.clone()), &loc);
+            self.compile_expr(&binary.children()[0])?;
+            self.emit(op, &loc);
+            self.emit(OpCode::SetProperty(target_name), &loc);
+          }
+          NodeKind::Index => {
+            self.compile_expr(&target.children()[0])?;
+            self.compile_expr(&target.children()[1])?;
+            self.emit(OpCode::Dup2, &loc);
+            self.emit(OpCode::GetIndex, &loc);
+            self.compile_expr(&binary.children()[0])?;
+            self.emit(op, &loc);
+            self.emit(OpCode::SetIndex, &loc);
+          }
+          _ => {
+            self.emit(OpCode::LoadVariable(target_name.clone()), target.location());
+            self.compile_expr(&binary.children()[0])?;
+            self.emit(op, &loc);
+            self.emit(OpCode::AssignVariable(target_name), target.location());
+          }
+        }
+      }
+      NodeKind::Index => {
+        self.compile_expr(&children[0])?;
+        self.compile_expr(&children[1])?;
+        self.emit(OpCode::GetIndex, &loc);
+      }
+      NodeKind::ArrayLitteral => {
+        for element in children.iter() {
+          self.compile_expr(element)?;
+        }
+        self.emit(OpCode::Array(children.len()), &loc);
+      }
+      NodeKind::ObjectLitteral => {
+        for entry in children.iter() {
+          let entry = entry.borrow();
+          let key = entry.name().clone().unwrap_or_default();
+          self.emit_constant(Value::String(key), entry.location());
+          self.compile_expr(&entry.children()[0])?;
+        }
+        self.emit(OpCode::Object(children.len()), &loc);
+      }
+      NodeKind::Property => {
+        self.check_access(&children[0], &name, &loc)?;
+        self.compile_expr(&children[0])?;
+        self.emit(OpCode::GetProperty(name), &loc);
+      }
+      NodeKind::Invoke | NodeKind::New => {
+        match kind {
+          NodeKind::Invoke => self.check_access(&children[0], &name, &loc)?,
+          _ => self.check_access(node, ClassProto::CONSTRUCTOR, &loc)?,
+        }
+        for child in children.iter() {
+          self.compile_expr(child)?;
+        }
+        let argc = children.len() - 1;
+        let op = match kind {
+          NodeKind::Invoke => OpCode::Invoke(name, argc),
+          _ => OpCode::New(argc),
+        };
+        self.emit(op, &loc);
+      }
+      NodeKind::Call if node.borrow().name().is_none() => {
+        for child in children.iter() {
+          self.compile_expr(child)?;
+        }
+        self.emit(OpCode::Call(children.len() - 1), &loc);
+      }
+      NodeKind::Call => {
+        for arg in children.iter() {
+          self.compile_expr(arg)?;
+        }
+        self.emit(OpCode::CallFunction(name, children.len()), &loc);
+      }
+      NodeKind::Lambda => {
+        // methods share their class with the functions they create
+        let f = self.compile_function(node, self.class.as_ref())?;
+        let idx = self.chunk.add_function(f);
+        self.emit(OpCode::Closure(idx), &loc);
+      }
+      NodeKind::Negate | NodeKind::Not => {
+        self.compile_expr(&children[0])?;
+        let op = match kind {
+          NodeKind::Negate => OpCode::Negate,
+          _ => OpCode::Not,
+        };
+        self.emit(op, &loc);
+      }
+      NodeKind::And | NodeKind::Or => {
+        // keep the left operand as result when it decides the outcome
+        self.compile_expr(&children[0])?;
+        self.emit(OpCode::Dup, &loc);
+        let jump = match kind {
+          NodeKind::And => OpCode::JumpIfFalse(0),
+          _ => OpCode::JumpIfTrue(0),
+        };
+        let to_end = self.emit(jump, &loc);
+        self.emit(OpCode::Pop, &loc);
+        self.compile_expr(&children[1])?;
+        self.patch_jump(to_end);
+      }
+      k => match OpCode::binary(k) {
+        Some(op) => {
+          self.compile_expr(&children[0])?;
+          self.compile_expr(&children[1])?;
+          self.emit(op, &loc);
+        }
+        None => return Err(Error::Syntax(format!("{:?} is not an expression", k), loc.into())),
+      },
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use super::*;
+  use crate::parser::Parser;
+  use crate::script::Script;
+
+  fn compile(src: &str) -> Chunk {
+    let mut script = Script::new(PathBuf::from("virtual://test"), Some("test"), Some(src));
+    let ast = Parser::default().parse(&mut script).unwrap();
+    Compiler::compile(&ast, "test").unwrap()
+  }
+
+  #[test]
+  fn expressions_are_lowered() {
+    let chunk = compile("let x = 1 + 2 * x;");
+    assert_eq!(
+      *chunk.code(),
+      vec![
+        OpCode::DeclareVariable("x".into()),
+        OpCode::Constant(0),
+        OpCode::Constant(1),
+        OpCode::LoadVariable("x".into()),
+        OpCode::Multiply,
+        OpCode::Add,
+        OpCode::InitVariable("x".into()),
+        OpCode::Constant(2),
+        OpCode::ReturnValue,
+      ]
+    );
+    assert_eq!(
+      *chunk.constants(),
+      vec![Value::Integer(1), Value::Integer(2), Value::None]
+    );
+    assert_eq!(chunk.locations().len(), chunk.code().len());
+  }
+
+  #[test]
+  fn loops_jump_to_their_bounds() {
+    let chunk = compile("while (a) { if (b) break; continue; }");
+    assert_eq!(
+      *chunk.code(),
+      vec![
+        OpCode::LoadVariable("a".into()),
+        OpCode::JumpIfFalse(11),
+        OpCode::PushScope,
+        OpCode::LoadVariable("b".into()),
+        OpCode::JumpIfFalse(7),
+        OpCode::PopScope,
+        OpCode::Jump(11),
+        OpCode::PopScope,
+        OpCode::Jump(0),
+        OpCode::PopScope,
+        OpCode::Jump(0),
+        OpCode::Constant(0),
+        OpCode::ReturnValue,
+      ]
+    );
+  }
+
+  #[test]
+  fn functions_are_hoisted() {
+    let chunk = compile("f();\nfunction f(a) { return a; }");
+    assert_eq!(chunk.hoisted_functions().len(), 1);
+    let f = chunk.functions()[0].clone();
+    assert_eq!(*f.name(), "f");
+    assert_eq!(*f.params(), vec!["a".to_string()]);
+    assert_eq!(
+      *f.chunk().code(),
+      vec![
+        OpCode::LoadVariable("a".into()),
+        OpCode::ReturnValue,
+        OpCode::Constant(0),
+        OpCode::ReturnValue,
+      ]
+    );
+  }
+}
@@ -0,0 +1,145 @@
+use std::fmt::Write;
+
+use crate::location::Location;
+use crate::parser::{OpCode, Value};
+
+use super::{Chunk, FunctionProto};
+
+/// Renders chunks as a textual listing, the format read back by `Assembler`.
+///
+/// ```text
+/// chunk "main" {
+///   constants:
+///     #0 = 42
+///   code:
+///     0000  constant 0                @ main:1:9+8  ; 42
+///     0001  return_value              @ main:1:1+0
+///   function #0 f(a, b) {
+///     code:
+///       ...
+///   }
+///   class #0 B extends A {
+///     private x
+///     function #0 constructor() {
+///       ...
+///     }
+///   }
+/// }
+/// ```
+pub struct Disassembler {
+  out: String,
+  indent: usize,
+}
+
+impl Disassembler {
+  pub fn disassemble(chunk: &Chunk) -> String {
+    let mut d = Disassembler {
+      out: String::new(),
+      indent: 0,
+    };
+    d.line(format!("chunk {:?} {{", chunk.name()));
+    d.chunk(chunk);
+    d.line("}");
+    d.out
+  }
+
+  /// Render a constant the way the assembler expects to read it.
+  pub fn litteral(v: &Value) -> String {
+    match v {
+      Value::String(s) => format!("{:?}", s),
+      Value::Double(d) => format!("{:?}", d),
+      Value::None => "none".to_string(),
+      Value::Enum(e) => {
+        let variants: Vec<String> = e
+          .variants()
+          .iter()
+          .map(|(name, v)| format!("{} = {}", name, v))
+          .collect();
+        format!("enum {} {{ {} }}", e.name(), variants.join(", "))
+      }
+      _ => v.to_string(),
+    }
+  }
+
+  fn chunk(&mut self, chunk: &Chunk) {
+    self.indent += 1;
+    if !chunk.constants().is_empty() {
+      self.line("constants:");
+      for (idx, c) in chunk.constants().iter().enumerate() {
+        self.line(format!("  #{} = {}", idx, Self::litteral(c)));
+      }
+    }
+    self.line("code:");
+    for (offset, op) in chunk.code().iter().enumerate() {
+      let mut text = format!("  {:04}  {:<24}", offset, op.to_string());
+      if let Some(loc) = chunk.location(offset) {
+        write!(text, "  @ {}", Self::location(loc)).unwrap();
+      }
+      if let Some(comment) = Self::comment(chunk, op) {
+        write!(text, "  ; {}", comment).unwrap();
+      }
+      self.line(text.trim_end());
+    }
+    for (idx, f) in chunk.functions().iter().enumerate() {
+      self.function(idx, f);
+    }
+    for (idx, c) in chunk.classes().iter().enumerate() {
+      match c.superclass() {
+        Some(s) => self.line(format!("class #{} {} extends {} {{", idx, c.name(), s)),
+        None => self.line(format!("class #{} {} {{", idx, c.name())),
+      }
+      self.indent += 1;
+      for (name, visibility) in c.members() {
+        self.line(format!("{} {}", visibility, name));
+      }
+      for (idx, m) in c.methods().iter().enumerate() {
+        self.function(idx, m);
+      }
+      self.indent -= 1;
+      self.line("}");
+    }
+    self.indent -= 1;
+  }
+
+  fn function(&mut self, idx: usize, f: &FunctionProto) {
+    self.line(format!(
+      "function #{} {}({}) {{",
+      idx,
+      f.name(),
+      f.params().join(", ")
+    ));
+    self.chunk(f.chunk());
+    self.line("}");
+  }
+
+  fn location(loc: &Location) -> String {
+    format!(
+      "{}:{}:{}+{}",
+      loc.file(),
+      loc.line(),
+      loc.column(),
+      loc.offset()
+    )
+  }
+
+  fn comment(chunk: &Chunk, op: &OpCode) -> Option<String> {
+    match op {
+      OpCode::Constant(idx) | OpCode::Import(idx) => chunk.constants().get(*idx).map(Self::litteral),
+      OpCode::DeclareFunction(idx) | OpCode::Closure(idx) => chunk.functions().get(*idx).map(|f| f.name().clone()),
+      OpCode::Class(idx) => chunk.classes().get(*idx).map(|c| c.name().clone()),
+      _ => None,
+    }
+  }
+
+  fn line<S: AsRef<str>>(&mut self, s: S) {
+    self.out.push_str(&"  ".repeat(self.indent));
+    self.out.push_str(s.as_ref());
+    self.out.push('\n');
+  }
+}
+
+impl std::fmt::Display for Chunk {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", Disassembler::disassemble(self))
+  }
+}
@@ -0,0 +1,12 @@
+#[allow(clippy::module_inception)]
+pub mod compiler;
+pub mod chunk;
+pub mod disassembler;
+pub mod assembler;
+pub mod binary;
+
+pub use compiler::*;
+pub use chunk::*;
+pub use disassembler::*;
+pub use assembler::*;
+pub use binary::*;
\ No newline at end of file
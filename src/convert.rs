@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+
+use crate::error::Error;
+use crate::parser::Value;
+use crate::result::Result;
+
+/// Conversion of a script value into a Rust type.
+pub trait FromValue: Sized {
+  fn from_value(v: Value) -> Result<Self>;
+}
+
+/// Conversion of a Rust value into a script value.
+pub trait IntoValue {
+  fn into_value(self) -> Value;
+}
+
+/// The arguments of a call made from Rust, as a tuple of convertible values.
+pub trait IntoArgs {
+  fn into_args(self) -> Vec<Value>;
+}
+
+/// What a native function returns: a convertible value, or a result of one.
+pub trait IntoResult {
+  fn into_result(self) -> Result<Value>;
+}
+
+/// A Rust function callable from scripts, its arguments converted from script values.
+///
+/// It is implemented for closures of up to six arguments implementing `FromValue`,
+/// returning `IntoResult`. Trailing `Option` arguments may be left out.
+pub trait TypedFn<Args> {
+  /// Call the function registered as `name`, failing on a wrong number or types of
+  /// arguments.
+  fn call(&self, name: &str, args: Vec<Value>) -> Result<Value>;
+}
+
+/// The error for `v` not being of the type `expected`.
+pub fn mismatch(expected: &str, v: &Value) -> Error {
+  Error::Runtime(
+    format!("expected {}, got {}", expected, v.type_name()),
+    None,
+  )
+}
+
+impl FromValue for Value {
+  fn from_value(v: Value) -> Result<Self> {
+    Ok(v)
+  }
+}
+
+impl IntoValue for Value {
+  fn into_value(self) -> Value {
+    self
+  }
+}
+
+impl FromValue for () {
+  fn from_value(v: Value) -> Result<Self> {
+    match v {
+      Value::None => Ok(()),
+      v => Err(mismatch("none", &v)),
+    }
+  }
+}
+
+impl IntoValue for () {
+  fn into_value(self) -> Value {
+    Value::None
+  }
+}
+
+impl FromValue for bool {
+  fn from_value(v: Value) -> Result<Self> {
+    match v {
+      Value::Boolean(b) => Ok(b),
+      v => Err(mismatch("boolean", &v)),
+    }
+  }
+}
+
+impl IntoValue for bool {
+  fn into_value(self) -> Value {
+    Value::Boolean(self)
+  }
+}
+
+impl FromValue for i64 {
+  fn from_value(v: Value) -> Result<Self> {
+    match v {
+      Value::Integer(i) => Ok(i),
+      v => Err(mismatch("integer", &v)),
+    }
+  }
+}
+
+impl IntoValue for i64 {
+  fn into_value(self) -> Value {
+    Value::Integer(self)
+  }
+}
+
+macro_rules! integer {
+  ($($t:ty),*) => {
+    $(
+      impl FromValue for $t {
+        fn from_value(v: Value) -> Result<Self> {
+          let i = i64::from_value(v)?;
+          <$t>::try_from(i).map_err(|_| {
+            Error::Runtime(
+              format!("integer {} is out of range for {}", i, stringify!($t)),
+              None,
+            )
+          })
+        }
+      }
+
+      /// Integers too large for scripts become doubles, like overflowing arithmetic does.
+      impl IntoValue for $t {
+        fn into_value(self) -> Value {
+          i64::try_from(self).map_or(Value::Double(self as f64), Value::Integer)
+        }
+      }
+    )*
+  };
+}
+
+integer!(i8, i16, i32, isize, u8, u16, u32, u64, usize);
+
+/// Integers convert to doubles too.
+impl FromValue for f64 {
+  fn from_value(v: Value) -> Result<Self> {
+    v.as_double().ok_or_else(|| mismatch("double", &v))
+  }
+}
+
+impl IntoValue for f64 {
+  fn into_value(self) -> Value {
+    Value::Double(self)
+  }
+}
+
+impl FromValue for f32 {
+  fn from_value(v: Value) -> Result<Self> {
+    Ok(f64::from_value(v)? as f32)
+  }
+}
+
+impl IntoValue for f32 {
+  fn into_value(self) -> Value {
+    Value::Double(self.into())
+  }
+}
+
+impl FromValue for String {
+  fn from_value(v: Value) -> Result<Self> {
+    match v {
+      Value::String(s) => Ok(s),
+      v => Err(mismatch("string", &v)),
+    }
+  }
+}
+
+impl IntoValue for String {
+  fn into_value(self) -> Value {
+    Value::String(self)
+  }
+}
+
+impl IntoValue for &str {
+  fn into_value(self) -> Value {
+    Value::String(self.into())
+  }
+}
+
+/// Arrays convert element by element.
+impl<T: FromValue> FromValue for Vec<T> {
+  fn from_value(v: Value) -> Result<Self> {
+    match v {
+      Value::Array(a) => a.borrow().iter().cloned().map(T::from_value).collect(),
+      v => Err(mismatch("array", &v)),
+    }
+  }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+  fn into_value(self) -> Value {
+    Value::array(self.into_iter().map(IntoValue::into_value).collect())
+  }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+  fn from_value(v: Value) -> Result<Self> {
+    match v {
+      Value::Object(o) => o
+        .borrow()
+        .iter()
+        .map(|(k, v)| Ok((k.clone(), T::from_value(v.clone())?)))
+        .collect(),
+      v => Err(mismatch("object", &v)),
+    }
+  }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+  fn into_value(self) -> Value {
+    Value::object(self.into_iter().map(|(k, v)| (k, v.into_value())).collect())
+  }
+}
+
+/// `none` stands for `None`.
+impl<T: FromValue> FromValue for Option<T> {
+  fn from_value(v: Value) -> Result<Self> {
+    match v {
+      Value::None => Ok(None),
+      v => T::from_value(v).map(Some),
+    }
+  }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+  fn into_value(self) -> Value {
+    self.map_or(Value::None, IntoValue::into_value)
+  }
+}
+
+impl<T: IntoValue> IntoResult for T {
+  fn into_result(self) -> Result<Value> {
+    Ok(self.into_value())
+  }
+}
+
+impl<T: IntoValue> IntoResult for Result<T> {
+  fn into_result(self) -> Result<Value> {
+    self.map(IntoValue::into_value)
+  }
+}
+
+impl IntoArgs for Vec<Value> {
+  fn into_args(self) -> Vec<Value> {
+    self
+  }
+}
+
+/// Prefix the message of a conversion error with what was being converted.
+fn in_context(e: Error, context: String) -> Error {
+  match e {
+    Error::Runtime(msg, loc) => Error::Runtime(format!("{}: {}", context, msg), loc),
+    e => e,
+  }
+}
+
+macro_rules! count {
+  () => { 0 };
+  ($head:ident $($tail:ident)*) => { 1 + count!($($tail)*) };
+}
+
+/// Tuples convert to arrays of the same length.
+macro_rules! tuple {
+  ($($name:ident),+) => {
+    #[allow(non_snake_case)]
+    impl<$($name: FromValue),+> FromValue for ($($name,)+) {
+      fn from_value(v: Value) -> Result<Self> {
+        let len = count!($($name)+);
+        let elements = match v {
+          Value::Array(a) if a.borrow().len() == len => a.borrow().clone(),
+          v => return Err(mismatch(&format!("array of {} elements", len), &v)),
+        };
+        let mut elements = elements.into_iter();
+        Ok(($($name::from_value(elements.next().unwrap())?,)+))
+      }
+    }
+
+    impl<$($name: IntoValue),+> IntoValue for ($($name,)+) {
+      fn into_value(self) -> Value {
+        Value::array(self.into_args())
+      }
+    }
+  };
+}
+
+/// Tuples serve as arguments, and closures taking convertible arguments as native
+/// functions.
+macro_rules! signature {
+  ($($name:ident),*) => {
+    #[allow(non_snake_case)]
+    impl<$($name: IntoValue),*> IntoArgs for ($($name,)*) {
+      fn into_args(self) -> Vec<Value> {
+        let ($($name,)*) = self;
+        vec![$($name.into_value()),*]
+      }
+    }
+
+    #[allow(non_snake_case, unused_mut, unused_variables)]
+    impl<Func, Ret, $($name: FromValue),*> TypedFn<($($name,)*)> for Func
+    where
+      Func: Fn($($name),*) -> Ret,
+      Ret: IntoResult,
+    {
+      fn call(&self, name: &str, args: Vec<Value>) -> Result<Value> {
+        let (arity, given) = (count!($($name)*), args.len());
+        let arity_error = || {
+          Error::Runtime(
+            format!("{}: expected {} arguments, got {}", name, arity, given),
+            None,
+          )
+        };
+        if given > arity {
+          return Err(arity_error());
+        }
+        // missing arguments are `none`, which only optional ones accept
+        let mut args = args
+          .into_iter()
+          .chain(std::iter::repeat(Value::None))
+          .enumerate();
+        $(
+          let (idx, arg) = args.next().unwrap();
+          let $name = match $name::from_value(arg) {
+            Ok(v) => v,
+            Err(_) if idx >= given => return Err(arity_error()),
+            Err(e) => return Err(in_context(e, format!("{}: argument {}", name, idx + 1))),
+          };
+        )*
+        (self)($($name),*).into_result()
+      }
+    }
+  };
+}
+
+signature!();
+signature!(A);
+signature!(A, B);
+signature!(A, B, C);
+signature!(A, B, C, D);
+signature!(A, B, C, D, E);
+signature!(A, B, C, D, E, F);
+tuple!(A);
+tuple!(A, B);
+tuple!(A, B, C);
+tuple!(A, B, C, D);
+tuple!(A, B, C, D, E);
+tuple!(A, B, C, D, E, F);
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn values_convert_both_ways() {
+    assert_eq!(i64::from_value(42.into_value()).unwrap(), 42);
+    assert_eq!(i32::from_value(Value::Integer(-7)).unwrap(), -7);
+    assert!(i32::from_value(Value::Integer(1 << 40)).is_err());
+    assert_eq!(f64::from_value(Value::Integer(2)).unwrap(), 2.0);
+    assert_eq!(String::from_value("hi".into_value()).unwrap(), "hi");
+    assert!(bool::from_value(Value::Integer(1)).is_err());
+    assert_eq!(
+      format!("{}", String::from_value(Value::None).unwrap_err()),
+      "Runtime: expected string, got none"
+    );
+    assert_eq!(
+      (1, "a", true).into_args(),
+      vec![
+        Value::Integer(1),
+        Value::String("a".into()),
+        Value::Boolean(true)
+      ]
+    );
+    assert!(().into_args().is_empty());
+  }
+
+  #[test]
+  fn containers_convert_their_elements() {
+    let v = vec![1u8, 2, 3].into_value();
+    assert_eq!(Vec::<i64>::from_value(v.clone()).unwrap(), [1, 2, 3]);
+    assert!(Vec::<String>::from_value(v.clone()).is_err());
+    assert_eq!(
+      <(u8, f64, i32)>::from_value(v.clone()).unwrap(),
+      (1, 2.0, 3)
+    );
+    assert!(<(u8, u8)>::from_value(v).is_err());
+
+    let map = HashMap::from([("a".to_string(), Some(true)), ("b".to_string(), None)]);
+    let back = HashMap::<String, Option<bool>>::from_value(map.clone().into_value()).unwrap();
+    assert_eq!(back, map);
+    assert_eq!(Option::<i64>::from_value(Value::None).unwrap(), None);
+    assert_eq!(u64::MAX.into_value(), Value::Double(u64::MAX as f64));
+    assert_eq!((1, "a").into_value().to_string(), "[1, \"a\"]");
+  }
+}
@@ -0,0 +1,262 @@
+use std::fmt::{Display, Write};
+
+use crate::location::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+  Error,
+  Warning,
+  Note,
+}
+
+impl Display for Severity {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}",
+      match *self {
+        Self::Error => "error",
+        Self::Warning => "warning",
+        Self::Note => "note",
+      }
+    )
+  }
+}
+
+/// A span of source highlighted by a diagnostic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+  span: Span,
+  message: String,
+  primary: bool,
+}
+
+impl Label {
+  pub fn new<S: AsRef<str>>(span: Span, message: S, primary: bool) -> Label {
+    Label {
+      span,
+      message: String::from(message.as_ref()),
+      primary,
+    }
+  }
+
+  pub fn span(&self) -> &Span {
+    &self.span
+  }
+
+  pub fn message(&self) -> &String {
+    &self.message
+  }
+
+  pub fn is_primary(&self) -> bool {
+    self.primary
+  }
+}
+
+/// A message about a script, rendered along with the source it points at.
+///
+/// ```text
+/// error[E0002]: expected expression but found '*'
+///  --> test:2:2
+///   |
+/// 2 |  * 2;
+///   |  ^ expected expression
+///   |
+///   = note: ...
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+  severity: Severity,
+  code: Option<String>,
+  message: String,
+  labels: Vec<Label>,
+  notes: Vec<String>,
+}
+
+impl Diagnostic {
+  pub fn new<S: AsRef<str>>(severity: Severity, message: S) -> Diagnostic {
+    Diagnostic {
+      severity,
+      code: None,
+      message: String::from(message.as_ref()),
+      labels: vec![],
+      notes: vec![],
+    }
+  }
+
+  pub fn error<S: AsRef<str>>(message: S) -> Diagnostic {
+    Self::new(Severity::Error, message)
+  }
+
+  pub fn warning<S: AsRef<str>>(message: S) -> Diagnostic {
+    Self::new(Severity::Warning, message)
+  }
+
+  pub fn with_code<S: AsRef<str>>(mut self, code: S) -> Diagnostic {
+    self.code = Some(String::from(code.as_ref()));
+    self
+  }
+
+  pub fn with_label<S: AsRef<str>>(mut self, span: Span, message: S) -> Diagnostic {
+    self.labels.push(Label::new(span, message, true));
+    self
+  }
+
+  pub fn with_secondary_label<S: AsRef<str>>(mut self, span: Span, message: S) -> Diagnostic {
+    self.labels.push(Label::new(span, message, false));
+    self
+  }
+
+  pub fn with_note<S: AsRef<str>>(mut self, note: S) -> Diagnostic {
+    self.notes.push(String::from(note.as_ref()));
+    self
+  }
+
+  pub fn severity(&self) -> Severity {
+    self.severity
+  }
+
+  pub fn code(&self) -> Option<&String> {
+    self.code.as_ref()
+  }
+
+  pub fn message(&self) -> &String {
+    &self.message
+  }
+
+  pub fn labels(&self) -> &Vec<Label> {
+    &self.labels
+  }
+
+  pub fn notes(&self) -> &Vec<String> {
+    &self.notes
+  }
+
+  pub fn primary_span(&self) -> Option<&Span> {
+    self.labels.iter().find(|l| l.primary).map(|l| &l.span)
+  }
+
+  /// Render the diagnostic, quoting the labelled lines of `source` when given.
+  pub fn render(&self, source: Option<&str>) -> String {
+    let mut out = String::new();
+    write!(out, "{}", self.severity).unwrap();
+    if let Some(code) = &self.code {
+      write!(out, "[{}]", code).unwrap();
+    }
+    writeln!(out, ": {}", self.message).unwrap();
+
+    let mut labels: Vec<&Label> = self.labels.iter().collect();
+    labels.sort_by_key(|l| (*l.span.start().line(), *l.span.start().column()));
+    let width = labels
+      .iter()
+      .map(|l| l.span.start().line().to_string().len())
+      .max()
+      .unwrap_or(1);
+    let gutter = " ".repeat(width);
+    if let Some(span) = self.primary_span().or(labels.first().map(|l| &l.span)) {
+      writeln!(out, "{}--> {}", gutter, span.start()).unwrap();
+    }
+
+    let lines: Vec<&str> = source.map(|s| s.lines().collect()).unwrap_or_default();
+    let mut quoted = false;
+    let mut last_line = None;
+    for label in labels.iter() {
+      let line_no = *label.span.start().line();
+      let text = match lines.get((line_no as usize).wrapping_sub(1)) {
+        Some(text) => *text,
+        None => continue,
+      };
+      if !quoted {
+        writeln!(out, "{} |", gutter).unwrap();
+        quoted = true;
+      }
+      if last_line != Some(line_no) {
+        writeln!(out, "{:>width$} | {}", line_no, text, width = width).unwrap();
+        last_line = Some(line_no);
+      }
+      let (indent, len) = Self::underline(label, text);
+      let marker = if label.primary { "^" } else { "-" };
+      let underline = format!("{}{} {}", indent, marker.repeat(len), label.message);
+      writeln!(out, "{} | {}", gutter, underline.trim_end()).unwrap();
+    }
+    if !self.notes.is_empty() {
+      if quoted {
+        writeln!(out, "{} |", gutter).unwrap();
+      }
+      for note in self.notes.iter() {
+        writeln!(out, "{} = note: {}", gutter, note).unwrap();
+      }
+    }
+    out
+  }
+
+  /// The whitespace leading to a label and its length, both in characters of `text`.
+  fn underline(label: &Label, text: &str) -> (String, usize) {
+    let (start, end) = (label.span.start(), label.span.end());
+    let col = (*start.column() as usize).max(1) - 1;
+    // keep tabs so the underline lines up with the quoted source
+    let indent: String = text
+      .chars()
+      .chain(std::iter::repeat(' '))
+      .take(col)
+      .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+      .collect();
+    let line_len = text.chars().count();
+    let len = if end.line() == start.line() {
+      (*end.column() as usize).saturating_sub(col + 1)
+    } else {
+      line_len.saturating_sub(col)
+    };
+    (indent, len.max(1))
+  }
+}
+
+impl Display for Diagnostic {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.render(None))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::location::Location;
+
+  fn span(line: u64, start: u64, end: u64) -> Span {
+    Span::new(
+      Location::new("test", 0, line, start),
+      Location::new("test", 0, line, end),
+    )
+  }
+
+  #[test]
+  fn diagnostics_underline_their_spans() {
+    let source = "let a = 1;\nconst b = a +\tfoo;\n";
+    let d = Diagnostic::error("undefined identifier 'foo'")
+      .with_code("E0004")
+      .with_label(span(2, 15, 18), "not found in this scope")
+      .with_secondary_label(span(2, 1, 6), "while initializing this")
+      .with_note("declare 'foo' before using it");
+    assert_eq!(
+      d.render(Some(source)),
+      [
+        "error[E0004]: undefined identifier 'foo'",
+        " --> test:2:15",
+        "  |",
+        "2 | const b = a +\tfoo;",
+        "  | ----- while initializing this",
+        "  |              \t^^^ not found in this scope",
+        "  |",
+        "  = note: declare 'foo' before using it",
+        "",
+      ]
+      .join("\n")
+    );
+  }
+
+  #[test]
+  fn diagnostics_render_without_source() {
+    let d = Diagnostic::warning("unused variable").with_label(span(3, 2, 2), "");
+    assert_eq!(d.to_string(), "warning: unused variable\n --> test:3:2\n");
+  }
+}
@@ -0,0 +1,133 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::compiler::FunctionProto;
+use crate::error::Error;
+use crate::parser::{Function, Value, Variable};
+use crate::result::Result;
+
+pub type EnvPtr = Rc<RefCell<Environment>>;
+
+/// A lexical scope: the variables declared in a block and a link to the enclosing scope.
+#[derive(Debug, Default)]
+pub struct Environment {
+  vars: HashMap<String, Variable>,
+  parent: Option<EnvPtr>,
+}
+
+impl Environment {
+  pub fn new(parent: Option<EnvPtr>) -> EnvPtr {
+    Rc::new(RefCell::new(Environment {
+      vars: HashMap::new(),
+      parent,
+    }))
+  }
+
+  pub fn parent(&self) -> &Option<EnvPtr> {
+    &self.parent
+  }
+
+  pub fn parent_mut(&mut self) -> &mut Option<EnvPtr> {
+    &mut self.parent
+  }
+
+  pub fn vars(&self) -> &HashMap<String, Variable> {
+    &self.vars
+  }
+
+  pub fn vars_mut(&mut self) -> &mut HashMap<String, Variable> {
+    &mut self.vars
+  }
+
+  /// Drop every variable and the link to the enclosing scope.
+  pub fn clear(&mut self) {
+    self.vars.clear();
+    self.parent = None;
+  }
+
+  /// Declare a function closing over `env`, replacing any previous declaration of the same name.
+  pub fn declare_function(env: &EnvPtr, proto: Rc<FunctionProto>) -> Value {
+    let name = proto.name().clone();
+    let f = Value::Function(Rc::new(Function::Closure {
+      proto,
+      env: env.clone(),
+      class: None,
+    }));
+    env
+      .borrow_mut()
+      .vars
+      .insert(name.clone(), Variable::new(name, f.clone()));
+    f
+  }
+
+  /// Declare `var` in this scope, failing if the name is already taken here.
+  pub fn declare(&mut self, var: Variable) -> Result<()> {
+    if self.vars.contains_key(var.name()) {
+      return Err(Error::Runtime(
+        format!("'{}' is already declared", var.name()),
+        None,
+      ));
+    }
+    self.vars.insert(var.name().clone(), var);
+    Ok(())
+  }
+
+  /// Find the scope declaring `name`, walking up the chain from `env`.
+  pub fn lookup<S: AsRef<str>>(env: &EnvPtr, name: S) -> Option<EnvPtr> {
+    let mut cur = Some(env.clone());
+    while let Some(e) = cur {
+      if e.borrow().vars.contains_key(name.as_ref()) {
+        return Some(e);
+      }
+      cur = e.borrow().parent.clone();
+    }
+    None
+  }
+
+  pub fn get<S: AsRef<str>>(env: &EnvPtr, name: S) -> Result<Value> {
+    let scope = Self::lookup(env, name.as_ref()).ok_or_else(|| Self::undefined(name.as_ref()))?;
+    let scope = scope.borrow();
+    let var = scope.vars.get(name.as_ref()).unwrap();
+    if !var.is_initialized() {
+      return Err(Error::Runtime(
+        format!("cannot access '{}' before initialization", name.as_ref()),
+        None,
+      ));
+    }
+    Ok(var.value().clone())
+  }
+
+  pub fn assign<S: AsRef<str>>(env: &EnvPtr, name: S, value: Value) -> Result<()> {
+    let scope = Self::lookup(env, name.as_ref()).ok_or_else(|| Self::undefined(name.as_ref()))?;
+    let mut scope = scope.borrow_mut();
+    let var = scope.vars.get_mut(name.as_ref()).unwrap();
+    if !var.is_initialized() {
+      return Err(Error::Runtime(
+        format!("cannot access '{}' before initialization", name.as_ref()),
+        None,
+      ));
+    }
+    if var.is_constant() {
+      return Err(Error::Runtime(
+        format!("cannot assign to constant '{}'", name.as_ref()),
+        None,
+      ));
+    }
+    *var.value_mut() = value;
+    Ok(())
+  }
+
+  /// Run the declaration of a hoisted variable of this scope.
+  pub fn initialize<S: AsRef<str>>(&mut self, name: S, value: Value) -> Result<()> {
+    match self.vars.get_mut(name.as_ref()) {
+      Some(var) => {
+        var.initialize(value);
+        Ok(())
+      }
+      None => Err(Self::undefined(name.as_ref())),
+    }
+  }
+
+  fn undefined(name: &str) -> Error {
+    Error::Runtime(format!("undefined identifier '{}'", name), None)
+  }
+}
@@ -1,10 +1,125 @@
-use crate::location::Location;
+#![allow(clippy::to_string_in_format_args, clippy::useless_format)]
+
+use crate::diagnostic::Diagnostic;
+use crate::location::{Location, Span};
+use crate::parser::Value;
+use crate::trace::StackTrace;
 
 #[derive(Debug)]
 pub enum Error {
   IO(std::io::Error),
-  Syntax(String, Location),
+  Syntax(String, Span),
+  /// Precompiled data that is corrupt or was produced by another version.
+  Format(String),
+  Runtime(String, Option<Location>),
   Unknown(String, Option<Location>),
+  /// A module that can't be found or imports itself back.
+  Module(String, Option<Location>),
+  /// A value thrown by a script and never caught.
+  Thrown(Value, Option<Location>),
+  /// An error that escaped running scripts, with the calls being run at the time.
+  Traced(Box<Error>, StackTrace),
+  /// Several errors reported at once, such as every syntax error of a script.
+  Multiple(Vec<Error>),
+}
+
+impl Error {
+  /// Attach `loc` to an error that doesn't know where it happened yet.
+  pub fn at(self, loc: &Location) -> Error {
+    match self {
+      Error::Runtime(msg, None) => Error::Runtime(msg, Some(loc.clone())),
+      Error::Unknown(msg, None) => Error::Unknown(msg, Some(loc.clone())),
+      Error::Module(msg, None) => Error::Module(msg, Some(loc.clone())),
+      Error::Thrown(v, None) => Error::Thrown(v, Some(loc.clone())),
+      Error::Traced(e, trace) => Error::Traced(Box::new(e.at(loc)), trace),
+      e => e,
+    }
+  }
+
+  /// Attach the calls being run, unless the error already knows them.
+  pub fn with_trace(self, trace: StackTrace) -> Error {
+    match self {
+      e @ Error::Traced(..) => e,
+      e => Error::Traced(Box::new(e), trace),
+    }
+  }
+
+  /// The calls being run when the error happened, if known.
+  pub fn trace(&self) -> Option<&StackTrace> {
+    match self {
+      Error::Traced(_, trace) => Some(trace),
+      _ => None,
+    }
+  }
+
+  /// The error itself, without its trace.
+  pub fn into_root(self) -> Error {
+    match self {
+      Error::Traced(e, _) => e.into_root(),
+      e => e,
+    }
+  }
+
+  /// Describe a thrown value, using the `message` field of error objects.
+  fn describe(v: &Value) -> String {
+    if let Value::Instance(i) = v {
+      let i = i.borrow();
+      if let Some(msg) = i.fields().get("message") {
+        return format!("{}: {}", i.class().name(), msg.to_display_string());
+      }
+    }
+    v.to_display_string()
+  }
+
+  /// A stable code identifying the kind of error.
+  pub fn code(&self) -> &'static str {
+    match self {
+      Error::IO(_) => "E0001",
+      Error::Syntax(..) => "E0002",
+      Error::Format(_) => "E0003",
+      Error::Runtime(..) => "E0004",
+      Error::Unknown(..) => "E0005",
+      Error::Thrown(..) => "E0006",
+      Error::Module(..) => "E0007",
+      Error::Traced(e, _) => e.code(),
+      Error::Multiple(errors) => errors.first().map_or("E0000", Error::code),
+    }
+  }
+
+  /// One diagnostic per error, flattening `Multiple`.
+  pub fn diagnostics(&self) -> Vec<Diagnostic> {
+    match self {
+      Error::Multiple(errors) => errors.iter().flat_map(Error::diagnostics).collect(),
+      e => vec![e.diagnostic()],
+    }
+  }
+
+  pub fn diagnostic(&self) -> Diagnostic {
+    let (msg, span) = match self {
+      Error::Traced(e, trace) => {
+        return trace
+          .lines()
+          .into_iter()
+          .fold(e.diagnostic(), |d, line| d.with_note(line))
+      }
+      Error::Multiple(errors) => (format!("{} errors", errors.len()), None),
+      Error::IO(e) => (e.to_string(), None),
+      Error::Syntax(msg, span) => (msg.clone(), Some(span.clone())),
+      Error::Format(msg) => (msg.clone(), None),
+      Error::Runtime(msg, loc) | Error::Unknown(msg, loc) | Error::Module(msg, loc) => {
+        (msg.clone(), loc.clone().map(Span::from))
+      }
+      Error::Thrown(v, loc) => (
+        format!("uncaught {}", Self::describe(v)),
+        loc.clone().map(Span::from),
+      ),
+    };
+    let d = Diagnostic::error(msg).with_code(self.code());
+    match span {
+      Some(span) => d.with_label(span, ""),
+      None => d,
+    }
+  }
 }
 
 impl std::error::Error for Error {}
@@ -16,13 +131,45 @@ impl std::fmt::Display for Error {
       "{}",
       match self {
         Error::IO(e) => format!("I/O: {}", e.to_string()),
-        Error::Syntax(s, loc) => format!("Syntax: {} at {}:{}", s, loc.file(), loc.line()),
+        Error::Syntax(s, span) => format!("Syntax: {} at {}", s, span.start()),
+        Error::Format(s) => format!("Format: {}", s),
+        Error::Runtime(msg, loc) => {
+          format!("Runtime: {}{}", msg, match loc {
+              Some(l) => format!(" at {}", l),
+              None => "".to_string(),
+          })
+        }
         Error::Unknown(msg, loc) => {
           format!("Unknown: {}{}", msg.clone(), match loc {
-              Some(l) => format!(" at {}:{}", l.file(), l.line()),
+              Some(l) => format!(" at {}", l),
               None => format!("{}", ""),
           })
         }
+        Error::Module(msg, loc) => {
+          format!("Module: {}{}", msg, match loc {
+              Some(l) => format!(" at {}", l),
+              None => "".to_string(),
+          })
+        }
+        Error::Thrown(v, loc) => {
+          format!("Uncaught: {}{}", Self::describe(v), match loc {
+              Some(l) => format!(" at {}", l),
+              None => "".to_string(),
+          })
+        }
+        Error::Traced(e, trace) => {
+          let frames: Vec<String> = trace
+            .lines()
+            .iter()
+            .map(|line| format!("\n  {}", line))
+            .collect();
+          format!("{}{}", e, frames.concat())
+        }
+        Error::Multiple(errors) => errors
+          .iter()
+          .map(|e| e.to_string())
+          .collect::<Vec<String>>()
+          .join("\n"),
       }
     )
   }
@@ -0,0 +1,333 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+
+use crate::environment::{EnvPtr, Environment};
+use crate::parser::{ArrayPtr, Class, Function, Instance, InstancePtr, ObjectPtr, Value};
+
+pub const DEFAULT_GC_THRESHOLD: usize = 10_000;
+pub const DEFAULT_GC_GROWTH: f64 = 2.0;
+
+/// A heap allocated value, held weakly so tracking it doesn't keep it alive.
+enum Tracked {
+  Array(Weak<RefCell<Vec<Value>>>),
+  Object(Weak<RefCell<HashMap<String, Value>>>),
+  Instance(Weak<RefCell<Instance>>),
+  Environment(Weak<RefCell<Environment>>),
+  Function(Weak<Function>),
+  Class(Weak<Class>),
+}
+
+impl Tracked {
+  fn upgrade(&self) -> Option<Live> {
+    Some(match self {
+      Tracked::Array(a) => Live::Array(a.upgrade()?),
+      Tracked::Object(o) => Live::Object(o.upgrade()?),
+      Tracked::Instance(i) => Live::Instance(i.upgrade()?),
+      Tracked::Environment(e) => Live::Environment(e.upgrade()?),
+      Tracked::Function(f) => Live::Function(f.upgrade()?),
+      Tracked::Class(c) => Live::Class(c.upgrade()?),
+    })
+  }
+
+  fn is_alive(&self) -> bool {
+    match self {
+      Tracked::Array(a) => a.strong_count() > 0,
+      Tracked::Object(o) => o.strong_count() > 0,
+      Tracked::Instance(i) => i.strong_count() > 0,
+      Tracked::Environment(e) => e.strong_count() > 0,
+      Tracked::Function(f) => f.strong_count() > 0,
+      Tracked::Class(c) => c.strong_count() > 0,
+    }
+  }
+}
+
+/// A tracked value kept alive for the time of a collection.
+enum Live {
+  Array(ArrayPtr),
+  Object(ObjectPtr),
+  Instance(InstancePtr),
+  Environment(EnvPtr),
+  Function(Rc<Function>),
+  Class(Rc<Class>),
+}
+
+impl Live {
+  fn id(&self) -> usize {
+    match self {
+      Live::Array(a) => Rc::as_ptr(a) as *const () as usize,
+      Live::Object(o) => Rc::as_ptr(o) as *const () as usize,
+      Live::Instance(i) => Rc::as_ptr(i) as *const () as usize,
+      Live::Environment(e) => env_id(e),
+      Live::Function(f) => Rc::as_ptr(f) as *const () as usize,
+      Live::Class(c) => Rc::as_ptr(c) as *const () as usize,
+    }
+  }
+
+  /// Strong references besides the one held by the collector.
+  fn references(&self) -> usize {
+    let count = match self {
+      Live::Array(a) => Rc::strong_count(a),
+      Live::Object(o) => Rc::strong_count(o),
+      Live::Instance(i) => Rc::strong_count(i),
+      Live::Environment(e) => Rc::strong_count(e),
+      Live::Function(f) => Rc::strong_count(f),
+      Live::Class(c) => Rc::strong_count(c),
+    };
+    count - 1
+  }
+
+  /// The ids of the values this one holds a strong reference to.
+  fn edges(&self, out: &mut Vec<usize>) {
+    match self {
+      Live::Array(a) => out.extend(a.borrow().iter().filter_map(value_id)),
+      Live::Object(o) => out.extend(o.borrow().values().filter_map(value_id)),
+      Live::Instance(i) => {
+        let i = i.borrow();
+        out.extend(i.fields().values().filter_map(value_id));
+        out.push(Rc::as_ptr(i.class()) as *const () as usize);
+      }
+      Live::Environment(e) => {
+        let e = e.borrow();
+        out.extend(e.vars().values().filter_map(|var| value_id(var.value())));
+        out.extend(e.parent().as_ref().map(env_id));
+      }
+      Live::Function(f) => {
+        if let Function::Closure { env, class, .. } = &**f {
+          out.push(env_id(env));
+          out.extend(class.as_ref().map(|c| Rc::as_ptr(c) as *const () as usize));
+        }
+      }
+      Live::Class(c) => {
+        out.push(env_id(c.env()));
+        out.extend(c.superclass().map(|s| Rc::as_ptr(s) as *const () as usize));
+      }
+    }
+  }
+
+  /// Drop everything this value holds, breaking the cycles going through it.
+  ///
+  /// Functions and classes can't change once created, so every cycle goes through one
+  /// of the other values.
+  fn clear(&self) {
+    match self {
+      Live::Array(a) => a.borrow_mut().clear(),
+      Live::Object(o) => o.borrow_mut().clear(),
+      Live::Instance(i) => i.borrow_mut().fields_mut().clear(),
+      Live::Environment(e) => e.borrow_mut().clear(),
+      Live::Function(_) | Live::Class(_) => {}
+    }
+  }
+}
+
+fn env_id(env: &EnvPtr) -> usize {
+  Rc::as_ptr(env) as *const () as usize
+}
+
+fn value_id(v: &Value) -> Option<usize> {
+  match v {
+    Value::Array(a) => Some(Rc::as_ptr(a) as *const () as usize),
+    Value::Object(o) => Some(Rc::as_ptr(o) as *const () as usize),
+    Value::Instance(i) => Some(Rc::as_ptr(i) as *const () as usize),
+    Value::Function(f) => Some(Rc::as_ptr(f) as *const () as usize),
+    Value::Class(c) => Some(Rc::as_ptr(c) as *const () as usize),
+    _ => None,
+  }
+}
+
+/// What a collection did.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+  tracked: usize,
+  freed: usize,
+  elapsed: Duration,
+}
+
+impl GcStats {
+  /// Values alive when the collection started.
+  pub fn tracked(&self) -> usize {
+    self.tracked
+  }
+
+  /// Unreachable values the collection released.
+  pub fn freed(&self) -> usize {
+    self.freed
+  }
+
+  /// Values left alive.
+  pub fn live(&self) -> usize {
+    self.tracked - self.freed
+  }
+
+  pub fn elapsed(&self) -> Duration {
+    self.elapsed
+  }
+}
+
+impl std::fmt::Display for GcStats {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "freed {} of {} values, {} live ({:?})",
+      self.freed,
+      self.tracked,
+      self.live(),
+      self.elapsed
+    )
+  }
+}
+
+/// The values allocated by scripts, along with a tracing collector for them.
+///
+/// Values are reference counted, which frees most of them as soon as they are dropped.
+/// The collector takes care of the rest: cycles of arrays, objects, instances and scopes
+/// no longer reachable from the roots. Values referenced from outside the heap, such as
+/// the ones a host holds on to, are roots as well.
+pub struct Heap {
+  tracked: Vec<Tracked>,
+  threshold: usize,
+  growth: f64,
+  /// How many tracked values trigger the next collection.
+  next: usize,
+  collections: usize,
+  last: Option<GcStats>,
+}
+
+impl Default for Heap {
+  fn default() -> Self {
+    Self {
+      tracked: vec![],
+      threshold: DEFAULT_GC_THRESHOLD,
+      growth: DEFAULT_GC_GROWTH,
+      next: 0,
+      collections: 0,
+      last: None,
+    }
+  }
+}
+
+impl Heap {
+  /// Keep track of `v` if it is allocated on the heap.
+  pub fn track(&mut self, v: &Value) {
+    let tracked = match v {
+      Value::Array(a) => Tracked::Array(Rc::downgrade(a)),
+      Value::Object(o) => Tracked::Object(Rc::downgrade(o)),
+      Value::Instance(i) => Tracked::Instance(Rc::downgrade(i)),
+      Value::Function(f) => Tracked::Function(Rc::downgrade(f)),
+      Value::Class(c) => Tracked::Class(Rc::downgrade(c)),
+      _ => return,
+    };
+    self.tracked.push(tracked);
+  }
+
+  pub fn track_env(&mut self, env: &EnvPtr) {
+    self.tracked.push(Tracked::Environment(Rc::downgrade(env)));
+  }
+
+  /// Tracked values still alive.
+  pub fn len(&self) -> usize {
+    self.tracked.iter().filter(|t| t.is_alive()).count()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// The least number of tracked values before collecting automatically.
+  pub fn threshold(&self) -> usize {
+    self.threshold
+  }
+
+  pub fn threshold_mut(&mut self) -> &mut usize {
+    &mut self.threshold
+  }
+
+  /// How much the heap grows past the values surviving a collection before the next one.
+  pub fn growth(&self) -> f64 {
+    self.growth
+  }
+
+  pub fn growth_mut(&mut self) -> &mut f64 {
+    &mut self.growth
+  }
+
+  /// How many collections ran so far.
+  pub fn collections(&self) -> usize {
+    self.collections
+  }
+
+  /// What the latest collection did, if any ran.
+  pub fn last(&self) -> Option<&GcStats> {
+    self.last.as_ref()
+  }
+
+  pub fn should_collect(&self) -> bool {
+    self.tracked.len() >= self.threshold.max(self.next)
+  }
+
+  /// Mark the values reachable from `values` and `envs`, or referenced from outside the
+  /// heap, then sweep the others.
+  pub fn collect<'a>(
+    &mut self,
+    values: impl IntoIterator<Item = &'a Value>,
+    envs: impl IntoIterator<Item = &'a EnvPtr>,
+  ) -> GcStats {
+    let start = Instant::now();
+    self.tracked.retain(Tracked::is_alive);
+    let objects: Vec<Live> = self.tracked.iter().filter_map(Tracked::upgrade).collect();
+    let index: HashMap<usize, usize> = objects
+      .iter()
+      .enumerate()
+      .map(|(idx, o)| (o.id(), idx))
+      .collect();
+
+    // references from other tracked values, the remaining ones come from outside
+    let mut internal = vec![0; objects.len()];
+    let mut edges = vec![];
+    for o in objects.iter() {
+      edges.clear();
+      o.edges(&mut edges);
+      for id in edges.iter() {
+        if let Some(idx) = index.get(id) {
+          internal[*idx] += 1;
+        }
+      }
+    }
+    let mut pending: Vec<usize> = values
+      .into_iter()
+      .filter_map(value_id)
+      .chain(envs.into_iter().map(env_id))
+      .filter_map(|id| index.get(&id).copied())
+      .chain((0..objects.len()).filter(|idx| objects[*idx].references() > internal[*idx]))
+      .collect();
+
+    let mut marked = HashSet::new();
+    while let Some(idx) = pending.pop() {
+      if !marked.insert(idx) {
+        continue;
+      }
+      edges.clear();
+      objects[idx].edges(&mut edges);
+      pending.extend(edges.iter().filter_map(|id| index.get(id).copied()));
+    }
+    for (idx, o) in objects.iter().enumerate() {
+      if !marked.contains(&idx) {
+        o.clear();
+      }
+    }
+
+    let tracked = objects.len();
+    drop(objects);
+    self.tracked.retain(Tracked::is_alive);
+    let stats = GcStats {
+      tracked,
+      freed: tracked - self.tracked.len(),
+      elapsed: start.elapsed(),
+    };
+    self.next = (stats.live() as f64 * self.growth) as usize;
+    self.collections += 1;
+    self.last = Some(stats);
+    stats
+  }
+}
@@ -3,4 +3,12 @@ pub mod script;
 pub mod error;
 pub mod result;
 pub mod parser;
-pub mod location;
\ No newline at end of file
+pub mod location;
+pub mod environment;
+pub mod compiler;
+pub mod diagnostic;
+pub mod trace;
+pub mod heap;
+pub mod module;
+pub mod loader;
+pub mod convert;
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::error::Error;
+use crate::module::Module;
+use crate::result::Result;
+
+/// Finds and reads the source of scripts, for the `Vm` to load and import them.
+pub trait ModuleLoader {
+  /// Whether there is a script at `path`.
+  fn exists(&self, path: &Path) -> bool;
+
+  /// The source of the script at `path`.
+  fn load(&self, path: &Path) -> Result<String>;
+
+  /// When the script at `path` last changed, if the loader can tell.
+  fn modified(&self, _path: &Path) -> Option<SystemTime> {
+    None
+  }
+
+  /// The path of the script `specifier` stands for when imported from the script at
+  /// `from`, if there is one.
+  ///
+  /// Specifiers resolve relative to `from`, and may leave out the extension of `from`.
+  fn resolve(&self, specifier: &str, from: &Path) -> Option<PathBuf> {
+    candidates(Module::resolve(from, specifier), from)
+      .into_iter()
+      .find(|p| self.exists(p))
+  }
+}
+
+/// `path`, then `path` with the extension of `from` if it has none.
+fn candidates(path: PathBuf, from: &Path) -> Vec<PathBuf> {
+  let mut ret = vec![path.clone()];
+  if let (None, Some(ext)) = (path.extension(), from.extension()) {
+    ret.push(path.with_extension(ext));
+  }
+  ret
+}
+
+/// Reads scripts from the filesystem.
+#[derive(Debug, Default, Clone)]
+pub struct FileSystemLoader;
+
+impl ModuleLoader for FileSystemLoader {
+  fn exists(&self, path: &Path) -> bool {
+    path.is_file()
+  }
+
+  fn load(&self, path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(Error::IO)
+  }
+
+  fn modified(&self, path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+  }
+}
+
+/// Serves scripts from memory, by path.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryLoader {
+  sources: HashMap<PathBuf, String>,
+}
+
+impl MemoryLoader {
+  pub fn new() -> MemoryLoader {
+    MemoryLoader::default()
+  }
+
+  /// Add the script at `path`, replacing any previous one.
+  pub fn insert<P: AsRef<Path>, S: AsRef<str>>(&mut self, path: P, source: S) {
+    self
+      .sources
+      .insert(Module::normalize(path), String::from(source.as_ref()));
+  }
+
+  pub fn with<P: AsRef<Path>, S: AsRef<str>>(mut self, path: P, source: S) -> MemoryLoader {
+    self.insert(path, source);
+    self
+  }
+
+  pub fn sources(&self) -> &HashMap<PathBuf, String> {
+    &self.sources
+  }
+}
+
+impl ModuleLoader for MemoryLoader {
+  fn exists(&self, path: &Path) -> bool {
+    self.sources.contains_key(&Module::normalize(path))
+  }
+
+  fn load(&self, path: &Path) -> Result<String> {
+    self
+      .sources
+      .get(&Module::normalize(path))
+      .cloned()
+      .ok_or_else(|| Error::Module(format!("no script at '{}'", path.display()), None))
+  }
+}
+
+/// Resolves specifiers that aren't relative, such as `"json"`, against a list of
+/// directories in order, reading scripts through another loader.
+pub struct SearchPathLoader<L: ModuleLoader> {
+  paths: Vec<PathBuf>,
+  inner: L,
+}
+
+impl<L: ModuleLoader> SearchPathLoader<L> {
+  pub fn new<P: AsRef<Path>>(paths: &[P], inner: L) -> SearchPathLoader<L> {
+    SearchPathLoader {
+      paths: paths.iter().map(|p| PathBuf::from(p.as_ref())).collect(),
+      inner,
+    }
+  }
+
+  pub fn paths(&self) -> &Vec<PathBuf> {
+    &self.paths
+  }
+
+  pub fn paths_mut(&mut self) -> &mut Vec<PathBuf> {
+    &mut self.paths
+  }
+
+  pub fn inner(&self) -> &L {
+    &self.inner
+  }
+}
+
+impl<L: ModuleLoader> ModuleLoader for SearchPathLoader<L> {
+  fn exists(&self, path: &Path) -> bool {
+    self.inner.exists(path)
+  }
+
+  fn load(&self, path: &Path) -> Result<String> {
+    self.inner.load(path)
+  }
+
+  fn modified(&self, path: &Path) -> Option<SystemTime> {
+    self.inner.modified(path)
+  }
+
+  fn resolve(&self, specifier: &str, from: &Path) -> Option<PathBuf> {
+    if Module::is_relative(specifier) {
+      return self.inner.resolve(specifier, from);
+    }
+    self
+      .paths
+      .iter()
+      .flat_map(|dir| candidates(Module::normalize(dir.join(specifier)), from))
+      .find(|p| self.inner.exists(p))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn specifiers_are_resolved() {
+    let memory = MemoryLoader::new()
+      .with("virtual://app/main.vm", "")
+      .with("virtual://app/lib/util.vm", "export const a = 1;")
+      .with("virtual://vendor/json.vm", "")
+      .with("virtual://vendor/json/parse", "");
+    let main = Path::new("virtual://app/main.vm");
+    let util = memory.resolve("./lib/util", main).unwrap();
+    assert_eq!(util, Module::normalize("virtual://app/lib/util.vm"));
+    assert_eq!(memory.load(&util).unwrap(), "export const a = 1;");
+    assert_eq!(
+      memory.resolve("../../app/./main", &util),
+      Some(Module::normalize(main))
+    );
+    assert_eq!(
+      memory.resolve("../main", &util),
+      Some(Module::normalize(main))
+    );
+    assert!(memory.resolve("./missing", main).is_none());
+    assert!(memory.load(Path::new("virtual://missing")).is_err());
+
+    let search = SearchPathLoader::new(&["virtual://nowhere", "virtual://vendor"], memory);
+    assert_eq!(
+      search.resolve("json", main),
+      Some(Module::normalize("virtual://vendor/json.vm"))
+    );
+    assert_eq!(
+      search.resolve("json/parse", main),
+      Some(Module::normalize("virtual://vendor/json/parse"))
+    );
+    assert!(search.resolve("./lib/util", main).is_some());
+    assert!(search.resolve("util", main).is_none());
+  }
+}
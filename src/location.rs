@@ -17,6 +17,12 @@ impl Default for Location {
   }
 }
 
+impl std::fmt::Display for Location {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}:{}:{}", self.file, self.line, self.column)
+  }
+}
+
 impl Location {
   pub fn new<S: AsRef<str>>(name: S, offset: u64, line: u64, column: u64) -> Location {
     Location {
@@ -59,3 +65,38 @@ impl Location {
     &mut self.column
   }
 }
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Span {
+  start: Location,
+  end: Location,
+}
+
+impl From<Location> for Span {
+  /// An empty span at `loc`.
+  fn from(loc: Location) -> Self {
+    Span::new(loc.clone(), loc)
+  }
+}
+
+impl Span {
+  pub fn new(start: Location, end: Location) -> Span {
+    Span { start, end }
+  }
+
+  pub fn start(&self) -> &Location {
+    &self.start
+  }
+
+  pub fn start_mut(&mut self) -> &mut Location {
+    &mut self.start
+  }
+
+  pub fn end(&self) -> &Location {
+    &self.end
+  }
+
+  pub fn end_mut(&mut self) -> &mut Location {
+    &mut self.end
+  }
+}
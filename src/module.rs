@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::environment::{EnvPtr, Environment};
+use crate::error::Error;
+use crate::parser::Value;
+use crate::result::Result;
+
+/// The namespace of a script: its top-level scope and the names it exports.
+#[derive(Debug)]
+pub struct Module {
+  name: String,
+  path: PathBuf,
+  env: EnvPtr,
+  exports: Vec<String>,
+}
+
+impl Module {
+  pub fn new<S: AsRef<str>, P: AsRef<Path>>(name: S, path: P, env: EnvPtr) -> Module {
+    Module {
+      name: String::from(name.as_ref()),
+      path: PathBuf::from(path.as_ref()),
+      env,
+      exports: vec![],
+    }
+  }
+
+  /// The name of the script.
+  pub fn name(&self) -> &String {
+    &self.name
+  }
+
+  pub fn path(&self) -> &PathBuf {
+    &self.path
+  }
+
+  /// The top-level scope of the script, enclosed by the globals.
+  pub fn env(&self) -> &EnvPtr {
+    &self.env
+  }
+
+  pub fn exports(&self) -> &Vec<String> {
+    &self.exports
+  }
+
+  pub fn exports_mut(&mut self) -> &mut Vec<String> {
+    &mut self.exports
+  }
+
+  /// A top-level binding of the script, exported or not.
+  pub fn get<S: AsRef<str>>(&self, name: S) -> Result<Value> {
+    Environment::get(&self.env, name)
+  }
+
+  /// The value exported as `name`.
+  pub fn export<S: AsRef<str>>(&self, name: S) -> Result<Value> {
+    if !self.exports.iter().any(|e| e == name.as_ref()) {
+      return Err(Error::Module(
+        format!("'{}' does not export '{}'", self.name, name.as_ref()),
+        None,
+      ));
+    }
+    self.get(name)
+  }
+
+  /// The exported values as an object, copied from the module's bindings so that it
+  /// doesn't follow later assignments.
+  pub fn namespace(&self) -> Result<Value> {
+    let mut entries = HashMap::new();
+    for name in self.exports.iter() {
+      entries.insert(name.clone(), self.get(name)?);
+    }
+    Ok(Value::object(entries))
+  }
+
+  /// The path `specifier` refers to when imported from the script at `from`.
+  ///
+  /// Paths are resolved lexically, so that virtual paths resolve like real ones.
+  pub fn resolve<P: AsRef<Path>>(from: P, specifier: &str) -> PathBuf {
+    let base = from.as_ref().parent().unwrap_or_else(|| Path::new(""));
+    Self::normalize(base.join(specifier))
+  }
+
+  /// Whether `specifier` is relative to the importing script, rather than naming a module.
+  pub fn is_relative(specifier: &str) -> bool {
+    specifier.starts_with("./") || specifier.starts_with("../")
+  }
+
+  /// Drop the `.` components of `path` and the ones its `..` components cancel.
+  pub fn normalize<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut ret = PathBuf::new();
+    for component in path.as_ref().components() {
+      match component {
+        Component::CurDir => {}
+        Component::ParentDir => match ret.components().next_back() {
+          Some(Component::Normal(_)) => {
+            ret.pop();
+          }
+          Some(Component::RootDir | Component::Prefix(_)) => {}
+          _ => ret.push(component),
+        },
+        c => ret.push(c),
+      }
+    }
+    ret
+  }
+}
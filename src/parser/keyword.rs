@@ -2,7 +2,7 @@ use std::fmt::Display;
 
 use enum_iterator::IntoEnumIterator;
 
-#[derive(IntoEnumIterator, Copy, Clone, Debug)]
+#[derive(IntoEnumIterator, Copy, Clone, PartialEq, Debug)]
 pub enum Keyword {
   Function,
   Class,
@@ -13,7 +13,27 @@ pub enum Keyword {
   Protected,
   Throw,
   Let,
-  Const
+  Const,
+  True,
+  False,
+  Null,
+  Undefined,
+  If,
+  Else,
+  While,
+  For,
+  Break,
+  Continue,
+  New,
+  This,
+  Extends,
+  Super,
+  InstanceOf,
+  Try,
+  Catch,
+  Finally,
+  Import,
+  Export,
 }
 
 impl Display for Keyword {
@@ -32,6 +52,26 @@ impl Display for Keyword {
         Keyword::Throw => "throw",
         Keyword::Let => "let",
         Keyword::Const => "const",
+        Keyword::True => "true",
+        Keyword::False => "false",
+        Keyword::Null => "null",
+        Keyword::Undefined => "undefined",
+        Keyword::If => "if",
+        Keyword::Else => "else",
+        Keyword::While => "while",
+        Keyword::For => "for",
+        Keyword::Break => "break",
+        Keyword::Continue => "continue",
+        Keyword::New => "new",
+        Keyword::This => "this",
+        Keyword::Extends => "extends",
+        Keyword::Super => "super",
+        Keyword::InstanceOf => "instanceof",
+        Keyword::Try => "try",
+        Keyword::Catch => "catch",
+        Keyword::Finally => "finally",
+        Keyword::Import => "import",
+        Keyword::Export => "export",
       }
     )
   }
@@ -41,4 +81,17 @@ impl Keyword {
   pub fn parse<S: AsRef<str>>(s: S) -> Option<Keyword> {
     Keyword::into_enum_iter().find(|kw| format!("{}", kw) == s.as_ref().trim())
   }
+
+  /// Whether this keyword denotes a value rather than starting a statement.
+  pub fn is_litteral(&self) -> bool {
+    matches!(
+      *self,
+      Keyword::True | Keyword::False | Keyword::Null | Keyword::Undefined
+    )
+  }
+
+  /// Whether a statement starting with this keyword is an expression.
+  pub fn starts_expression(&self) -> bool {
+    self.is_litteral() || matches!(*self, Keyword::New | Keyword::This | Keyword::Super)
+  }
 }
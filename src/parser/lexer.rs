@@ -0,0 +1,388 @@
+use std::fmt::Display;
+
+use crate::error::Error;
+use crate::location::{Location, Span};
+use crate::result::Result;
+
+use super::{Keyword, Operator, Symbol};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+  Identifier(String),
+  Keyword(Keyword),
+  Integer(i64),
+  Double(f64),
+  String(String),
+  Symbol(Symbol),
+  Operator(Operator),
+  /// Input that couldn't be lexed, only produced by `tokenize_partial`.
+  Error,
+  Eof,
+}
+
+impl Display for Token {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Identifier(id) => write!(f, "{}", id),
+      Self::Keyword(kw) => write!(f, "{}", kw),
+      Self::Integer(i) => write!(f, "{}", i),
+      Self::Double(d) => write!(f, "{}", d),
+      Self::String(s) => write!(f, "\"{}\"", s),
+      Self::Symbol(sym) => write!(f, "{}", sym.repr()),
+      Self::Operator(op) => write!(f, "{}", op),
+      Self::Error => write!(f, "invalid token"),
+      Self::Eof => write!(f, "end of file"),
+    }
+  }
+}
+
+/// A token along with the source span it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lexeme {
+  token: Token,
+  span: Span,
+}
+
+impl Lexeme {
+  pub fn new(token: Token, span: Span) -> Lexeme {
+    Lexeme { token, span }
+  }
+
+  pub fn token(&self) -> &Token {
+    &self.token
+  }
+
+  pub fn token_mut(&mut self) -> &mut Token {
+    &mut self.token
+  }
+
+  pub fn span(&self) -> &Span {
+    &self.span
+  }
+
+  pub fn span_mut(&mut self) -> &mut Span {
+    &mut self.span
+  }
+}
+
+/// Turns script source into a stream of `Lexeme`s, skipping whitespace and comments.
+pub struct Lexer {
+  chars: Vec<char>,
+  pos: usize,
+  location: Location,
+  done: bool,
+}
+
+impl Lexer {
+  pub fn new<S: AsRef<str>, C: AsRef<str>>(file: S, content: C) -> Lexer {
+    Lexer {
+      chars: content.as_ref().chars().collect(),
+      pos: 0,
+      location: Location::new(file, 0, 1, 1),
+      done: false,
+    }
+  }
+
+  pub fn location(&self) -> &Location {
+    &self.location
+  }
+
+  /// Lex the whole input, the last lexeme always being `Token::Eof`.
+  pub fn tokenize(self) -> Result<Vec<Lexeme>> {
+    self.collect()
+  }
+
+  /// Lex the whole input, skipping over invalid characters instead of stopping at them.
+  ///
+  /// Each error leaves a `Token::Error` lexeme spanning the invalid input.
+  pub fn tokenize_partial(mut self) -> (Vec<Lexeme>, Vec<Error>) {
+    let (mut lexemes, mut errors) = (vec![], vec![]);
+    loop {
+      match self.next_lexeme() {
+        Ok(lexeme) => {
+          let eof = *lexeme.token() == Token::Eof;
+          lexemes.push(lexeme);
+          if eof {
+            break;
+          }
+        }
+        Err(e) => {
+          // skip the offending character when nothing could be read
+          if matches!(&e, Error::Syntax(_, span) if span.start() == &self.location) {
+            self.bump();
+          }
+          if let Error::Syntax(_, span) = &e {
+            lexemes.push(Lexeme::new(Token::Error, span.clone()));
+          }
+          errors.push(e);
+        }
+      }
+    }
+    (lexemes, errors)
+  }
+
+  pub fn next_lexeme(&mut self) -> Result<Lexeme> {
+    self.skip_blanks()?;
+    let start = self.location.clone();
+    let token = match self.peek() {
+      None => Token::Eof,
+      Some(ch) if ch.is_ascii_digit() => self.lex_number()?,
+      Some(ch) if Self::is_ident_start(ch) => self.lex_word(),
+      Some(ch) if ch == Symbol::DoubleQuote.repr() || ch == Symbol::SingleQuote.repr() => {
+        self.lex_string(ch)?
+      }
+      Some(ch) if Operator::is_operator_char(ch) => self.lex_operator()?,
+      Some(ch) => match Symbol::parse(ch) {
+        Some(sym) => {
+          self.bump();
+          Token::Symbol(sym)
+        }
+        None => {
+          return Err(Error::Syntax(
+            format!("unexpected character '{}'", ch),
+            start.into(),
+          ))
+        }
+      },
+    };
+    Ok(Lexeme::new(token, Span::new(start, self.location.clone())))
+  }
+
+  fn is_ident_start(ch: char) -> bool {
+    ch.is_alphabetic() || ch == '_' || ch == '$'
+  }
+
+  fn is_ident_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch == '$'
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.chars.get(self.pos).copied()
+  }
+
+  fn peek_at(&self, n: usize) -> Option<char> {
+    self.chars.get(self.pos + n).copied()
+  }
+
+  fn bump(&mut self) -> Option<char> {
+    let ch = self.peek()?;
+    self.pos += 1;
+    *self.location.offset_mut() += 1;
+    if ch == Symbol::NewLine.repr() {
+      *self.location.line_mut() += 1;
+      *self.location.column_mut() = 1;
+    } else {
+      *self.location.column_mut() += 1;
+    }
+    Some(ch)
+  }
+
+  fn skip_blanks(&mut self) -> Result<()> {
+    loop {
+      match (self.peek(), self.peek_at(1)) {
+        (Some(ch), _) if ch.is_whitespace() => {
+          self.bump();
+        }
+        (Some('/'), Some('/')) => {
+          while self.peek().is_some() && self.peek() != Some(Symbol::NewLine.repr()) {
+            self.bump();
+          }
+        }
+        (Some('/'), Some('*')) => {
+          let start = self.location.clone();
+          self.bump();
+          self.bump();
+          loop {
+            match (self.peek(), self.peek_at(1)) {
+              (Some('*'), Some('/')) => {
+                self.bump();
+                self.bump();
+                break;
+              }
+              (Some(_), _) => {
+                self.bump();
+              }
+              (None, _) => {
+                return Err(Error::Syntax("unterminated comment".into(), Span::new(start, self.location.clone())));
+              }
+            }
+          }
+        }
+        _ => return Ok(()),
+      }
+    }
+  }
+
+  fn lex_word(&mut self) -> Token {
+    let mut word = String::new();
+    while let Some(ch) = self.peek().filter(|ch| Self::is_ident_char(*ch)) {
+      word.push(ch);
+      self.bump();
+    }
+    match Keyword::parse(&word) {
+      Some(kw) => Token::Keyword(kw),
+      None => Token::Identifier(word),
+    }
+  }
+
+  fn lex_number(&mut self) -> Result<Token> {
+    let start = self.location.clone();
+    let mut text = String::new();
+    while let Some(ch) = self.peek().filter(|ch| ch.is_ascii_digit()) {
+      text.push(ch);
+      self.bump();
+    }
+    let is_double = self.peek() == Some(Symbol::Dot.repr())
+      && self.peek_at(1).is_some_and(|ch| ch.is_ascii_digit());
+    if is_double {
+      text.push(Symbol::Dot.repr());
+      self.bump();
+      while let Some(ch) = self.peek().filter(|ch| ch.is_ascii_digit()) {
+        text.push(ch);
+        self.bump();
+      }
+    }
+    if self.peek().is_some_and(Self::is_ident_char) {
+      return Err(Error::Syntax(
+        format!("invalid number literal '{}{}'", text, self.peek().unwrap()),
+        Span::new(start, self.location.clone()),
+      ));
+    }
+    let span = Span::new(start, self.location.clone());
+    if is_double {
+      text
+        .parse::<f64>()
+        .map(Token::Double)
+        .map_err(|e| Error::Syntax(format!("invalid number literal '{}': {}", text, e), span))
+    } else {
+      text
+        .parse::<i64>()
+        .map(Token::Integer)
+        .map_err(|e| Error::Syntax(format!("invalid number literal '{}': {}", text, e), span))
+    }
+  }
+
+  fn lex_string(&mut self, quote: char) -> Result<Token> {
+    let start = self.location.clone();
+    let mut text = String::new();
+    self.bump();
+    loop {
+      match self.bump() {
+        None => return Err(Error::Syntax("unterminated string".into(), Span::new(start, self.location.clone()))),
+        Some(ch) if ch == quote => break,
+        Some('\\') => {
+          let escaped = match self.bump() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some(ch) => ch,
+            None => return Err(Error::Syntax("unterminated string".into(), Span::new(start, self.location.clone()))),
+          };
+          text.push(escaped);
+        }
+        Some(ch) => text.push(ch),
+      }
+    }
+    Ok(Token::String(text))
+  }
+
+  fn lex_operator(&mut self) -> Result<Token> {
+    let start = self.location.clone();
+    // longest match first
+    for len in (1..=2).rev() {
+      let text: String = self.chars.iter().skip(self.pos).take(len).collect();
+      if let Some(op) = Operator::parse(&text) {
+        for _ in 0..len {
+          self.bump();
+        }
+        return Ok(Token::Operator(op));
+      }
+    }
+    Err(Error::Syntax(
+      format!("unexpected character '{}'", self.peek().unwrap_or_default()),
+      start.into(),
+    ))
+  }
+}
+
+impl Iterator for Lexer {
+  type Item = Result<Lexeme>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.done {
+      return None;
+    }
+    let ret = self.next_lexeme();
+    match &ret {
+      Ok(lexeme) => self.done = *lexeme.token() == Token::Eof,
+      Err(_) => self.done = true,
+    }
+    Some(ret)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn tokens(s: &str) -> Vec<Token> {
+    Lexer::new("test", s)
+      .tokenize()
+      .unwrap()
+      .into_iter()
+      .map(|l| l.token().clone())
+      .collect()
+  }
+
+  #[test]
+  fn lexing_works() {
+    assert_eq!(
+      tokens("function f(a) { return a >= 1.5; } // done"),
+      vec![
+        Token::Keyword(Keyword::Function),
+        Token::Identifier("f".into()),
+        Token::Symbol(Symbol::LParent),
+        Token::Identifier("a".into()),
+        Token::Symbol(Symbol::RParent),
+        Token::Symbol(Symbol::LBrace),
+        Token::Keyword(Keyword::Return),
+        Token::Identifier("a".into()),
+        Token::Operator(Operator::GreaterEqual),
+        Token::Double(1.5),
+        Token::Symbol(Symbol::SemiColon),
+        Token::Symbol(Symbol::RBrace),
+        Token::Eof,
+      ]
+    );
+    assert_eq!(
+      tokens("letter = 'it\\'s' /* c */ != 42"),
+      vec![
+        Token::Identifier("letter".into()),
+        Token::Operator(Operator::Assign),
+        Token::String("it's".into()),
+        Token::Operator(Operator::NotEqual),
+        Token::Integer(42),
+        Token::Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn lexemes_carry_spans() {
+    let lexemes = Lexer::new("test", "a\n  bc").tokenize().unwrap();
+    let bc = lexemes.get(1).unwrap().span();
+    assert_eq!(*bc.start().line(), 2);
+    assert_eq!(*bc.start().column(), 3);
+    assert_eq!(*bc.end().column(), 5);
+    assert_eq!(*bc.start().offset(), 4);
+  }
+
+  #[test]
+  fn lexing_errors_are_located() {
+    match Lexer::new("test", "a = \"oops").tokenize() {
+      Err(Error::Syntax(_, span)) => assert_eq!(*span.start().column(), 5),
+      _ => panic!("expected a syntax error"),
+    }
+  }
+}
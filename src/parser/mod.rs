@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod parser;
 pub mod node;
 pub mod keyword;
@@ -9,6 +10,9 @@ pub mod node_kind;
 pub mod visibility;
 pub mod symbol;
 pub mod options;
+pub mod operator;
+pub mod lexer;
+pub mod object;
 
 pub use parser::*;
 pub use node::*;
@@ -20,4 +24,7 @@ pub use variable::*;
 pub use node_kind::*;
 pub use visibility::*;
 pub use symbol::*;
-pub use options::*;
\ No newline at end of file
+pub use options::*;
+pub use operator::*;
+pub use lexer::*;
+pub use object::*;
\ No newline at end of file
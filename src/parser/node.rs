@@ -1,19 +1,29 @@
-use std::{borrow::BorrowMut, cell::RefCell, fmt::Display, rc::Rc};
+#![allow(clippy::map_clone, clippy::single_match)]
+
+use std::{
+  cell::RefCell,
+  fmt::Display,
+  rc::{Rc, Weak},
+};
 
 use crate::location::Location;
 
 use super::{NodeKind, Value, Visibility};
 
 pub type NodePtr = Rc<RefCell<Node>>;
+/// A link back to a parent node, which doesn't keep it alive.
+pub type WeakNodePtr = Weak<RefCell<Node>>;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct Node {
-  parent: Option<NodePtr>,
+  parent: Option<WeakNodePtr>,
   kind: NodeKind,
   name: Option<String>,
   location: Location,
   children: Vec<NodePtr>,
   visiblity: Visibility,
+  /// Whether the declaration is exported from its module.
+  exported: bool,
   value: Option<Value>,
 }
 
@@ -30,6 +40,7 @@ impl Node {
       kind,
       name: None,
       visiblity: Visibility::Private,
+      exported: false,
       location: loc,
       children: vec![],
       value: None,
@@ -46,6 +57,12 @@ impl Node {
     self.children.get_mut(idx).unwrap()
   }
 
+  /// Append `child` to `parent`, linking it back to its new parent.
+  pub fn append(parent: &NodePtr, child: NodePtr) -> NodePtr {
+    *child.borrow_mut().parent_mut() = Some(Rc::downgrade(parent));
+    parent.borrow_mut().add_child(child).clone()
+  }
+
   pub fn get_child(&mut self, idx: usize) -> Option<&NodePtr> {
     self.children.get(idx)
   }
@@ -71,7 +88,7 @@ impl Node {
   }
 
   pub fn parent_kind(&self) -> Option<NodeKind> {
-    if let Some(p) = self.parent.clone() {
+    if let Some(p) = self.parent() {
       let k = *p.borrow().kind();
       return Some(k);
     }
@@ -105,7 +122,7 @@ impl Node {
 
   pub fn ancestors(&self) -> Vec<NodePtr> {
     let mut ret: Vec<NodePtr> = vec![];
-    match self.parent.clone() {
+    match self.parent() {
       Some(p) => {
         ret.push(p.clone());
         for ancestor in p.borrow().ancestors() {
@@ -159,18 +176,19 @@ impl Node {
       .map(|child| child.clone())
   }
 
-  pub fn parent(&self) -> &Option<NodePtr> {
-    &self.parent
+  /// The parent node, unless this is a root or the tree holding it was dropped.
+  pub fn parent(&self) -> Option<NodePtr> {
+    self.parent.as_ref().and_then(Weak::upgrade)
   }
 
-  pub fn parent_mut(&mut self) -> &mut Option<NodePtr> {
+  pub fn parent_mut(&mut self) -> &mut Option<WeakNodePtr> {
     &mut self.parent
   }
 
   pub fn root(&self) -> Option<NodePtr> {
-    let mut root = self.parent.clone();
+    let mut root = self.parent();
     while root.is_some() && root.as_ref().unwrap().borrow().parent().is_some() {
-      root = root.unwrap().borrow().parent().clone();
+      root = root.unwrap().borrow().parent();
     }
     root
   }
@@ -183,6 +201,22 @@ impl Node {
     &mut self.location
   }
 
+  pub fn visibility(&self) -> Visibility {
+    self.visiblity
+  }
+
+  pub fn visibility_mut(&mut self) -> &mut Visibility {
+    &mut self.visiblity
+  }
+
+  pub fn exported(&self) -> bool {
+    self.exported
+  }
+
+  pub fn exported_mut(&mut self) -> &mut bool {
+    &mut self.exported
+  }
+
   pub fn value(&self) -> &Option<Value> {
     &self.value
   }
@@ -192,6 +226,19 @@ impl Node {
   }
 }
 
+/// Nodes are compared by content, their subtree included but not their parent.
+impl PartialEq for Node {
+  fn eq(&self, other: &Self) -> bool {
+    self.kind == other.kind
+      && self.name == other.name
+      && self.location == other.location
+      && self.children == other.children
+      && self.visiblity == other.visiblity
+      && self.exported == other.exported
+      && self.value == other.value
+  }
+}
+
 impl Display for Node {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     write!(f, "{:?}", self.kind)?;
@@ -1,3 +1,7 @@
+#![allow(clippy::derivable_impls)]
+
+use super::Operator;
+
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
 pub enum NodeKind {
   Global,
@@ -5,20 +9,80 @@ pub enum NodeKind {
   FunctionParams,
   FunctionParam,
   FunctionImpl,
+  /// A function expression or an arrow function, named only if the source names it.
+  Lambda,
+  /// A class, an `Identifier` child naming its superclass if any.
   Class,
   Enum,
   Method,
+  /// An enum variant, its value resolved by the parser.
+  Variant,
+  /// A class field or an object literal entry, its child being the optional initializer.
+  Field,
+  Block,
+  Return,
+  If,
+  While,
+  For,
+  Break,
+  Continue,
+  /// `throw expr;`
+  Throw,
+  /// A protected block followed by a `Catch` and/or a `Finally` node.
+  Try,
+  /// A handler block, named after the variable bound to the thrown value if any.
+  Catch,
+  Finally,
 
+  Variable,
+  Constant,
   Assignment,
+  /// An assignment like `a += 1`, its second child being the binary operator applied,
+  /// holding only the right-hand operand.
+  CompoundAssignment,
 
   Add,
   Subtract,
   Multiply,
   Divide,
+  Modulo,
+  Equal,
+  NotEqual,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  InstanceOf,
+  And,
+  Or,
+  Negate,
+  Not,
 
+  /// `name(args...)` or, when unnamed, `callee(args...)`, the callee being the first child.
   Call,
+  /// `object.name`, the object being the only child.
+  Property,
+  /// `object.name(args...)`, the object being the first child.
+  Invoke,
+  /// `object[index]`, the object being the first child.
+  Index,
+  /// `new Class(args...)`, the class being the first child.
+  New,
+  This,
+  /// `super(args...)` or, when named, `super.name(args...)`.
+  Super,
+  Identifier,
   Litteral,
+  /// `[elements...]`
+  ArrayLitteral,
+  /// `{ key: value, ... }`, each entry being a `Field`.
   ObjectLitteral,
+  /// `import { a, b as c } from "path"`, valued with the path. Each child is an
+  /// `Identifier` named after the binding and valued with the imported name, or
+  /// unvalued for `import * as name`, which binds the whole namespace.
+  Import,
+  /// A statement that failed to parse, named after the error.
+  Error,
 
   None,
 }
@@ -28,3 +92,80 @@ impl Default for NodeKind {
     NodeKind::Global
   }
 }
+
+impl NodeKind {
+  /// The node kind built from `op` when used as a binary operator.
+  pub fn binary(op: Operator) -> Option<NodeKind> {
+    match op {
+      Operator::Plus => Some(NodeKind::Add),
+      Operator::Minus => Some(NodeKind::Subtract),
+      Operator::Star => Some(NodeKind::Multiply),
+      Operator::Slash => Some(NodeKind::Divide),
+      Operator::Percent => Some(NodeKind::Modulo),
+      Operator::Equal => Some(NodeKind::Equal),
+      Operator::NotEqual => Some(NodeKind::NotEqual),
+      Operator::Less => Some(NodeKind::Less),
+      Operator::LessEqual => Some(NodeKind::LessEqual),
+      Operator::Greater => Some(NodeKind::Greater),
+      Operator::GreaterEqual => Some(NodeKind::GreaterEqual),
+      Operator::And => Some(NodeKind::And),
+      Operator::Or => Some(NodeKind::Or),
+      _ => None,
+    }
+  }
+
+  /// The node kind built from `op` when used as a prefix operator.
+  pub fn unary(op: Operator) -> Option<NodeKind> {
+    match op {
+      Operator::Minus => Some(NodeKind::Negate),
+      Operator::Not => Some(NodeKind::Not),
+      _ => None,
+    }
+  }
+
+  /// The source operator of an operator node.
+  pub fn operator(&self) -> Option<Operator> {
+    match *self {
+      NodeKind::Add => Some(Operator::Plus),
+      NodeKind::Subtract | NodeKind::Negate => Some(Operator::Minus),
+      NodeKind::Multiply => Some(Operator::Star),
+      NodeKind::Divide => Some(Operator::Slash),
+      NodeKind::Modulo => Some(Operator::Percent),
+      NodeKind::Equal => Some(Operator::Equal),
+      NodeKind::NotEqual => Some(Operator::NotEqual),
+      NodeKind::Less => Some(Operator::Less),
+      NodeKind::LessEqual => Some(Operator::LessEqual),
+      NodeKind::Greater => Some(Operator::Greater),
+      NodeKind::GreaterEqual => Some(Operator::GreaterEqual),
+      NodeKind::And => Some(Operator::And),
+      NodeKind::Or => Some(Operator::Or),
+      NodeKind::Not => Some(Operator::Not),
+      _ => None,
+    }
+  }
+
+  /// Binding power of a binary operator node, higher binds tighter.
+  pub fn precedence(&self) -> u8 {
+    match *self {
+      NodeKind::Or => 1,
+      NodeKind::And => 2,
+      NodeKind::Equal | NodeKind::NotEqual => 3,
+      NodeKind::Less
+      | NodeKind::LessEqual
+      | NodeKind::Greater
+      | NodeKind::GreaterEqual
+      | NodeKind::InstanceOf => 4,
+      NodeKind::Add | NodeKind::Subtract => 5,
+      NodeKind::Multiply | NodeKind::Divide | NodeKind::Modulo => 6,
+      _ => 0,
+    }
+  }
+
+  pub fn is_binary(&self) -> bool {
+    self.precedence() > 0
+  }
+
+  pub fn is_unary(&self) -> bool {
+    matches!(*self, NodeKind::Negate | NodeKind::Not)
+  }
+}
\ No newline at end of file
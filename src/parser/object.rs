@@ -0,0 +1,216 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::compiler::{ClassProto, FunctionProto};
+use crate::environment::EnvPtr;
+use crate::vm::NativeFn;
+
+use super::{Value, Visibility};
+
+pub type InstancePtr = Rc<RefCell<Instance>>;
+
+/// A function value.
+pub enum Function {
+  /// A script function along with the environment it was created in, and the class
+  /// of the method creating it if any.
+  Closure {
+    proto: Rc<FunctionProto>,
+    env: EnvPtr,
+    class: Option<Rc<Class>>,
+  },
+  Native {
+    name: String,
+    f: Rc<NativeFn>,
+  },
+}
+
+impl Function {
+  pub fn name(&self) -> &String {
+    match self {
+      Function::Closure { proto, .. } => proto.name(),
+      Function::Native { name, .. } => name,
+    }
+  }
+
+  pub fn is_native(&self) -> bool {
+    matches!(self, Function::Native { .. })
+  }
+}
+
+/// Functions are compared by identity.
+impl PartialEq for Function {
+  fn eq(&self, other: &Self) -> bool {
+    std::ptr::eq(self, other)
+  }
+}
+
+impl std::fmt::Debug for Function {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Function({})", self.name())
+  }
+}
+
+/// A class created at run time from its compiled declaration.
+pub struct Class {
+  proto: Rc<ClassProto>,
+  superclass: Option<Rc<Class>>,
+  /// The environment the class was declared in, enclosing its methods.
+  env: EnvPtr,
+}
+
+impl Class {
+  pub fn new(proto: Rc<ClassProto>, superclass: Option<Rc<Class>>, env: EnvPtr) -> Class {
+    Class {
+      proto,
+      superclass,
+      env,
+    }
+  }
+
+  pub fn name(&self) -> &String {
+    self.proto.name()
+  }
+
+  pub fn proto(&self) -> &Rc<ClassProto> {
+    &self.proto
+  }
+
+  pub fn superclass(&self) -> Option<&Rc<Class>> {
+    self.superclass.as_ref()
+  }
+
+  pub fn env(&self) -> &EnvPtr {
+    &self.env
+  }
+
+  /// Look a method up along the superclass chain, along with the class defining it.
+  pub fn method<S: AsRef<str>>(
+    class: &Rc<Class>,
+    name: S,
+  ) -> Option<(Rc<Class>, Rc<FunctionProto>)> {
+    let mut class = class;
+    loop {
+      if let Some(m) = class.proto.method(name.as_ref()) {
+        return Some((class.clone(), m.clone()));
+      }
+      class = class.superclass.as_ref()?;
+    }
+  }
+
+  /// The class declaring the member `name` and its visibility, if any declares it.
+  pub fn member<S: AsRef<str>>(&self, name: S) -> Option<(&Class, Visibility)> {
+    let mut class = self;
+    loop {
+      if let Some(v) = class.proto.visibility(name.as_ref()) {
+        return Some((class, v));
+      }
+      class = class.superclass.as_deref()?;
+    }
+  }
+
+  /// Whether this class is `other` or one of its subclasses.
+  pub fn derives_from(&self, other: &Class) -> bool {
+    let mut class = self;
+    loop {
+      if std::ptr::eq(class, other) {
+        return true;
+      }
+      match class.superclass.as_deref() {
+        Some(s) => class = s,
+        None => return false,
+      }
+    }
+  }
+}
+
+/// Classes are compared by identity.
+impl PartialEq for Class {
+  fn eq(&self, other: &Self) -> bool {
+    std::ptr::eq(self, other)
+  }
+}
+
+impl std::fmt::Debug for Class {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Class({})", self.name())
+  }
+}
+
+/// An object created by `new`.
+pub struct Instance {
+  class: Rc<Class>,
+  fields: HashMap<String, Value>,
+}
+
+impl Instance {
+  pub fn new(class: Rc<Class>) -> Instance {
+    Instance {
+      class,
+      fields: HashMap::new(),
+    }
+  }
+
+  pub fn class(&self) -> &Rc<Class> {
+    &self.class
+  }
+
+  pub fn fields(&self) -> &HashMap<String, Value> {
+    &self.fields
+  }
+
+  pub fn fields_mut(&mut self) -> &mut HashMap<String, Value> {
+    &mut self.fields
+  }
+}
+
+/// Instances are compared by identity.
+impl PartialEq for Instance {
+  fn eq(&self, other: &Self) -> bool {
+    std::ptr::eq(self, other)
+  }
+}
+
+// fields may point back to the instance, so they are not shown
+impl std::fmt::Debug for Instance {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Instance({})", self.class.name())
+  }
+}
+
+/// An enumeration, its variants standing for integers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enum {
+  name: String,
+  variants: Vec<(String, i64)>,
+}
+
+impl Enum {
+  pub fn new(name: String, variants: Vec<(String, i64)>) -> Enum {
+    Enum { name, variants }
+  }
+
+  pub fn name(&self) -> &String {
+    &self.name
+  }
+
+  /// The variants in declaration order.
+  pub fn variants(&self) -> &Vec<(String, i64)> {
+    &self.variants
+  }
+
+  pub fn variant<S: AsRef<str>>(&self, name: S) -> Option<i64> {
+    self
+      .variants
+      .iter()
+      .find(|(variant, _)| variant == name.as_ref())
+      .map(|(_, v)| *v)
+  }
+
+  /// Reverse lookup: the first variant standing for `value`.
+  pub fn name_of(&self, value: i64) -> Option<&String> {
+    self
+      .variants
+      .iter()
+      .find(|(_, v)| *v == value)
+      .map(|(variant, _)| variant)
+  }
+}
@@ -1,49 +1,203 @@
 use std::fmt::Display;
 
-use super::{Value, Variable};
-
+use super::NodeKind;
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum OpCode {
+  // stack
+  Constant(usize),
+  Pop,
+  Dup,
+  /// Duplicate the two values on top of the stack, keeping their order.
+  Dup2,
+
   // variable
   DeclareVariable(String),
+  DeclareConstant(String),
+  InitVariable(String),
+  LoadVariable(String),
   AssignVariable(String),
 
-  // end of instruction
-  FinishStatement,
+  // scope
+  PushScope,
+  PopScope,
+
+  // operators
+  Add,
+  Subtract,
+  Multiply,
+  Divide,
+  Modulo,
+  Negate,
+  Not,
+  Equal,
+  NotEqual,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  InstanceOf,
+
+  // jumps
+  Jump(usize),
+  JumpIfFalse(usize),
+  JumpIfTrue(usize),
+
+  // exceptions
+  /// Catch what is thrown until the matching `LeaveTry`, resuming at the given offset.
+  EnterTry(usize),
+  LeaveTry,
+  Throw,
 
   // function decl
-  DeclareFunction(String),
-  StartFunctionParams,
-  AddFunctionParam(Variable),
-  EndFunctionParams,
-  StartFunctionImpl,
-  EndFunctionImpl,
-  SetReturnType(Variable),
+  DeclareFunction(usize),
+
+  /// Create a function value closing over the current environment.
+  Closure(usize),
 
   // function call
-  CallFunction(String, Vec<Variable>),
-  ReturnValue(Value),
+  CallFunction(String, usize),
+  /// Call the function value pushed before the arguments.
+  Call(usize),
+  ReturnValue,
+
+  // objects
+  Class(usize),
+  GetProperty(String),
+  SetProperty(String),
+  Invoke(String, usize),
+  /// Call a method of the superclass of the running method's class.
+  InvokeSuper(String, usize),
+  /// Call the superclass constructor with all the arguments of the running constructor.
+  ForwardSuper,
+  New(usize),
+  /// Collect the given number of elements into an array.
+  Array(usize),
+  /// Collect the given number of key and value pairs into an object.
+  Object(usize),
+  GetIndex,
+  SetIndex,
+
+  // modules
+  /// Run the module at the path held by the given constant unless it ran already,
+  /// pushing its namespace.
+  Import(usize),
+  /// Replace the namespace on top of the stack with the current value of the given export
+  /// of the module just imported.
+  ImportName(String),
+  /// Make the given top-level binding visible to the scripts importing this one.
+  Export(String),
 }
 
 impl Display for OpCode {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(
-      f,
-      "{}",
-      match self {
-        &Self::DeclareVariable(..) => "declare_variable",
-        &Self::AssignVariable(..) => "assign_variable",
-        &Self::FinishStatement => "finish_statement",
-        &Self::DeclareFunction(..) => "declare_function",
-        &Self::StartFunctionParams => "start_function_params",
-        &Self::AddFunctionParam(..) => "add_function_param",
-        &Self::SetReturnType(..) => "set_return_type",
-        &Self::EndFunctionParams => "",
-        &Self::StartFunctionImpl => "start_function_impl",
-        &Self::EndFunctionImpl => "end_function_impl",
-        &Self::CallFunction(..) => "call_function",
-        &Self::ReturnValue(..) => "return_value",
+    write!(f, "{}", self.mnemonic())?;
+    match self {
+      Self::Constant(idx)
+      | Self::DeclareFunction(idx)
+      | Self::Class(idx)
+      | Self::New(idx)
+      | Self::Array(idx)
+      | Self::Object(idx)
+      | Self::Closure(idx)
+      | Self::Call(idx)
+      | Self::Import(idx) => write!(f, " {}", idx),
+      Self::Jump(target)
+      | Self::JumpIfFalse(target)
+      | Self::JumpIfTrue(target)
+      | Self::EnterTry(target) => {
+        write!(f, " {}", target)
+      }
+      Self::DeclareVariable(name)
+      | Self::DeclareConstant(name)
+      | Self::InitVariable(name)
+      | Self::LoadVariable(name)
+      | Self::AssignVariable(name)
+      | Self::GetProperty(name)
+      | Self::SetProperty(name)
+      | Self::ImportName(name)
+      | Self::Export(name) => write!(f, " {}", name),
+      Self::CallFunction(name, argc) | Self::Invoke(name, argc) | Self::InvokeSuper(name, argc) => {
+        write!(f, " {} {}", name, argc)
       }
-    )
+      _ => Ok(()),
+    }
+  }
+}
+
+impl OpCode {
+  /// The name of the instruction, without its operands.
+  pub fn mnemonic(&self) -> &'static str {
+    match self {
+      Self::Constant(..) => "constant",
+      Self::Pop => "pop",
+      Self::Dup => "dup",
+      Self::Dup2 => "dup2",
+      Self::DeclareVariable(..) => "declare_variable",
+      Self::DeclareConstant(..) => "declare_constant",
+      Self::InitVariable(..) => "init_variable",
+      Self::LoadVariable(..) => "load_variable",
+      Self::AssignVariable(..) => "assign_variable",
+      Self::PushScope => "push_scope",
+      Self::PopScope => "pop_scope",
+      Self::Add => "add",
+      Self::Subtract => "subtract",
+      Self::Multiply => "multiply",
+      Self::Divide => "divide",
+      Self::Modulo => "modulo",
+      Self::Negate => "negate",
+      Self::Not => "not",
+      Self::Equal => "equal",
+      Self::NotEqual => "not_equal",
+      Self::Less => "less",
+      Self::LessEqual => "less_equal",
+      Self::Greater => "greater",
+      Self::GreaterEqual => "greater_equal",
+      Self::InstanceOf => "instance_of",
+      Self::Jump(..) => "jump",
+      Self::JumpIfFalse(..) => "jump_if_false",
+      Self::JumpIfTrue(..) => "jump_if_true",
+      Self::EnterTry(..) => "enter_try",
+      Self::LeaveTry => "leave_try",
+      Self::Throw => "throw",
+      Self::DeclareFunction(..) => "declare_function",
+      Self::Closure(..) => "closure",
+      Self::CallFunction(..) => "call_function",
+      Self::Call(..) => "call",
+      Self::ReturnValue => "return_value",
+      Self::Class(..) => "class",
+      Self::GetProperty(..) => "get_property",
+      Self::SetProperty(..) => "set_property",
+      Self::Invoke(..) => "invoke",
+      Self::InvokeSuper(..) => "invoke_super",
+      Self::ForwardSuper => "forward_super",
+      Self::New(..) => "new",
+      Self::Array(..) => "array",
+      Self::Object(..) => "object",
+      Self::GetIndex => "get_index",
+      Self::SetIndex => "set_index",
+      Self::Import(..) => "import",
+      Self::ImportName(..) => "import_name",
+      Self::Export(..) => "export",
+    }
+  }
+
+  /// The opcode applying a binary operator node.
+  pub fn binary(kind: NodeKind) -> Option<OpCode> {
+    match kind {
+      NodeKind::Add => Some(Self::Add),
+      NodeKind::Subtract => Some(Self::Subtract),
+      NodeKind::Multiply => Some(Self::Multiply),
+      NodeKind::Divide => Some(Self::Divide),
+      NodeKind::Modulo => Some(Self::Modulo),
+      NodeKind::Equal => Some(Self::Equal),
+      NodeKind::NotEqual => Some(Self::NotEqual),
+      NodeKind::Less => Some(Self::Less),
+      NodeKind::LessEqual => Some(Self::LessEqual),
+      NodeKind::Greater => Some(Self::Greater),
+      NodeKind::GreaterEqual => Some(Self::GreaterEqual),
+      NodeKind::InstanceOf => Some(Self::InstanceOf),
+      _ => None,
+    }
   }
 }
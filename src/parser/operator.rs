@@ -0,0 +1,90 @@
+use std::fmt::Display;
+
+use enum_iterator::IntoEnumIterator;
+
+#[derive(IntoEnumIterator, Copy, Clone, PartialEq, Debug)]
+pub enum Operator {
+  Plus,
+  Minus,
+  Star,
+  Slash,
+  Percent,
+  Assign,
+  PlusAssign,
+  MinusAssign,
+  StarAssign,
+  SlashAssign,
+  PercentAssign,
+  Equal,
+  NotEqual,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  And,
+  Or,
+  Not,
+  Arrow,
+}
+
+impl Display for Operator {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.repr())
+  }
+}
+
+impl Operator {
+  pub fn repr(&self) -> &'static str {
+    match *self {
+      Self::Plus => "+",
+      Self::Minus => "-",
+      Self::Star => "*",
+      Self::Slash => "/",
+      Self::Percent => "%",
+      Self::Assign => "=",
+      Self::PlusAssign => "+=",
+      Self::MinusAssign => "-=",
+      Self::StarAssign => "*=",
+      Self::SlashAssign => "/=",
+      Self::PercentAssign => "%=",
+      Self::Equal => "==",
+      Self::NotEqual => "!=",
+      Self::Less => "<",
+      Self::LessEqual => "<=",
+      Self::Greater => ">",
+      Self::GreaterEqual => ">=",
+      Self::And => "&&",
+      Self::Or => "||",
+      Self::Not => "!",
+      Self::Arrow => "=>",
+    }
+  }
+
+  pub fn parse<S: AsRef<str>>(s: S) -> Option<Operator> {
+    Self::into_enum_iter().find(|op| op.repr() == s.as_ref())
+  }
+
+  pub fn is_assignment(&self) -> bool {
+    *self == Self::Assign || self.compound().is_some()
+  }
+
+  /// The arithmetic operator applied by a compound assignment.
+  pub fn compound(&self) -> Option<Operator> {
+    match *self {
+      Self::PlusAssign => Some(Self::Plus),
+      Self::MinusAssign => Some(Self::Minus),
+      Self::StarAssign => Some(Self::Star),
+      Self::SlashAssign => Some(Self::Slash),
+      Self::PercentAssign => Some(Self::Percent),
+      _ => None,
+    }
+  }
+
+  /// Whether `ch` can start or continue an operator.
+  pub fn is_operator_char(ch: char) -> bool {
+    matches!(
+      ch,
+      '+' | '-' | '*' | '/' | '%' | '=' | '!' | '<' | '>' | '&' | '|'
+    )
+  }
+}
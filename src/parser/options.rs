@@ -1,3 +1,5 @@
+#![allow(clippy::match_like_matches_macro, clippy::needless_return, clippy::unnecessary_unwrap)]
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ParserOption {
   Debug,
@@ -2,20 +2,26 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::error::Error;
-use crate::location::Location;
+use crate::location::{Location, Span};
 use crate::result::Result;
 use crate::script::{Script, ScriptState};
 
-use super::{AST, Keyword, Node, NodeKind, NodePtr, ParserOption, Symbol, Value};
+use super::{
+  Keyword, Lexeme, Lexer, Node, NodeKind, NodePtr, Operator, ParserOption, Symbol, Token, Value,
+  Visibility, AST,
+};
 
 pub struct Parser {
   location: Location,
   root_scope: NodePtr,
   cur_scope: NodePtr,
-  accu: String,
-  quote: Option<char>,
+  lexemes: Vec<Lexeme>,
+  pos: usize,
   keywords: Vec<Keyword>,
   options: Vec<ParserOption>,
+  errors: Vec<Error>,
+  /// Where lexing errors left off, parse errors there being reported already.
+  lexed_errors: Vec<Location>,
 }
 
 impl Default for Parser {
@@ -25,10 +31,12 @@ impl Default for Parser {
       location: Default::default(),
       root_scope: root_scope.clone(),
       cur_scope: root_scope.clone(),
-      accu: Default::default(),
-      quote: Default::default(),
+      lexemes: Default::default(),
+      pos: 0,
       keywords: Default::default(),
       options: ParserOption::from_env(),
+      errors: vec![],
+      lexed_errors: vec![],
     }
   }
 }
@@ -80,6 +88,11 @@ impl Parser {
     &mut self.options
   }
 
+  /// Syntax errors met by the last `parse_partial`.
+  pub fn errors(&self) -> &Vec<Error> {
+    &self.errors
+  }
+
   pub fn reset(&mut self) {
     let options = self.options.clone();
     *self = Parser::default();
@@ -92,12 +105,26 @@ impl Parser {
   }
 
   pub fn cur_scope_kind(&self) -> NodeKind {
-    self.cur_scope.borrow().kind().clone()
+    *self.cur_scope.borrow().kind()
   }
 
+  /// Parse a whole script, failing with every syntax error it contains.
   pub fn parse(&mut self, s: &mut Script) -> Result<AST> {
+    let ast = self.parse_partial(s)?;
+    match self.errors.len() {
+      0 => Ok(ast),
+      1 => Err(self.errors.pop().unwrap()),
+      _ => Err(Error::Multiple(std::mem::take(&mut self.errors))),
+    }
+  }
+
+  /// Parse a script as far as possible, recovering from syntax errors.
+  ///
+  /// Statements that failed to parse are kept as `NodeKind::Error` nodes and their
+  /// errors are available from `errors()`.
+  pub fn parse_partial(&mut self, s: &mut Script) -> Result<AST> {
     self.reset();
-    s.content().ok_or_else(|| {
+    let content = s.content().ok_or_else(|| {
       Error::IO(std::io::Error::new(
         std::io::ErrorKind::InvalidData,
         "script has no content",
@@ -105,42 +132,25 @@ impl Parser {
     })?;
     *self.location.file_mut() = s.name().clone();
     *self.root_scope.borrow_mut().location_mut() = self.location.clone();
-    let ch_it = s.content().unwrap().chars();
-    #[allow(unused_assignments)]
-    let mut is_symbol = false;
-    for ch in ch_it {
-      if self.has_option(ParserOption::Debug) {
-        println!("parse: {}", ch);
-      }
-      if self.quote.is_some() && ch != Symbol::DoubleQuote.repr() && ch != Symbol::SingleQuote.repr() {
-        self.accu.push(ch);
-      } else {
-        is_symbol = false;
-        if let Some(sym) = Symbol::parse(ch) {
-          is_symbol = true;
-          match sym {
-            Symbol::LParent => self.parse_lparen(ch),
-            Symbol::RParent => self.parse_rparen(ch),
-            Symbol::LBrace => self.parse_lbrace(ch),
-            Symbol::RBrace => self.parse_rbrace(ch),
-            Symbol::LBracket => self.parse_lbracket(ch),
-            Symbol::RBracket => self.parse_rbracket(ch),
-            Symbol::DoubleQuote | Symbol::SingleQuote => self.parse_quote(ch),
-            Symbol::Comma => self.parse_comma(ch),
-            Symbol::SemiColon => self.parse_semicolon(ch),
-            Symbol::Tab | Symbol::Space => self.parse_space(ch),
-            Symbol::NewLine => self.parse_eol(ch),
-          }?;
-        }
-        if !is_symbol {
-          self.accu.push(ch);
-        }
-        self.parse_keyword();
+    let (lexemes, errors) = Lexer::new(s.name(), content).tokenize_partial();
+    for lexeme in lexemes {
+      match lexeme.token() {
+        Token::Error => self.lexed_errors.push(lexeme.span().end().clone()),
+        _ => self.lexemes.push(lexeme),
       }
-      *self.location.offset_mut() += 1;
-      *self.location.column_mut() += 1;
     }
-    *s.state_mut() = ScriptState::PARSED;
+    self.errors = errors;
+    while !self.is_eof() {
+      self.parse_statement_or_recover()?;
+    }
+    // lexing errors were collected first
+    self.errors.sort_by_key(|e| match e {
+      Error::Syntax(_, span) => (*span.start().line(), *span.start().column()),
+      _ => (0, 0),
+    });
+    if self.errors.is_empty() {
+      *s.state_mut() = ScriptState::PARSED;
+    }
     if self.has_option(ParserOption::Debug) {
       self.dump(self.root_scope.clone(), 0);
     }
@@ -149,210 +159,979 @@ impl Parser {
 
   pub fn dump(&self, node: NodePtr, indent: usize) {
     print!("{}", "\t".repeat(indent));
-    if *node.borrow().kind() == NodeKind::None {
-      println!(
-        "{} = {:?}",
-        node.borrow().name().clone().unwrap_or("".into()),
-        node.borrow().value()
-      );
-    } else {
-      println!(
-        "{:?}:{} {{",
-        node.borrow().kind(),
-        node.borrow().name().clone().unwrap_or("".into())
-      );
+    if node.borrow().children().is_empty() {
+      println!("{}", node.borrow());
+      return;
     }
+    println!(
+      "{:?}:{} {{",
+      node.borrow().kind(),
+      node.borrow().name().clone().unwrap_or_default()
+    );
     for child in node.borrow().children() {
       self.dump(child.clone(), indent + 1);
     }
-    if *node.borrow().kind() != NodeKind::None {
-      println!("{}}}", "\t".repeat(indent))
+    println!("{}}}", "\t".repeat(indent))
+  }
+
+  fn peek(&self) -> &Token {
+    self.peek_at(0)
+  }
+
+  fn peek_at(&self, n: usize) -> &Token {
+    self
+      .lexemes
+      .get(self.pos + n)
+      .map_or(&Token::Eof, |l| l.token())
+  }
+
+  fn peek_location(&self) -> Location {
+    self
+      .lexemes
+      .get(self.pos)
+      .map_or_else(|| self.location.clone(), |l| l.span().start().clone())
+  }
+
+  fn peek_span(&self) -> Span {
+    self
+      .lexemes
+      .get(self.pos)
+      .map_or_else(|| self.location.clone().into(), |l| l.span().clone())
+  }
+
+  /// Span of the last consumed lexeme.
+  fn last_span(&self) -> Span {
+    self
+      .pos
+      .checked_sub(1)
+      .and_then(|idx| self.lexemes.get(idx))
+      .map_or_else(|| self.location.clone().into(), |l| l.span().clone())
+  }
+
+  fn is_eof(&self) -> bool {
+    *self.peek() == Token::Eof
+  }
+
+  fn is_symbol(&self, sym: Symbol) -> bool {
+    *self.peek() == Token::Symbol(sym)
+  }
+
+  /// Consume the next lexeme, moving `location` to its start.
+  fn advance(&mut self) -> Lexeme {
+    let lexeme = self.lexemes.get(self.pos).cloned().unwrap_or_else(|| {
+      Lexeme::new(
+        Token::Eof,
+        Span::new(self.location.clone(), self.location.clone()),
+      )
+    });
+    if self.pos < self.lexemes.len() {
+      self.pos += 1;
+    }
+    self.location = lexeme.span().start().clone();
+    if self.has_option(ParserOption::Debug) {
+      println!("parse: {:?}", lexeme.token());
+    }
+    lexeme
+  }
+
+  fn eat_symbol(&mut self, sym: Symbol) -> bool {
+    if self.is_symbol(sym) {
+      self.advance();
+      return true;
     }
+    false
   }
 
-  fn parse_keyword(&mut self) -> Option<Keyword> {
-    if self.accu.len() > 0 {
-      if self.has_option(ParserOption::Debug) {
-        println!("parse kw: {:?}", self.accu);
+  fn expect_symbol(&mut self, sym: Symbol) -> Result<Lexeme> {
+    if self.is_symbol(sym) {
+      return Ok(self.advance());
+    }
+    Err(self.unexpected(format!("'{}'", sym.repr())))
+  }
+
+  fn expect_identifier(&mut self) -> Result<String> {
+    if let Token::Identifier(id) = self.peek().clone() {
+      self.advance();
+      return Ok(id);
+    }
+    Err(self.unexpected("identifier"))
+  }
+
+  fn unexpected<S: AsRef<str>>(&self, expected: S) -> Error {
+    Error::Syntax(
+      format!("expected {} but found '{}'", expected.as_ref(), self.peek()),
+      self.peek_span(),
+    )
+  }
+
+  fn new_node(&self, kind: NodeKind, loc: Location) -> NodePtr {
+    Rc::new(RefCell::new(Node::new(kind, loc)))
+  }
+
+  /// Parse a statement, replacing it with an error node if it is invalid.
+  fn parse_statement_or_recover(&mut self) -> Result<()> {
+    let scope = self.cur_scope.clone();
+    let start = self.pos;
+    match self.parse_statement() {
+      Err(e @ Error::Syntax(..)) => {
+        if let Error::Syntax(msg, span) = &e {
+          let node = self.new_node(NodeKind::Error, span.start().clone());
+          *node.borrow_mut().name_mut() = Some(msg.clone());
+          Node::append(&scope, node);
+        }
+        self.cur_scope = scope;
+        self.keywords.clear();
+        if !self.follows_lexed_error(&e) {
+          self.errors.push(e);
+        }
+        self.synchronize(start);
+        Ok(())
       }
-      if let Some(kw) = Keyword::parse(&self.accu) {
-        match kw {
-          Keyword::Function => {
-            self.push_scope(NodeKind::Function);
-          }
-          Keyword::Class => {
-            self.push_scope(NodeKind::Class);
-          }
-          Keyword::Enum => {
-            self.push_scope(NodeKind::Enum);
+      ret => ret,
+    }
+  }
+
+  /// Whether `e` is reported at the lexeme right after input the lexer couldn't read,
+  /// and so likely follows from it.
+  fn follows_lexed_error(&self, e: &Error) -> bool {
+    let Error::Syntax(_, span) = e else {
+      return false;
+    };
+    self.lexed_errors.iter().any(|end| {
+      let next = self
+        .lexemes
+        .iter()
+        .find(|l| l.span().start().offset() >= end.offset())
+        .map_or(&self.location, |l| l.span().start());
+      next == span.start()
+    })
+  }
+
+  /// Skip to the end of the statement that failed, after a `;` or before the `}`
+  /// closing the enclosing block, stepping over any nested block.
+  fn synchronize(&mut self, start: usize) {
+    // leave the blocks the statement opened before failing, e.g. a class body
+    let mut depth = self.lexemes[start..self.pos]
+      .iter()
+      .fold(0, |depth, lexeme| match lexeme.token() {
+        Token::Symbol(Symbol::LBrace) => depth + 1,
+        Token::Symbol(Symbol::RBrace) => depth - 1,
+        _ => depth,
+      })
+      .max(0);
+    while !self.is_eof() {
+      if self.is_symbol(Symbol::RBrace) && depth == 0 {
+        break;
+      }
+      let lexeme = self.advance();
+      match lexeme.token() {
+        Token::Symbol(Symbol::SemiColon) if depth == 0 => break,
+        Token::Symbol(Symbol::LBrace) => depth += 1,
+        Token::Symbol(Symbol::RBrace) => {
+          depth -= 1;
+          if depth == 0 {
+            break;
           }
-          Keyword::Private => {}
-          Keyword::Protected => {}
-          Keyword::Public => {}
-          Keyword::Return => {}
-          Keyword::Throw => {}
-          Keyword::Let => {}
-          Keyword::Const => {}
-        };
-        self.keywords.push(kw);
-        self.accu.clear();
-        return Some(kw);
+        }
+        _ => {}
+      }
+    }
+    // always make progress, even on a stray '}'
+    if self.pos == start && !self.is_eof() {
+      self.advance();
+    }
+  }
+
+  fn parse_statement(&mut self) -> Result<()> {
+    match self.peek().clone() {
+      Token::Keyword(kw) if !kw.starts_expression() => self.parse_keyword(kw),
+      Token::Symbol(Symbol::LBrace) => self.parse_block(NodeKind::Block),
+      Token::Symbol(Symbol::SemiColon) => {
+        self.advance();
+        Ok(())
+      }
+      _ => {
+        let expr = self.parse_expr()?;
+        self.expect_symbol(Symbol::SemiColon)?;
+        Node::append(&self.cur_scope, expr);
+        self.keywords.clear();
+        Ok(())
       }
     }
-    None
   }
 
+  fn parse_keyword(&mut self, kw: Keyword) -> Result<()> {
+    if self.has_option(ParserOption::Debug) {
+      println!("parse kw: {:?}", kw);
+    }
+    self.advance();
+    self.keywords.push(kw);
+    match kw {
+      Keyword::Function => self.parse_function(),
+      Keyword::Class => self.parse_class(),
+      Keyword::Enum => self.parse_enum(),
+      Keyword::Return => self.parse_return(),
+      Keyword::Let => self.parse_declaration(NodeKind::Variable),
+      Keyword::Const => self.parse_declaration(NodeKind::Constant),
+      Keyword::Private | Keyword::Protected | Keyword::Public => Err(Error::Syntax(
+        format!("'{}' can only be applied to class members", kw),
+        self.last_span(),
+      )),
+      Keyword::Throw => self.parse_throw(),
+      Keyword::Import => self.parse_import(),
+      Keyword::Export => self.parse_export(),
+      Keyword::Try => self.parse_try(),
+      Keyword::If => self.parse_if(),
+      Keyword::While => self.parse_while(),
+      Keyword::For => self.parse_for(),
+      Keyword::Break => self.parse_loop_jump(NodeKind::Break),
+      Keyword::Continue => self.parse_loop_jump(NodeKind::Continue),
+      Keyword::True
+      | Keyword::False
+      | Keyword::Null
+      | Keyword::Undefined
+      | Keyword::New
+      | Keyword::This
+      | Keyword::Super
+      | Keyword::Extends
+      | Keyword::InstanceOf
+      | Keyword::Catch
+      | Keyword::Finally
+      | Keyword::Else => Err(Error::Syntax(
+        format!("unexpected '{}'", kw),
+        self.last_span(),
+      )),
+    }
+  }
+
+  fn parse_function(&mut self) -> Result<()> {
+    let name = self.expect_identifier()?;
+    self.parse_callable(NodeKind::Function, Some(name))?;
+    Ok(())
+  }
+
+  /// Parse the parameters and body of a function or method named `name`.
+  fn parse_callable(&mut self, kind: NodeKind, name: Option<String>) -> Result<NodePtr> {
+    let node = self.push_scope(kind);
+    *self.cur_scope.borrow_mut().name_mut() = name;
+    self.parse_params()?;
+    self.parse_block(NodeKind::FunctionImpl)?;
+    self.pop_scope()?;
+    Ok(node)
+  }
 
-  fn parse_lparen(&mut self, _ch: char) -> Result<()> {
-    if *self.cur_scope.borrow().kind() == NodeKind::Function {
-      // parse function declaration
-      if self.accu.len() > 0 {
-        // register function name if given
-        *self.cur_scope.borrow_mut().name_mut() = Some(self.accu.clone());
-        self.accu.clear();
+  /// `(params...)`
+  fn parse_params(&mut self) -> Result<()> {
+    self.expect_symbol(Symbol::LParent)?;
+    self.push_scope(NodeKind::FunctionParams);
+    while !self.is_symbol(Symbol::RParent) {
+      self.parse_param()?;
+      if !self.eat_symbol(Symbol::Comma) && !self.is_symbol(Symbol::RParent) {
+        return Err(self.unexpected("',' or ')'"));
       }
-      // check first param decl
-      if self
-        .cur_scope
-        .borrow()
-        .children()
-        .iter()
-        .find(|ch| *ch.borrow().kind() == NodeKind::FunctionParams)
-        .is_some()
-      {
-        return Err(Error::Syntax(
-          "unexpected '('".into(),
-          self.location.clone(),
-        ));
+    }
+    self.advance();
+    self.pop_scope()?;
+    Ok(())
+  }
+
+  fn parse_param(&mut self) -> Result<()> {
+    let loc = self.peek_location();
+    let param = self.expect_identifier()?;
+    let node = self.new_node(NodeKind::FunctionParam, loc);
+    *node.borrow_mut().name_mut() = Some(param);
+    Node::append(&self.cur_scope, node);
+    Ok(())
+  }
+
+  /// `function [name](params...) { ... }` used as a value.
+  fn parse_function_expr(&mut self) -> Result<NodePtr> {
+    self.advance();
+    let name = match self.peek().clone() {
+      Token::Identifier(name) => {
+        self.advance();
+        Some(name)
       }
+      _ => None,
+    };
+    let node = self.parse_callable(NodeKind::Lambda, name)?;
+    self.detach(&node);
+    Ok(node)
+  }
+
+  /// `(params...) => body` or `param => body`, the body being a block or an expression.
+  fn parse_arrow(&mut self) -> Result<NodePtr> {
+    let loc = self.peek_location();
+    let node = self.push_scope(NodeKind::Lambda);
+    *node.borrow_mut().location_mut() = loc;
+    if self.is_symbol(Symbol::LParent) {
+      self.parse_params()?;
+    } else {
       self.push_scope(NodeKind::FunctionParams);
+      self.parse_param()?;
+      self.pop_scope()?;
+    }
+    if *self.peek() != Token::Operator(Operator::Arrow) {
+      return Err(self.unexpected("'=>'"));
+    }
+    self.advance();
+    if self.is_symbol(Symbol::LBrace) {
+      self.parse_block(NodeKind::FunctionImpl)?;
     } else {
-      // parse function call
-      self.accu = self.accu.trim().to_string();
-      if self.accu.is_empty() {
-        return Err(Error::Syntax(
-          "unexpected '('".into(),
-          self.location.clone()
-        ));
+      let body = self.push_scope(NodeKind::FunctionImpl);
+      let ret = self.new_node(NodeKind::Return, self.peek_location());
+      let expr = self.parse_expr()?;
+      Node::append(&ret, expr);
+      Node::append(&body, ret);
+      self.pop_scope()?;
+    }
+    self.pop_scope()?;
+    self.detach(&node);
+    Ok(node)
+  }
+
+  /// Whether the parenthesis ahead opens the parameters of an arrow function.
+  fn is_arrow(&self) -> bool {
+    let mut depth = 0;
+    for (n, lexeme) in self.lexemes[self.pos..].iter().enumerate() {
+      match lexeme.token() {
+        Token::Symbol(Symbol::LParent) => depth += 1,
+        Token::Symbol(Symbol::RParent) => {
+          depth -= 1;
+          if depth == 0 {
+            return *self.peek_at(n + 1) == Token::Operator(Operator::Arrow);
+          }
+        }
+        _ => {}
       }
-      let func = self.push_scope(NodeKind::Call);
-      *func.borrow_mut().name_mut() = Some(self.accu.clone());
-      self.accu.clear();
     }
-    Ok(())
+    false
   }
 
-  fn parse_rparen(&mut self, _ch: char) -> Result<()> {
-    if *self.cur_scope.borrow().kind() == NodeKind::FunctionParams {
-      if self.accu.trim().len() != 0 {
-        self.push_fn_param()?;
+  /// Remove an expression parsed as a scope from the statements of its enclosing scope.
+  fn detach(&mut self, node: &NodePtr) {
+    self
+      .cur_scope
+      .borrow_mut()
+      .children_mut()
+      .retain(|child| !Rc::ptr_eq(child, node));
+  }
+
+  /// `class Name [extends Base] { [visibility] field = init; [visibility] method(params) { ... } }`
+  ///
+  /// Members without a visibility modifier are public.
+  fn parse_class(&mut self) -> Result<()> {
+    self.push_scope(NodeKind::Class);
+    let name = self.expect_identifier()?;
+    *self.cur_scope.borrow_mut().name_mut() = Some(name);
+    if *self.peek() == Token::Keyword(Keyword::Extends) {
+      self.advance();
+      let loc = self.peek_location();
+      let base = self.expect_identifier()?;
+      let node = self.new_node(NodeKind::Identifier, loc);
+      *node.borrow_mut().name_mut() = Some(base);
+      Node::append(&self.cur_scope, node);
+    }
+    self.expect_symbol(Symbol::LBrace)?;
+    while !self.eat_symbol(Symbol::RBrace) {
+      let visibility = match self.peek() {
+        Token::Keyword(kw) => Visibility::from_keyword(*kw),
+        _ => None,
+      };
+      if visibility.is_some() {
+        self.advance();
       }
-    } else if self.cur_scope_kind() == NodeKind::Call {
-      self.accu = self.accu.trim().to_string();
-      if !self.accu.is_empty() {
-        let param = self.cur_scope.borrow_mut().create_child(NodeKind::FunctionParam, self.location.clone()).clone();
-        *param.borrow_mut().value_mut() = Some(Value::String(self.accu.clone()));
-        self.accu.clear();
+      let visibility = visibility.unwrap_or(Visibility::Public);
+      let loc = self.peek_location();
+      let member = self.expect_identifier()?;
+      if self.is_symbol(Symbol::LParent) {
+        let method = self.parse_callable(NodeKind::Method, Some(member))?;
+        *method.borrow_mut().visibility_mut() = visibility;
+        continue;
       }
+      let field = self.new_node(NodeKind::Field, loc);
+      *field.borrow_mut().name_mut() = Some(member);
+      *field.borrow_mut().visibility_mut() = visibility;
+      if *self.peek() == Token::Operator(Operator::Assign) {
+        self.advance();
+        let init = self.parse_expr()?;
+        Node::append(&field, init);
+      }
+      self.expect_symbol(Symbol::SemiColon)?;
+      Node::append(&self.cur_scope, field);
     }
+    self.keywords.clear();
     self.pop_scope()?;
     Ok(())
   }
 
-  fn parse_lbrace(&mut self, _ch: char) -> Result<()> {
-    let mut kind = NodeKind::None;
-    match self.cur_scope_kind() {
-      NodeKind::Function => {
-        kind = NodeKind::FunctionImpl;
+  /// `import { a, b as c } from "path";` or `import * as ns from "path";`
+  fn parse_import(&mut self) -> Result<()> {
+    let span = self.last_span();
+    let node = self.new_node(NodeKind::Import, self.location.clone());
+    if *self.peek() == Token::Operator(Operator::Star) {
+      self.advance();
+      self.expect_word("as")?;
+      let loc = self.peek_location();
+      let binding = self.new_node(NodeKind::Identifier, loc);
+      *binding.borrow_mut().name_mut() = Some(self.expect_identifier()?);
+      Node::append(&node, binding);
+    } else {
+      self.expect_symbol(Symbol::LBrace)?;
+      while !self.eat_symbol(Symbol::RBrace) {
+        let loc = self.peek_location();
+        let name = self.expect_identifier()?;
+        let alias = match self.peek() {
+          Token::Identifier(id) if id == "as" => {
+            self.advance();
+            self.expect_identifier()?
+          }
+          _ => name.clone(),
+        };
+        let binding = self.new_node(NodeKind::Identifier, loc);
+        *binding.borrow_mut().name_mut() = Some(alias);
+        *binding.borrow_mut().value_mut() = Some(Value::String(name));
+        Node::append(&node, binding);
+        if !self.eat_symbol(Symbol::Comma) && !self.is_symbol(Symbol::RBrace) {
+          return Err(self.unexpected("',' or '}'"));
+        }
       }
-      _ => {}
     }
+    self.expect_word("from")?;
+    let path = match self.peek().clone() {
+      Token::String(path) => path,
+      _ => return Err(self.unexpected("module path")),
+    };
+    self.advance();
+    self.expect_symbol(Symbol::SemiColon)?;
     self.keywords.clear();
-    self.push_scope(kind);
+    self.expect_top_level(Keyword::Import, span)?;
+    *node.borrow_mut().value_mut() = Some(Value::String(path));
+    Node::append(&self.cur_scope, node);
     Ok(())
   }
 
-  fn parse_rbrace(&mut self, _ch: char) -> Result<()> {
-    if !self.accu.is_empty() {
-      self.parse_expr()?;
+  /// `export` before a top-level declaration, exporting it from the module.
+  fn parse_export(&mut self) -> Result<()> {
+    let span = self.last_span();
+    match self.peek().clone() {
+      Token::Keyword(
+        kw @ (Keyword::Function | Keyword::Class | Keyword::Enum | Keyword::Let | Keyword::Const),
+      ) => self.parse_keyword(kw)?,
+      _ => return Err(self.unexpected("declaration")),
     }
-    if self.cur_scope_kind() == NodeKind::FunctionImpl {
-      // pop 2 scopes: FunctionImpl and Function
-      self.pop_scope()?;
+    self.expect_top_level(Keyword::Export, span)?;
+    if let Some(decl) = self.cur_scope.borrow().children().last() {
+      *decl.borrow_mut().exported_mut() = true;
+    }
+    Ok(())
+  }
+
+  /// Fail unless the statement introduced by `kw` at `span` is at the top level.
+  fn expect_top_level(&self, kw: Keyword, span: Span) -> Result<()> {
+    if self.cur_scope_kind() != NodeKind::Global {
+      return Err(Error::Syntax(
+        format!("'{}' is only allowed at the top level of a script", kw),
+        span,
+      ));
     }
-    self.pop_scope()?;
     Ok(())
   }
 
-  fn accu_empty(&self) -> bool {
-    self.accu.trim().len() == 0
+  /// Expect the identifier `word`, which is a keyword only where it is expected.
+  fn expect_word(&mut self, word: &str) -> Result<()> {
+    match self.peek() {
+      Token::Identifier(id) if id == word => {
+        self.advance();
+        Ok(())
+      }
+      _ => Err(self.unexpected(format!("'{}'", word))),
+    }
   }
 
-  fn parse_comma(&mut self, _ch: char) -> Result<()> {
-    if *self.cur_scope.borrow().kind() == NodeKind::FunctionParams {
-      if self.accu.trim().len() == 0 {
+  /// `enum Name { A, B = 5, C }`, variants without a value following the previous one.
+  fn parse_enum(&mut self) -> Result<()> {
+    self.push_scope(NodeKind::Enum);
+    let name = self.expect_identifier()?;
+    *self.cur_scope.borrow_mut().name_mut() = Some(name.clone());
+    self.expect_symbol(Symbol::LBrace)?;
+    let mut next = 0;
+    while !self.eat_symbol(Symbol::RBrace) {
+      let loc = self.peek_location();
+      let variant = self.expect_identifier()?;
+      if self.cur_scope.borrow().child_by_name(&variant).is_some() {
         return Err(Error::Syntax(
-          "unexpected ','".into(),
-          self.location.clone(),
+          format!("duplicate variant '{}' in {}", variant, name),
+          self.last_span(),
         ));
       }
-      self.push_fn_param()?;
-    } else if self.cur_scope_kind() == NodeKind::Call {
-      let param = self.cur_scope.borrow_mut().create_child(NodeKind::FunctionParam, self.location.clone()).clone();
-      *param.borrow_mut().value_mut() = Some(Value::String(self.accu.clone()));
-      self.accu.clear();
+      if *self.peek() == Token::Operator(Operator::Assign) {
+        self.advance();
+        let negative = *self.peek() == Token::Operator(Operator::Minus);
+        if negative {
+          self.advance();
+        }
+        next = match self.peek() {
+          Token::Integer(i) if negative => -i,
+          Token::Integer(i) => *i,
+          _ => return Err(self.unexpected("integer")),
+        };
+        self.advance();
+      }
+      let node = self.new_node(NodeKind::Variant, loc);
+      *node.borrow_mut().name_mut() = Some(variant);
+      *node.borrow_mut().value_mut() = Some(Value::Integer(next));
+      Node::append(&self.cur_scope, node);
+      next = next.wrapping_add(1);
+      if !self.eat_symbol(Symbol::Comma) && !self.is_symbol(Symbol::RBrace) {
+        return Err(self.unexpected("',' or '}'"));
+      }
+    }
+    self.keywords.clear();
+    self.pop_scope()?;
+    Ok(())
+  }
+
+  fn parse_block(&mut self, kind: NodeKind) -> Result<()> {
+    self.expect_symbol(Symbol::LBrace)?;
+    self.keywords.clear();
+    self.push_scope(kind);
+    while !self.is_symbol(Symbol::RBrace) {
+      if self.is_eof() {
+        return Err(self.unexpected("'}'"));
+      }
+      self.parse_statement_or_recover()?;
+    }
+    self.advance();
+    self.pop_scope()?;
+    Ok(())
+  }
+
+  /// Parse `(expr)` into the current scope.
+  fn parse_condition(&mut self) -> Result<()> {
+    self.expect_symbol(Symbol::LParent)?;
+    let cond = self.parse_expr()?;
+    self.expect_symbol(Symbol::RParent)?;
+    Node::append(&self.cur_scope, cond);
+    Ok(())
+  }
+
+  fn parse_if(&mut self) -> Result<()> {
+    self.push_scope(NodeKind::If);
+    self.parse_condition()?;
+    self.parse_statement()?;
+    if *self.peek() == Token::Keyword(Keyword::Else) {
+      self.advance();
+      self.parse_statement()?;
+    }
+    self.pop_scope()?;
+    Ok(())
+  }
+
+  fn parse_while(&mut self) -> Result<()> {
+    self.push_scope(NodeKind::While);
+    self.parse_condition()?;
+    self.parse_statement()?;
+    self.pop_scope()?;
+    Ok(())
+  }
+
+  /// `for (init; cond; update) body`, absent clauses being kept as `NodeKind::None` nodes.
+  fn parse_for(&mut self) -> Result<()> {
+    self.push_scope(NodeKind::For);
+    self.expect_symbol(Symbol::LParent)?;
+    match self.peek().clone() {
+      Token::Keyword(kw @ (Keyword::Let | Keyword::Const)) => self.parse_keyword(kw)?,
+      Token::Symbol(Symbol::SemiColon) => {
+        self.advance();
+        self.push_empty();
+      }
+      _ => {
+        let init = self.parse_expr()?;
+        self.expect_symbol(Symbol::SemiColon)?;
+        Node::append(&self.cur_scope, init);
+      }
+    }
+    if self.is_symbol(Symbol::SemiColon) {
+      self.push_empty();
     } else {
+      let cond = self.parse_expr()?;
+      Node::append(&self.cur_scope, cond);
+    }
+    self.expect_symbol(Symbol::SemiColon)?;
+    if self.is_symbol(Symbol::RParent) {
+      self.push_empty();
+    } else {
+      let update = self.parse_expr()?;
+      Node::append(&self.cur_scope, update);
+    }
+    self.expect_symbol(Symbol::RParent)?;
+    self.parse_statement()?;
+    self.pop_scope()?;
+    Ok(())
+  }
+
+  fn push_empty(&mut self) {
+    let empty = self.new_node(NodeKind::None, self.location.clone());
+    Node::append(&self.cur_scope, empty);
+  }
+
+  /// Parse `break;` or `continue;`, which must appear in a loop of the current function.
+  fn parse_loop_jump(&mut self, kind: NodeKind) -> Result<()> {
+    let mut scope = Some(self.cur_scope.clone());
+    let mut in_loop = false;
+    while let Some(s) = scope {
+      let k = *s.borrow().kind();
+      if k == NodeKind::While || k == NodeKind::For {
+        in_loop = true;
+        break;
+      }
+      if k == NodeKind::FunctionImpl {
+        break;
+      }
+      scope = s.borrow().parent().clone();
+    }
+    if !in_loop {
       return Err(Error::Syntax(
-        "unexpected ','".into(),
-        self.location.clone(),
+        format!("'{}' outside of loop", self.keywords.last().unwrap()),
+        self.last_span(),
       ));
     }
+    let node = self.new_node(kind, self.location.clone());
+    self.expect_symbol(Symbol::SemiColon)?;
+    Node::append(&self.cur_scope, node);
+    self.keywords.clear();
     Ok(())
   }
 
-  fn parse_semicolon(&mut self, _ch: char) -> Result<()> {
-    if !self.accu_empty() {
-      self.parse_expr()?;
+  fn parse_return(&mut self) -> Result<()> {
+    let in_function = self.cur_scope_kind() == NodeKind::FunctionImpl
+      || self
+        .cur_scope
+        .borrow()
+        .ancestor_by_kind(NodeKind::FunctionImpl)
+        .is_some();
+    if !in_function {
+      return Err(Error::Syntax(
+        "'return' outside of function".into(),
+        self.last_span(),
+      ));
     }
-    if self.cur_scope_kind() == NodeKind::Function && self.cur_scope().borrow().child_by_kind(NodeKind::FunctionImpl).is_none() {
-      *self.cur_scope.borrow_mut().kind_mut() = NodeKind::Call;
+    let node = self.new_node(NodeKind::Return, self.location.clone());
+    if !self.is_symbol(Symbol::SemiColon) {
+      let expr = self.parse_expr()?;
+      Node::append(&node, expr);
     }
+    self.expect_symbol(Symbol::SemiColon)?;
+    Node::append(&self.cur_scope, node);
+    self.keywords.clear();
+    Ok(())
+  }
+
+  fn parse_throw(&mut self) -> Result<()> {
+    let node = self.new_node(NodeKind::Throw, self.location.clone());
+    let expr = self.parse_expr()?;
+    Node::append(&node, expr);
+    self.expect_symbol(Symbol::SemiColon)?;
+    Node::append(&self.cur_scope, node);
     self.keywords.clear();
     Ok(())
   }
 
-  fn parse_quote(&mut self, ch: char) -> Result<()> {
-    if self.quote == Some(ch) {
-      self.quote = None;
-    } else if self.quote == None {
-      self.quote = Some(ch);
+  /// `try { ... } catch (e) { ... } finally { ... }`, either handler being optional.
+  fn parse_try(&mut self) -> Result<()> {
+    self.push_scope(NodeKind::Try);
+    self.parse_block(NodeKind::Block)?;
+    let mut handled = false;
+    if *self.peek() == Token::Keyword(Keyword::Catch) {
+      self.advance();
+      self.push_scope(NodeKind::Catch);
+      if self.eat_symbol(Symbol::LParent) {
+        let name = self.expect_identifier()?;
+        *self.cur_scope.borrow_mut().name_mut() = Some(name);
+        self.expect_symbol(Symbol::RParent)?;
+      }
+      self.parse_block(NodeKind::Block)?;
+      self.pop_scope()?;
+      handled = true;
+    }
+    if *self.peek() == Token::Keyword(Keyword::Finally) {
+      self.advance();
+      self.push_scope(NodeKind::Finally);
+      self.parse_block(NodeKind::Block)?;
+      self.pop_scope()?;
+      handled = true;
+    }
+    if !handled {
+      return Err(self.unexpected("'catch' or 'finally'"));
     }
+    self.pop_scope()?;
     Ok(())
   }
 
-  fn parse_space(&mut self, _ch: char) -> Result<()> {
-    // if self.parse_keyword().is_none() {
-      // self.accu.push(_ch);
-    // }
+  fn parse_declaration(&mut self, kind: NodeKind) -> Result<()> {
+    let node = self.new_node(kind, self.location.clone());
+    let name = self.expect_identifier()?;
+    *node.borrow_mut().name_mut() = Some(name);
+    if *self.peek() == Token::Operator(Operator::Assign) {
+      self.advance();
+      let init = self.parse_expr()?;
+      Node::append(&node, init);
+    } else if kind == NodeKind::Constant {
+      return Err(self.unexpected("'='"));
+    }
+    self.expect_symbol(Symbol::SemiColon)?;
+    Node::append(&self.cur_scope, node);
+    self.keywords.clear();
     Ok(())
   }
 
-  fn parse_eol(&mut self, ch: char) -> Result<()> {
-    if self.quote != None {
-      self.accu.push(ch);
+  pub fn parse_expr(&mut self) -> Result<NodePtr> {
+    self.parse_assignment()
+  }
+
+  /// Assignments are right associative.
+  fn parse_assignment(&mut self) -> Result<NodePtr> {
+    let target = self.parse_binary(1)?;
+    let op = match self.peek() {
+      Token::Operator(op) if op.is_assignment() => *op,
+      _ => return Ok(target),
+    };
+    if !matches!(
+      *target.borrow().kind(),
+      NodeKind::Identifier | NodeKind::Property | NodeKind::Index
+    ) {
+      return Err(Error::Syntax(
+        "invalid assignment target".into(),
+        target.borrow().location().clone().into(),
+      ));
+    }
+    self.advance();
+    let loc = self.location.clone();
+    let mut value = self.parse_assignment()?;
+    let node = match op.compound().and_then(NodeKind::binary) {
+      Some(kind) => {
+        let binary = self.new_node(kind, loc.clone());
+        Node::append(&binary, value);
+        value = binary;
+        self.new_node(NodeKind::CompoundAssignment, loc)
+      }
+      None => self.new_node(NodeKind::Assignment, loc),
+    };
+    Node::append(&node, target);
+    Node::append(&node, value);
+    Ok(node)
+  }
+
+  /// Precedence climbing: parse operators binding at least as tight as `min_prec`.
+  fn parse_binary(&mut self, min_prec: u8) -> Result<NodePtr> {
+    let mut lhs = self.parse_unary()?;
+    loop {
+      let kind = match self.peek() {
+        Token::Operator(op) => NodeKind::binary(*op),
+        Token::Keyword(Keyword::InstanceOf) => Some(NodeKind::InstanceOf),
+        _ => None,
+      };
+      let kind = match kind {
+        Some(k) if k.precedence() >= min_prec => k,
+        _ => break,
+      };
+      self.advance();
+      let node = self.new_node(kind, self.location.clone());
+      // all binary operators are left associative
+      let rhs = self.parse_binary(kind.precedence() + 1)?;
+      Node::append(&node, lhs);
+      Node::append(&node, rhs);
+      lhs = node;
+    }
+    Ok(lhs)
+  }
+
+  fn parse_unary(&mut self) -> Result<NodePtr> {
+    if let Token::Operator(op) = self.peek() {
+      if let Some(kind) = NodeKind::unary(*op) {
+        self.advance();
+        let node = self.new_node(kind, self.location.clone());
+        let operand = self.parse_unary()?;
+        Node::append(&node, operand);
+        return Ok(node);
+      }
     }
-    *self.location.line_mut() += 1;
-    *self.location.column_mut() = 0;
+    self.parse_primary()
+  }
+
+  /// An atom followed by any number of `.name`, `.name(args)`, `[index]` and `(args)` accesses.
+  fn parse_primary(&mut self) -> Result<NodePtr> {
+    let mut node = self.parse_atom()?;
+    loop {
+      if self.is_symbol(Symbol::LParent) {
+        let call = self.new_node(NodeKind::Call, self.peek_location());
+        Node::append(&call, node);
+        self.parse_args(&call)?;
+        node = call;
+        continue;
+      }
+      if self.eat_symbol(Symbol::LBracket) {
+        let access = self.new_node(NodeKind::Index, self.location.clone());
+        Node::append(&access, node);
+        let index = self.parse_expr()?;
+        Node::append(&access, index);
+        self.expect_symbol(Symbol::RBracket)?;
+        node = access;
+        continue;
+      }
+      if !self.eat_symbol(Symbol::Dot) {
+        break;
+      }
+      let name = self.expect_identifier()?;
+      let kind = if self.is_symbol(Symbol::LParent) {
+        NodeKind::Invoke
+      } else {
+        NodeKind::Property
+      };
+      let access = self.new_node(kind, self.location.clone());
+      *access.borrow_mut().name_mut() = Some(name);
+      Node::append(&access, node);
+      if kind == NodeKind::Invoke {
+        self.parse_args(&access)?;
+      }
+      node = access;
+    }
+    Ok(node)
+  }
+
+  fn parse_atom(&mut self) -> Result<NodePtr> {
+    let value = match self.peek().clone() {
+      Token::Integer(i) => Value::Integer(i),
+      Token::Double(d) => Value::Double(d),
+      Token::String(s) => Value::String(s),
+      Token::Keyword(Keyword::True) => Value::Boolean(true),
+      Token::Keyword(Keyword::False) => Value::Boolean(false),
+      Token::Keyword(Keyword::Null) | Token::Keyword(Keyword::Undefined) => Value::None,
+      Token::Identifier(id) => {
+        match self.peek_at(1) {
+          Token::Symbol(Symbol::LParent) => return self.parse_call(),
+          Token::Operator(Operator::Arrow) => return self.parse_arrow(),
+          _ => {}
+        }
+        self.advance();
+        let node = self.new_node(NodeKind::Identifier, self.location.clone());
+        *node.borrow_mut().name_mut() = Some(id);
+        return Ok(node);
+      }
+      Token::Keyword(Keyword::This) => {
+        self.advance();
+        return Ok(self.new_node(NodeKind::This, self.location.clone()));
+      }
+      Token::Keyword(Keyword::New) => return self.parse_new(),
+      Token::Keyword(Keyword::Super) => return self.parse_super(),
+      Token::Symbol(Symbol::LBracket) => return self.parse_array(),
+      Token::Symbol(Symbol::LBrace) => return self.parse_object(),
+      Token::Keyword(Keyword::Function) => return self.parse_function_expr(),
+      Token::Symbol(Symbol::LParent) if self.is_arrow() => return self.parse_arrow(),
+      Token::Symbol(Symbol::LParent) => {
+        self.advance();
+        let expr = self.parse_expr()?;
+        self.expect_symbol(Symbol::RParent)?;
+        return Ok(expr);
+      }
+      _ => return Err(self.unexpected("expression")),
+    };
+    self.advance();
+    let node = self.new_node(NodeKind::Litteral, self.location.clone());
+    *node.borrow_mut().value_mut() = Some(value);
+    Ok(node)
+  }
+
+  /// `[elements...]`, a trailing comma being allowed.
+  fn parse_array(&mut self) -> Result<NodePtr> {
+    self.advance();
+    let node = self.new_node(NodeKind::ArrayLitteral, self.location.clone());
+    while !self.is_symbol(Symbol::RBracket) {
+      let element = self.parse_expr()?;
+      Node::append(&node, element);
+      if !self.eat_symbol(Symbol::Comma) && !self.is_symbol(Symbol::RBracket) {
+        return Err(self.unexpected("',' or ']'"));
+      }
+    }
+    self.advance();
+    Ok(node)
+  }
+
+  /// `{ key: value, "key": value }`, a trailing comma being allowed.
+  fn parse_object(&mut self) -> Result<NodePtr> {
+    self.advance();
+    let node = self.new_node(NodeKind::ObjectLitteral, self.location.clone());
+    while !self.is_symbol(Symbol::RBrace) {
+      let key = match self.peek().clone() {
+        Token::Identifier(key) | Token::String(key) => key,
+        _ => return Err(self.unexpected("property name")),
+      };
+      self.advance();
+      let entry = self.new_node(NodeKind::Field, self.location.clone());
+      *entry.borrow_mut().name_mut() = Some(key);
+      self.expect_symbol(Symbol::Colon)?;
+      let value = self.parse_expr()?;
+      Node::append(&entry, value);
+      Node::append(&node, entry);
+      if !self.eat_symbol(Symbol::Comma) && !self.is_symbol(Symbol::RBrace) {
+        return Err(self.unexpected("',' or '}'"));
+      }
+    }
+    self.advance();
+    Ok(node)
+  }
+
+  fn parse_call(&mut self) -> Result<NodePtr> {
+    let name = self.expect_identifier()?;
+    let node = self.new_node(NodeKind::Call, self.location.clone());
+    *node.borrow_mut().name_mut() = Some(name);
+    self.parse_args(&node)?;
+    Ok(node)
+  }
+
+  /// `new Name(args...)`
+  fn parse_new(&mut self) -> Result<NodePtr> {
+    self.advance();
+    let node = self.new_node(NodeKind::New, self.location.clone());
+    let loc = self.peek_location();
+    let name = self.expect_identifier()?;
+    let class = self.new_node(NodeKind::Identifier, loc);
+    *class.borrow_mut().name_mut() = Some(name);
+    Node::append(&node, class);
+    self.parse_args(&node)?;
+    Ok(node)
+  }
+
+  /// `super(args...)` or `super.name(args...)`
+  fn parse_super(&mut self) -> Result<NodePtr> {
+    self.advance();
+    let node = self.new_node(NodeKind::Super, self.location.clone());
+    if self.eat_symbol(Symbol::Dot) {
+      let name = self.expect_identifier()?;
+      *node.borrow_mut().name_mut() = Some(name);
+    }
+    self.parse_args(&node)?;
+    Ok(node)
+  }
+
+  /// Parse `(args...)`, appending each argument to `node`.
+  fn parse_args(&mut self, node: &NodePtr) -> Result<()> {
+    self.expect_symbol(Symbol::LParent)?;
+    while !self.is_symbol(Symbol::RParent) {
+      let arg = self.parse_expr()?;
+      Node::append(node, arg);
+      if !self.eat_symbol(Symbol::Comma) && !self.is_symbol(Symbol::RParent) {
+        return Err(self.unexpected("',' or ')'"));
+      }
+    }
+    self.advance();
     Ok(())
   }
 
   fn push_scope(&mut self, kind: NodeKind) -> NodePtr {
     let last_scope = self.cur_scope.clone();
-    self.cur_scope = last_scope
-      .borrow_mut()
-      .create_child(kind, self.location.clone())
-      .clone();
-    *self.cur_scope.borrow_mut().parent_mut() = Some(last_scope.clone());
+    self.cur_scope = Node::append(&last_scope, self.new_node(kind, self.location.clone()));
     if self.has_option(ParserOption::Debug) {
       println!(
         "push_scope: {:?} -> {:?}",
@@ -367,14 +1146,8 @@ impl Parser {
     if self.cur_scope.borrow().parent().is_none() {
       return Err(Error::Unknown("no active scope".into(), Some(self.location.clone())));
     }
-    if self.accu.trim().len() > 0 {
-      return Err(Error::Syntax(
-        format!("unprocessed expression: {}", self.accu),
-        self.location.clone(),
-      ));
-    }
     let parent = self.cur_scope.borrow().parent().clone().unwrap();
-    let last_kind = self.cur_scope.borrow().kind().clone();
+    let last_kind = *self.cur_scope.borrow().kind();
     self.cur_scope = parent;
     if self.has_option(ParserOption::Debug) {
       println!(
@@ -385,29 +1158,6 @@ impl Parser {
     }
     Ok(self.cur_scope.clone())
   }
-
-  fn push_fn_param(&mut self) -> Result<()> {
-    *self
-      .cur_scope
-      .borrow_mut()
-      .create_child(NodeKind::FunctionParam, self.location.clone())
-      .borrow_mut()
-      .name_mut() = Some(self.accu.clone());
-    self.accu.clear();
-    Ok(())
-  }
-
-  fn parse_expr(&mut self) -> Result<()> {
-    Ok(())
-  }
-
-  fn parse_lbracket(&self, _ch: char) -> Result<()> {
-    Ok(())
-  }
-
-  fn parse_rbracket(&self, _ch: char) -> Result<()> {
-    Ok(())
-  }
 }
 
 #[cfg(test)]
@@ -417,6 +1167,7 @@ mod tests {
   use super::*;
 
   #[test]
+  #[allow(clippy::get_first, clippy::len_zero)]
   fn function_parsing_works() {
     let mut script = Script::new(
       PathBuf::from("virtual://test"),
@@ -488,4 +1239,219 @@ mod tests {
     let func_impl = func.children().get(1);
     assert_ne!(func_impl, None);
   }
+
+  fn parse_source(src: &str) -> AST {
+    let mut script = Script::new(PathBuf::from("virtual://test"), Some("test"), Some(src));
+    Parser::default().parse(&mut script).unwrap()
+  }
+
+  /// Render an expression tree as a fully parenthesized string.
+  fn render(node: &NodePtr) -> String {
+    let node = node.borrow();
+    match node.kind() {
+      NodeKind::Litteral => format!("{}", node.value().clone().unwrap()),
+      NodeKind::Identifier => node.name().clone().unwrap(),
+      NodeKind::Call => format!(
+        "{}({})",
+        node.name().clone().unwrap(),
+        node.children().iter().map(render).collect::<Vec<_>>().join(", ")
+      ),
+      k if k.is_unary() => format!("({}{})", k.operator().unwrap(), render(&node.children()[0])),
+      k => format!(
+        "({} {} {})",
+        render(&node.children()[0]),
+        k.operator().unwrap(),
+        render(&node.children()[1])
+      ),
+    }
+  }
+
+  #[test]
+  fn trees_are_freed_once_dropped() {
+    let mut script = Script::new(
+      PathBuf::from("virtual://test"),
+      Some("test"),
+      Some("function f(a) { return a + 1; }"),
+    );
+    let mut p = Parser::default();
+    let ast = p.parse(&mut script).unwrap();
+    let func = Rc::downgrade(ast.root().borrow().children().first().unwrap());
+    let parent = func.upgrade().unwrap().borrow().parent().unwrap();
+    assert!(Rc::ptr_eq(&parent, ast.root()));
+    drop(parent);
+    drop(ast);
+    drop(p);
+    assert!(func.upgrade().is_none());
+  }
+
+  #[test]
+  fn expression_precedence_works() {
+    let ast = parse_source(
+      "1 + 2 * 3;\n\
+       (1 + 2) * 3 % 4;\n\
+       -1 - -2 - 3;\n\
+       1 < 2 == 3 >= 4 != 5;\n\
+       !a || b && c;\n\
+       f(1 + 2, g());",
+    );
+    let rendered: Vec<String> = ast.root().borrow().children().iter().map(render).collect();
+    assert_eq!(
+      rendered,
+      vec![
+        "(1 + (2 * 3))",
+        "(((1 + 2) * 3) % 4)",
+        "(((-1) - (-2)) - 3)",
+        "(((1 < 2) == (3 >= 4)) != 5)",
+        "((!a) || (b && c))",
+        "f((1 + 2), g())",
+      ]
+    );
+  }
+
+  #[test]
+  fn litterals_are_typed() {
+    let ast = parse_source("f(42, 4.5, \"s\", true, false, null, undefined, a);");
+    let call = ast.root().borrow().children()[0].clone();
+    let args: Vec<(NodeKind, Option<Value>)> = call
+      .borrow()
+      .children()
+      .iter()
+      .map(|n| (*n.borrow().kind(), n.borrow().value().clone()))
+      .collect();
+    assert_eq!(
+      args,
+      vec![
+        (NodeKind::Litteral, Some(Value::Integer(42))),
+        (NodeKind::Litteral, Some(Value::Double(4.5))),
+        (NodeKind::Litteral, Some(Value::String("s".into()))),
+        (NodeKind::Litteral, Some(Value::Boolean(true))),
+        (NodeKind::Litteral, Some(Value::Boolean(false))),
+        (NodeKind::Litteral, Some(Value::None)),
+        (NodeKind::Litteral, Some(Value::None)),
+        (NodeKind::Identifier, None),
+      ]
+    );
+  }
+
+  #[test]
+  fn expression_errors_are_located() {
+    let mut script = Script::new(PathBuf::from("virtual://test"), Some("test"), Some("1 +\n * 2;"));
+    match Parser::default().parse(&mut script) {
+      Err(Error::Syntax(msg, span)) => {
+        assert_eq!(msg, "expected expression but found '*'");
+        assert_eq!((*span.start().line(), *span.start().column()), (2, 2));
+        assert_eq!(*span.end().column(), 3);
+      }
+      _ => panic!("expected a syntax error"),
+    }
+  }
+
+  #[test]
+  fn loop_jumps_must_be_in_loops() {
+    let mut script = Script::new(
+      PathBuf::from("virtual://test"),
+      Some("test"),
+      Some("while (true) {\n  function f() {\n    break;\n  }\n}"),
+    );
+    match Parser::default().parse(&mut script) {
+      Err(Error::Syntax(msg, span)) => {
+        assert_eq!(msg, "'break' outside of loop");
+        assert_eq!(*span.start().line(), 3);
+      }
+      _ => panic!("expected a syntax error"),
+    }
+  }
+
+  #[test]
+  fn syntax_errors_are_recovered() {
+    let mut script = Script::new(
+      PathBuf::from("virtual://test"),
+      Some("test"),
+      Some("let = 1;\nlet ok = 2;\nfunction f() {\n  return +;\n  ok;\n}\nout(ok #);\n}"),
+    );
+    let mut p = Parser::default();
+    let ast = p.parse_partial(&mut script).unwrap();
+    let lines: Vec<u64> = p
+      .errors()
+      .iter()
+      .map(|e| match e {
+        Error::Syntax(_, span) => *span.start().line(),
+        e => panic!("unexpected error {:?}", e),
+      })
+      .collect();
+    assert_eq!(lines, vec![1, 4, 7, 8]);
+    let kinds: Vec<NodeKind> = ast
+      .root()
+      .borrow()
+      .children()
+      .iter()
+      .map(|c| *c.borrow().kind())
+      .collect();
+    assert_eq!(
+      kinds,
+      vec![
+        NodeKind::Error,
+        NodeKind::Variable,
+        NodeKind::Function,
+        NodeKind::Call,
+        NodeKind::Error
+      ]
+    );
+    let body = ast.root().borrow().children()[2]
+      .borrow()
+      .child_by_kind(NodeKind::FunctionImpl)
+      .unwrap();
+    assert_eq!(body.borrow().children().len(), 2);
+    assert_eq!(*script.state(), ScriptState::LOADED);
+
+    match Parser::default().parse(&mut script) {
+      Err(Error::Multiple(errors)) => assert_eq!(errors.len(), 4),
+      _ => panic!("expected every syntax error"),
+    }
+  }
+
+  #[test]
+  fn lexing_errors_are_reported_once() {
+    let mut script = Script::new(
+      PathBuf::from("virtual://test"),
+      Some("test"),
+      Some("let a = 1 @ 2;\nlet b = #;\nlet ok = 3;\nlet s = \"oops"),
+    );
+    let mut p = Parser::default();
+    let ast = p.parse_partial(&mut script).unwrap();
+    let errors: Vec<(u64, String)> = p
+      .errors()
+      .iter()
+      .map(|e| match e {
+        Error::Syntax(msg, span) => (*span.start().line(), msg.clone()),
+        e => panic!("unexpected error {:?}", e),
+      })
+      .collect();
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert_eq!(
+      errors.iter().map(|e| e.0).collect::<Vec<_>>(),
+      vec![1, 2, 4]
+    );
+    assert_eq!(errors[2].1, "unterminated string");
+    let ok = ast.root().borrow().children()[2].clone();
+    assert_eq!(*ok.borrow().kind(), NodeKind::Variable);
+  }
+
+  #[test]
+  fn exports_are_flagged() {
+    let mut script = Script::new(
+      PathBuf::from("virtual://test"),
+      Some("test"),
+      Some("export class A { x = 1; }\nlet b = 2;"),
+    );
+    let ast = Parser::default().parse(&mut script).unwrap();
+    let decls = ast.root().borrow().children().clone();
+    assert!(decls[0].borrow().exported());
+    assert!(!decls[1].borrow().exported());
+    // exporting doesn't touch the declaration's visibility
+    assert_eq!(decls[0].borrow().visibility(), Visibility::Private);
+    let mut unexported = decls[0].borrow().clone();
+    *unexported.exported_mut() = false;
+    assert_ne!(unexported, *decls[0].borrow());
+  }
 }
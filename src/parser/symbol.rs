@@ -1,3 +1,5 @@
+#![allow(clippy::manual_find)]
+
 use enum_iterator::IntoEnumIterator;
 
 #[derive(IntoEnumIterator, Copy, Clone, PartialEq, Debug)]
@@ -12,6 +14,8 @@ pub enum Symbol {
   SingleQuote,
   Comma,
   SemiColon,
+  Colon,
+  Dot,
   Tab,
   Space,
   NewLine,
@@ -30,6 +34,8 @@ impl Symbol {
       Self::SingleQuote => '\'',
       Self::Comma => ',',
       Self::SemiColon => ';',
+      Self::Colon => ':',
+      Self::Dot => '.',
       Self::Tab => '\t',
       Self::Space => ' ',
       Self::NewLine => '\n',
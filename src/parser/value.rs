@@ -1,28 +1,344 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+  cell::RefCell,
+  cmp::Ordering,
+  collections::HashMap,
+  fmt::{Debug, Display},
+  rc::Rc,
+};
 
-#[derive(Debug, Clone, PartialEq)]
+use crate::{error::Error, result::Result};
+
+use super::{Class, Enum, Function, InstancePtr};
+
+pub type ArrayPtr = Rc<RefCell<Vec<Value>>>;
+pub type ObjectPtr = Rc<RefCell<HashMap<String, Value>>>;
+
+/// Arrays and objects are shared: copies of the value alias the same elements.
+///
+/// `PartialEq` compares their contents, while scripts compare them by identity. As they
+/// may contain themselves, `Debug` shows them as `[...]` or `{...}` when met again, and
+/// `PartialEq` takes pairs being compared already for equal.
+#[derive(Clone)]
 pub enum Value {
   String(String),
-  Object(HashMap<String, Value>),
-  Array(Vec<Value>),
+  Object(ObjectPtr),
+  Array(ArrayPtr),
   Integer(i64),
   Double(f64),
   Boolean(bool),
-  Function(),
+  Function(Rc<Function>),
+  Class(Rc<Class>),
+  Instance(InstancePtr),
+  Enum(Rc<Enum>),
   None,
 }
 
 impl Display for Value {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{}", match self {
+    write!(f, "{}", self.render(&mut vec![]))
+  }
+}
+
+impl Debug for Value {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    self.debug(f, &mut vec![])
+  }
+}
+
+impl PartialEq for Value {
+  fn eq(&self, other: &Self) -> bool {
+    self.equals(other, &mut vec![])
+  }
+}
+
+impl Value {
+  pub fn array(elements: Vec<Value>) -> Value {
+    Value::Array(Rc::new(RefCell::new(elements)))
+  }
+
+  pub fn object(entries: HashMap<String, Value>) -> Value {
+    Value::Object(Rc::new(RefCell::new(entries)))
+  }
+
+  /// Render the value, `seen` holding the arrays and objects being rendered so that
+  /// cycles are shown as `[...]` or `{...}`.
+  fn render(&self, seen: &mut Vec<*const ()>) -> String {
+    match self {
       Self::String(s) => format!("\"{}\"", s),
-      Self::Object(m) => format!("\"{:?}\"", m),
-      Self::Array(v) => format!("{:?}", v),
+      Self::Object(o) => {
+        let ptr = Rc::as_ptr(o) as *const ();
+        if seen.contains(&ptr) {
+          return "{...}".to_string();
+        }
+        seen.push(ptr);
+        let o = o.borrow();
+        // keys are sorted so that the output is stable
+        let mut keys: Vec<&String> = o.keys().collect();
+        keys.sort();
+        let entries: Vec<String> =
+          keys.iter().map(|k| format!("{}: {}", k, o[*k].render(seen))).collect();
+        seen.pop();
+        format!("{{{}}}", entries.join(", "))
+      }
+      Self::Array(a) => {
+        let ptr = Rc::as_ptr(a) as *const ();
+        if seen.contains(&ptr) {
+          return "[...]".to_string();
+        }
+        seen.push(ptr);
+        let elements: Vec<String> = a.borrow().iter().map(|v| v.render(seen)).collect();
+        seen.pop();
+        format!("[{}]", elements.join(", "))
+      }
       Self::Integer(i) => format!("{}", i),
       Self::Double(d) => format!("{}", d),
       Self::Boolean(b) => format!("{}", b),
-      Self::Function() => format!("fn () {{}}", ),
-      Self::None => format!("{}", "none")
-    })
+      Self::Function(f) if f.is_native() => format!("<native function {}>", f.name()),
+      Self::Function(f) => format!("<function {}>", f.name()),
+      Self::Class(c) => format!("<class {}>", c.name()),
+      Self::Instance(i) => format!("<{} instance>", i.borrow().class().name()),
+      Self::Enum(e) => format!("<enum {}>", e.name()),
+      Self::None => "none".to_string(),
+    }
+  }
+
+  /// Write the value for `Debug`, `seen` holding the arrays and objects being written.
+  fn debug(&self, f: &mut std::fmt::Formatter<'_>, seen: &mut Vec<*const ()>) -> std::fmt::Result {
+    match self {
+      Self::Object(o) => {
+        let ptr = Rc::as_ptr(o) as *const ();
+        if seen.contains(&ptr) {
+          return write!(f, "{{...}}");
+        }
+        seen.push(ptr);
+        let o = o.borrow();
+        let mut keys: Vec<&String> = o.keys().collect();
+        keys.sort();
+        write!(f, "Object({{")?;
+        for (idx, key) in keys.iter().enumerate() {
+          if idx > 0 {
+            write!(f, ", ")?;
+          }
+          write!(f, "{:?}: ", key)?;
+          o[*key].debug(f, seen)?;
+        }
+        seen.pop();
+        write!(f, "}})")
+      }
+      Self::Array(a) => {
+        let ptr = Rc::as_ptr(a) as *const ();
+        if seen.contains(&ptr) {
+          return write!(f, "[...]");
+        }
+        seen.push(ptr);
+        write!(f, "Array([")?;
+        for (idx, v) in a.borrow().iter().enumerate() {
+          if idx > 0 {
+            write!(f, ", ")?;
+          }
+          v.debug(f, seen)?;
+        }
+        seen.pop();
+        write!(f, "])")
+      }
+      Self::String(s) => write!(f, "String({:?})", s),
+      Self::Integer(i) => write!(f, "Integer({:?})", i),
+      Self::Double(d) => write!(f, "Double({:?})", d),
+      Self::Boolean(b) => write!(f, "Boolean({:?})", b),
+      Self::Function(func) => write!(f, "Function({:?})", func),
+      Self::Class(c) => write!(f, "Class({:?})", c),
+      Self::Instance(i) => write!(f, "Instance({:?})", i),
+      Self::Enum(e) => write!(f, "Enum({:?})", e),
+      Self::None => write!(f, "None"),
+    }
+  }
+
+  /// Compare the contents of values, `seen` holding the pairs of arrays and objects being
+  /// compared, which are equal unless something else differs.
+  fn equals(&self, other: &Value, seen: &mut Vec<(*const (), *const ())>) -> bool {
+    match (self, other) {
+      (Self::Object(a), Self::Object(b)) => {
+        let pair = (Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ());
+        if seen.contains(&pair) {
+          return true;
+        }
+        seen.push(pair);
+        let (a, b) = (a.borrow(), b.borrow());
+        let eq = a.len() == b.len()
+          && a
+            .iter()
+            .all(|(k, v)| b.get(k).is_some_and(|w| v.equals(w, seen)));
+        seen.pop();
+        eq
+      }
+      (Self::Array(a), Self::Array(b)) => {
+        let pair = (Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ());
+        if seen.contains(&pair) {
+          return true;
+        }
+        seen.push(pair);
+        let (a, b) = (a.borrow(), b.borrow());
+        let eq = a.len() == b.len() && a.iter().zip(b.iter()).all(|(v, w)| v.equals(w, seen));
+        seen.pop();
+        eq
+      }
+      (Self::String(a), Self::String(b)) => a == b,
+      (Self::Integer(a), Self::Integer(b)) => a == b,
+      (Self::Double(a), Self::Double(b)) => a == b,
+      (Self::Boolean(a), Self::Boolean(b)) => a == b,
+      (Self::Function(a), Self::Function(b)) => a == b,
+      (Self::Class(a), Self::Class(b)) => a == b,
+      (Self::Instance(a), Self::Instance(b)) => a == b,
+      (Self::Enum(a), Self::Enum(b)) => a == b,
+      (Self::None, Self::None) => true,
+      _ => false,
+    }
+  }
+
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Self::String(_) => "string",
+      Self::Object(_) => "object",
+      Self::Array(_) => "array",
+      Self::Integer(_) => "integer",
+      Self::Double(_) => "double",
+      Self::Boolean(_) => "boolean",
+      Self::Function(_) => "function",
+      Self::Class(_) => "class",
+      Self::Instance(_) => "instance",
+      Self::Enum(_) => "enum",
+      Self::None => "none",
+    }
+  }
+
+  /// JS-like truthiness: `none`, `false`, zero, NaN and the empty string are falsy.
+  pub fn is_truthy(&self) -> bool {
+    match self {
+      Self::None => false,
+      Self::Boolean(b) => *b,
+      Self::Integer(i) => *i != 0,
+      Self::Double(d) => *d != 0.0 && !d.is_nan(),
+      Self::String(s) => !s.is_empty(),
+      Self::Object(_)
+      | Self::Array(_)
+      | Self::Function(_)
+      | Self::Class(_)
+      | Self::Instance(_)
+      | Self::Enum(_) => true,
+    }
+  }
+
+  pub fn as_double(&self) -> Option<f64> {
+    match *self {
+      Self::Integer(i) => Some(i as f64),
+      Self::Double(d) => Some(d),
+      _ => None,
+    }
+  }
+
+  fn unsupported(op: &str, lhs: &Value, rhs: &Value) -> Error {
+    Error::Runtime(
+      format!(
+        "unsupported operand types for '{}': {} and {}",
+        op,
+        lhs.type_name(),
+        rhs.type_name()
+      ),
+      None,
+    )
+  }
+
+  /// Apply an arithmetic operator, integers overflowing into doubles.
+  fn arithmetic(
+    &self,
+    rhs: &Value,
+    op: &str,
+    int_op: fn(i64, i64) -> Option<i64>,
+    double_op: fn(f64, f64) -> f64,
+  ) -> Result<Value> {
+    match (self, rhs) {
+      (Self::Integer(a), Self::Integer(b)) => Ok(
+        int_op(*a, *b).map_or_else(|| Self::Double(double_op(*a as f64, *b as f64)), Self::Integer),
+      ),
+      _ => match (self.as_double(), rhs.as_double()) {
+        (Some(a), Some(b)) => Ok(Self::Double(double_op(a, b))),
+        _ => Err(Self::unsupported(op, self, rhs)),
+      },
+    }
+  }
+
+  pub fn add(&self, rhs: &Value) -> Result<Value> {
+    match (self, rhs) {
+      (Self::String(a), b) => Ok(Self::String(format!("{}{}", a, b.to_display_string()))),
+      (a, Self::String(b)) => Ok(Self::String(format!("{}{}", a.to_display_string(), b))),
+      _ => self.arithmetic(rhs, "+", i64::checked_add, |a, b| a + b),
+    }
+  }
+
+  pub fn sub(&self, rhs: &Value) -> Result<Value> {
+    self.arithmetic(rhs, "-", i64::checked_sub, |a, b| a - b)
+  }
+
+  pub fn mul(&self, rhs: &Value) -> Result<Value> {
+    self.arithmetic(rhs, "*", i64::checked_mul, |a, b| a * b)
+  }
+
+  pub fn div(&self, rhs: &Value) -> Result<Value> {
+    match (self, rhs) {
+      (Self::Integer(_), Self::Integer(0)) => Err(Error::Runtime("division by zero".into(), None)),
+      (Self::Integer(a), Self::Integer(b)) if a % b != 0 => Ok(Self::Double(*a as f64 / *b as f64)),
+      _ => self.arithmetic(rhs, "/", i64::checked_div, |a, b| a / b),
+    }
+  }
+
+  pub fn rem(&self, rhs: &Value) -> Result<Value> {
+    match (self, rhs) {
+      (Self::Integer(_), Self::Integer(0)) => Err(Error::Runtime("division by zero".into(), None)),
+      _ => self.arithmetic(rhs, "%", i64::checked_rem, |a, b| a % b),
+    }
+  }
+
+  pub fn neg(&self) -> Result<Value> {
+    match self {
+      Self::Integer(i) => Ok(i.checked_neg().map_or(Self::Double(-(*i as f64)), Self::Integer)),
+      Self::Double(d) => Ok(Self::Double(-d)),
+      _ => Err(Error::Runtime(
+        format!("unsupported operand type for '-': {}", self.type_name()),
+        None,
+      )),
+    }
+  }
+
+  /// Equality as seen by scripts: numbers compare by value whatever their representation,
+  /// arrays and objects by identity.
+  pub fn loose_eq(&self, rhs: &Value) -> bool {
+    match (self, rhs) {
+      (Self::Array(a), Self::Array(b)) => return Rc::ptr_eq(a, b),
+      (Self::Object(a), Self::Object(b)) => return Rc::ptr_eq(a, b),
+      _ => {}
+    }
+    match (self.as_double(), rhs.as_double()) {
+      (Some(a), Some(b)) => a == b,
+      _ => self == rhs,
+    }
+  }
+
+  /// Order two values, `None` meaning they are unordered (NaN).
+  pub fn compare(&self, rhs: &Value, op: &str) -> Result<Option<Ordering>> {
+    match (self, rhs) {
+      (Self::String(a), Self::String(b)) => Ok(Some(a.cmp(b))),
+      _ => match (self.as_double(), rhs.as_double()) {
+        (Some(a), Some(b)) => Ok(a.partial_cmp(&b)),
+        _ => Err(Self::unsupported(op, self, rhs)),
+      },
+    }
+  }
+
+  /// The text printed for this value, strings being shown without quotes.
+  pub fn to_display_string(&self) -> String {
+    match self {
+      Self::String(s) => s.clone(),
+      v => v.to_string(),
+    }
   }
 }
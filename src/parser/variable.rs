@@ -1,13 +1,64 @@
 use super::Value;
 
-#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Variable {
   name: String,
   value: Value,
+  constant: bool,
+  initialized: bool,
 }
 
 impl Variable {
   pub fn new(name: String, value: Value) -> Self {
-    Self { name, value }
+    Self {
+      name,
+      value,
+      constant: false,
+      initialized: true,
+    }
+  }
+
+  pub fn constant(name: String, value: Value) -> Self {
+    Self {
+      name,
+      value,
+      constant: true,
+      initialized: true,
+    }
+  }
+
+  /// A hoisted declaration, unreadable until its statement runs.
+  pub fn uninitialized(name: String, constant: bool) -> Self {
+    Self {
+      name,
+      value: Value::None,
+      constant,
+      initialized: false,
+    }
+  }
+
+  pub fn name(&self) -> &String {
+    &self.name
+  }
+
+  pub fn value(&self) -> &Value {
+    &self.value
+  }
+
+  pub fn value_mut(&mut self) -> &mut Value {
+    &mut self.value
+  }
+
+  pub fn is_constant(&self) -> bool {
+    self.constant
+  }
+
+  pub fn is_initialized(&self) -> bool {
+    self.initialized
+  }
+
+  pub fn initialize(&mut self, value: Value) {
+    self.value = value;
+    self.initialized = true;
   }
 }
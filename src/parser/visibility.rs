@@ -1,7 +1,44 @@
-#[derive(Debug, Copy, Clone, PartialEq)]
+use std::fmt::Display;
+
+use enum_iterator::IntoEnumIterator;
+
+use super::Keyword;
+
+#[derive(IntoEnumIterator, Debug, Copy, Clone, PartialEq)]
 pub enum Visibility {
   Public,
   Protected,
   Private,
   Package,
 }
+
+impl Display for Visibility {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}",
+      match *self {
+        Visibility::Public => "public",
+        Visibility::Protected => "protected",
+        Visibility::Private => "private",
+        Visibility::Package => "package",
+      }
+    )
+  }
+}
+
+impl Visibility {
+  pub fn parse<S: AsRef<str>>(s: S) -> Option<Visibility> {
+    Visibility::into_enum_iter().find(|v| format!("{}", v) == s.as_ref().trim())
+  }
+
+  /// The visibility set by a modifier keyword.
+  pub fn from_keyword(kw: Keyword) -> Option<Visibility> {
+    match kw {
+      Keyword::Public => Some(Visibility::Public),
+      Keyword::Protected => Some(Visibility::Protected),
+      Keyword::Private => Some(Visibility::Private),
+      _ => None,
+    }
+  }
+}
@@ -1,5 +1,7 @@
-use crate::{error::Error, result::Result};
-use std::{ffi::OsStr, fs::read_to_string, path::{Path, PathBuf}};
+#![allow(clippy::match_ref_pats, clippy::redundant_closure, noop_method_call)]
+
+use crate::{loader::{FileSystemLoader, ModuleLoader}, module::Module, result::Result};
+use std::{ffi::OsStr, path::{Path, PathBuf}, time::SystemTime};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ScriptState {
@@ -28,6 +30,8 @@ pub struct Script {
   path: PathBuf,
   content: Option<String>,
   state: ScriptState,
+  /// When the loaded content last changed, if the loader could tell.
+  modified: Option<SystemTime>,
 }
 
 impl std::fmt::Display for Script {
@@ -56,7 +60,8 @@ impl Script {
       name: name.map_or_else(|| stem, |v| String::from(v.as_ref())),
       path: PathBuf::from(path.as_ref()),
       content: content.map(|c| c.as_ref().clone().into()),
-      state
+      state,
+      modified: None,
     }
   }
 
@@ -82,6 +87,11 @@ impl Script {
     &mut self.path
   }
 
+  /// The path with `.` and `..` resolved, which tells scripts apart.
+  pub fn normalized_path(&self) -> PathBuf {
+    Module::normalize(&self.path)
+  }
+
   pub fn state(&self) -> &ScriptState {
     &self.state
   }
@@ -98,8 +108,22 @@ impl Script {
     self.content.as_mut()
   }
 
+  pub fn modified(&self) -> Option<SystemTime> {
+    self.modified
+  }
+
+  pub fn modified_mut(&mut self) -> &mut Option<SystemTime> {
+    &mut self.modified
+  }
+
   pub fn load(&mut self) -> Result<()> {
-    self.content = Some(read_to_string(&self.path).map_or_else(|e| Err(Error::IO(e)), |c| Ok(c))?);
+    self.load_with(&FileSystemLoader)
+  }
+
+  /// Read the content of the script through `loader`.
+  pub fn load_with(&mut self, loader: &dyn ModuleLoader) -> Result<()> {
+    self.content = Some(loader.load(&self.path)?);
+    self.modified = loader.modified(&self.path);
     Ok(())
   }
 }
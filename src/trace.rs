@@ -0,0 +1,93 @@
+use crate::location::Location;
+
+/// A call being run when an error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+  function: String,
+  location: Option<Location>,
+}
+
+impl StackFrame {
+  pub fn new<S: AsRef<str>>(function: S, location: Option<Location>) -> StackFrame {
+    StackFrame {
+      function: String::from(function.as_ref()),
+      location,
+    }
+  }
+
+  /// The function being run, or the script for top-level code.
+  pub fn function(&self) -> &String {
+    &self.function
+  }
+
+  /// Where the frame was, the call it was making for the outer ones.
+  pub fn location(&self) -> Option<&Location> {
+    self.location.as_ref()
+  }
+
+  /// The script the frame was running.
+  pub fn script(&self) -> Option<&String> {
+    self.location.as_ref().map(Location::file)
+  }
+}
+
+impl std::fmt::Display for StackFrame {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match &self.location {
+      Some(loc) => write!(f, "at {} ({})", self.function, loc),
+      None => write!(f, "at {}", self.function),
+    }
+  }
+}
+
+/// The calls being run, innermost first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StackTrace {
+  frames: Vec<StackFrame>,
+}
+
+impl StackTrace {
+  /// How many times in a row the same frame is shown before the others are counted.
+  const REPEATS_SHOWN: usize = 3;
+  /// How many lines are shown at most before the remaining frames are counted.
+  const LINES_SHOWN: usize = 32;
+
+  pub fn new(frames: Vec<StackFrame>) -> StackTrace {
+    StackTrace { frames }
+  }
+
+  pub fn frames(&self) -> &Vec<StackFrame> {
+    &self.frames
+  }
+
+  /// One line per frame, cut short for deep recursions.
+  pub fn lines(&self) -> Vec<String> {
+    let mut lines = vec![];
+    let mut idx = 0;
+    while idx < self.frames.len() {
+      if lines.len() >= Self::LINES_SHOWN {
+        lines.push(format!("... {} more frames", self.frames.len() - idx));
+        break;
+      }
+      let frame = &self.frames[idx];
+      let run = self.frames[idx..].iter().take_while(|f| *f == frame).count();
+      lines.extend(std::iter::repeat_n(frame.to_string(), run.min(Self::REPEATS_SHOWN)));
+      if run > Self::REPEATS_SHOWN {
+        lines.push(format!(
+          "... {} more frames in {}",
+          run - Self::REPEATS_SHOWN,
+          frame.function
+        ));
+      }
+      idx += run;
+    }
+    lines
+  }
+}
+
+/// The `lines`, one per line.
+impl std::fmt::Display for StackTrace {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.lines().join("\n"))
+  }
+}
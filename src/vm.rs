@@ -1,45 +1,231 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
 
+use crate::compiler::{Chunk, ClassProto, Compiler, FunctionProto, Precompiled};
+use crate::convert::{FromValue, IntoArgs, TypedFn};
+use crate::environment::{EnvPtr, Environment};
 use crate::error::Error;
-use crate::parser::{NodeKind, NodePtr, Parser, Value, AST};
+use crate::heap::{GcStats, Heap};
+use crate::loader::{FileSystemLoader, ModuleLoader};
+use crate::module::Module;
+use crate::parser::{
+  Class, Enum, Function, Instance, Keyword, OpCode, Parser, Value, Variable, Visibility,
+};
 use crate::result::Result;
 use crate::script::{Script, ScriptState};
+use crate::trace::{StackFrame, StackTrace};
 
 pub const BANNER: &str = env!("CARGO_PKG_NAME");
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub type NativeFn = dyn Fn(Vec<Value>) -> Result<Value>;
 
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+/// Declarations available to every script.
+const PRELUDE: &str = "class Error {
+  constructor(message) { this.message = message; }
+}";
+
+/// Activation record of a running chunk.
+struct Frame {
+  chunk: Rc<Chunk>,
+  ip: usize,
+  stack_base: usize,
+  caller_env: EnvPtr,
+  /// The instance returned in place of a constructor's own result.
+  construct: Option<Value>,
+  /// The class defining the running method, granting access to its restricted members.
+  class: Option<Rc<Class>>,
+}
+
+/// Where to resume when a value is thrown inside a `try` block.
+struct Handler {
+  /// Index of the frame running the `try` block.
+  frame: usize,
+  target: usize,
+  stack_len: usize,
+  env: EnvPtr,
+}
+
 pub struct Vm {
   version: String,
   scripts: Vec<Script>,
-  asts: Vec<AST>,
-  native_funcs: HashMap<String, Box<NativeFn>>,
+  /// The compiled chunk of every script, by normalized script path.
+  chunks: HashMap<PathBuf, Rc<Chunk>>,
+  globals: EnvPtr,
+  env: EnvPtr,
+  stack: Vec<Value>,
+  frames: Vec<Frame>,
+  handlers: Vec<Handler>,
+  max_call_depth: usize,
+  /// The builtin `Error` class, instantiated for errors caught by scripts.
+  error_class: Option<Rc<Class>>,
+  heap: Heap,
+  /// The namespace of every script that ran, by normalized script path.
+  modules: HashMap<PathBuf, Module>,
+  /// The paths of the scripts being run, the innermost one importing none of the others yet.
+  running: Vec<PathBuf>,
+  /// The path of the module last imported, which the names imported next come from.
+  imported: Option<PathBuf>,
+  loader: Box<dyn ModuleLoader>,
 }
 
 impl Default for Vm {
   fn default() -> Self {
+    let globals = Environment::new(None);
+    let mut heap = Heap::default();
+    heap.track_env(&globals);
     let mut ret = Self {
       version: String::from(VERSION),
       scripts: vec![],
-      asts: vec![],
-      native_funcs: HashMap::new(),
+      chunks: HashMap::new(),
+      globals: globals.clone(),
+      env: globals,
+      stack: vec![],
+      frames: vec![],
+      handlers: vec![],
+      max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+      error_class: None,
+      heap,
+      modules: HashMap::new(),
+      running: vec![],
+      imported: None,
+      loader: Box::new(FileSystemLoader),
     };
-    ret.add_native_func("println", |v| Vm::native_println(v)).unwrap();
-    ret.add_native_func("print", |v| Vm::native_println(v)).unwrap();
+    ret.add_native_func("println", Vm::native_println).unwrap();
+    ret.add_native_func("print", Vm::native_println).unwrap();
+    ret.load_prelude().unwrap();
     ret
   }
 }
 
 impl Vm {
+  fn load_prelude(&mut self) -> Result<()> {
+    let mut script = Script::new("virtual://prelude", Some("prelude"), Some(PRELUDE));
+    let ast = Parser::default().parse(&mut script)?;
+    self.execute(Rc::new(Compiler::compile(&ast, script.name())?))?;
+    match self.global("Error")? {
+      Value::Class(c) => self.error_class = Some(c),
+      v => return Err(Error::Runtime(format!("Error is {}, not a class", v.type_name()), None)),
+    }
+    Ok(())
+  }
+
+  /// Register `f` as a global function value.
   pub fn add_native_func<S: AsRef<str>, F: 'static + Fn(Vec<Value>) -> Result<Value>>(&mut self, k: S, f: F) -> Result<()> {
-    if self.native_funcs.contains_key(k.as_ref().into()) {
-      return Err(Error::Unknown(format!("native function '{}' already registered", k.as_ref()), None));
+    if let Ok(Value::Function(existing)) = self.global(k.as_ref()) {
+      if existing.is_native() {
+        return Err(Error::Unknown(format!("native function '{}' already registered", k.as_ref()), None));
+      }
     }
-    self.native_funcs.insert(k.as_ref().into(), Box::new(f));
+    let f = Function::Native {
+      name: k.as_ref().into(),
+      f: Rc::new(f),
+    };
+    self.set_global(k, Value::Function(Rc::new(f)));
     Ok(())
   }
+
+  /// Register a Rust closure as a global function, converting its arguments and result.
+  /// Calls with the wrong number or types of arguments fail.
+  pub fn add_typed_func<S: AsRef<str>, Args, F: 'static + TypedFn<Args>>(
+    &mut self,
+    k: S,
+    f: F,
+  ) -> Result<()> {
+    let name = String::from(k.as_ref());
+    self.add_native_func(k, move |args| f.call(&name, args))
+  }
+
+  pub fn globals(&self) -> &EnvPtr {
+    &self.globals
+  }
+
+  /// Define or overwrite a global variable visible to every script.
+  pub fn set_global<S: AsRef<str>>(&mut self, name: S, value: Value) {
+    self
+      .globals
+      .borrow_mut()
+      .vars_mut()
+      .insert(name.as_ref().into(), Variable::new(name.as_ref().into(), value));
+  }
+
+  pub fn global<S: AsRef<str>>(&self, name: S) -> Result<Value> {
+    Environment::get(&self.globals, name)
+  }
+
+  /// The global enum `name`, to look its variants up from the host.
+  pub fn enumeration<S: AsRef<str>>(&self, name: S) -> Result<Rc<Enum>> {
+    match self.global(name.as_ref())? {
+      Value::Enum(e) => Ok(e),
+      v => Err(Error::Runtime(
+        format!("'{}' is {}, not an enum", name.as_ref(), v.type_name()),
+        None,
+      )),
+    }
+  }
+
+  pub fn max_call_depth(&self) -> usize {
+    self.max_call_depth
+  }
+
+  pub fn max_call_depth_mut(&mut self) -> &mut usize {
+    &mut self.max_call_depth
+  }
+
+  /// The values allocated by scripts.
+  pub fn heap(&self) -> &Heap {
+    &self.heap
+  }
+
+  /// Tune when the collector runs through the heap's thresholds.
+  pub fn heap_mut(&mut self) -> &mut Heap {
+    &mut self.heap
+  }
+
+  /// Free the unreachable cycles of values, the roots being the globals, the running
+  /// frames and the values on the stack.
+  pub fn gc(&mut self) -> GcStats {
+    let mut roots: Vec<Value> = self
+      .frames
+      .iter()
+      .flat_map(|f| f.construct.clone().into_iter().chain(f.class.clone().map(Value::Class)))
+      .collect();
+    roots.extend(self.error_class.clone().map(Value::Class));
+    let envs = [&self.globals, &self.env]
+      .into_iter()
+      .chain(self.modules.values().map(Module::env))
+      .chain(self.frames.iter().map(|f| &f.caller_env))
+      .chain(self.handlers.iter().map(|h| &h.env));
+    self
+      .heap
+      .collect(self.stack.iter().chain(roots.iter()), envs)
+  }
+
+  /// The namespace of the script `name`, once it ran.
+  pub fn module<S: AsRef<str>>(&self, name: S) -> Option<&Module> {
+    self.modules.get(&self.script(name)?.normalized_path())
+  }
+
+  /// The namespaces of the scripts that ran, by normalized script path.
+  pub fn modules(&self) -> &HashMap<PathBuf, Module> {
+    &self.modules
+  }
+
+  /// Where scripts are read from, the filesystem by default.
+  pub fn loader(&self) -> &dyn ModuleLoader {
+    self.loader.as_ref()
+  }
+
+  pub fn set_loader<L: ModuleLoader + 'static>(&mut self, loader: L) {
+    self.loader = Box::new(loader);
+  }
+
   pub fn version(&self) -> &String {
     &self.version
   }
@@ -73,110 +259,1142 @@ impl Vm {
       .find(|scr| scr.name() == name.as_ref())
   }
 
-  pub fn reset(&mut self) {
-    self.scripts.clear()
+  /// The compiled chunk of a script that has been run.
+  pub fn chunk<S: AsRef<str>>(&self, name: S) -> Option<&Rc<Chunk>> {
+    self.chunks.get(&self.script(name)?.normalized_path())
   }
 
-  pub fn reachable_nodes(&self, from: NodePtr) -> Vec<NodePtr> {
-    let mut ret: Vec<NodePtr> = from.borrow().ancestors();
-    for ast in &self.asts {
-      for root_node in ast.root().borrow().children() {
-        ret.push(root_node.clone());
-      }
-    }
-    ret
+  /// Render `e` as a diagnostic quoting the script it happened in.
+  pub fn render_error(&self, e: &Error) -> String {
+    e.diagnostics()
+      .iter()
+      .map(|d| {
+        let source = d
+          .primary_span()
+          .and_then(|span| self.script(span.start().file()))
+          .and_then(|script| script.content());
+        d.render(source.map(String::as_str))
+      })
+      .collect::<Vec<String>>()
+      .join("\n")
+  }
+
+  pub fn reset(&mut self) {
+    self.scripts.clear();
+    self.chunks.clear();
+    self.modules.clear();
+    self.imported = None;
   }
 
+  /// Load the script at `path`, named after its file unless `name` is given.
+  ///
+  /// Scripts whose file name another script took already are named after their path, so
+  /// that errors and `module` tell them apart.
   pub fn load<S: AsRef<str>, P: AsRef<Path>>(
     &mut self,
     path: P,
     name: Option<S>,
   ) -> Result<&mut Script> {
-    self.scripts.push(Script::import(path, name)?);
+    let named = name.is_some();
+    let mut script = Script::new(path, name, None);
+    if !named && self.script(script.name()).is_some() {
+      *script.name_mut() = script.normalized_path().display().to_string();
+    }
+    script.load_with(self.loader.as_ref())?;
+    self.scripts.push(script);
     let n = self.scripts.len() - 1;
     *self.scripts.get_mut(n).unwrap().state_mut() = ScriptState::LOADED;
     Ok(self.scripts.get_mut(n).unwrap())
   }
 
-  fn execute_function_call(&self, node: NodePtr) -> Result<Value> {
-    let scope = self.reachable_nodes(node.clone());
-    let func = scope.iter().find(|n| {
-      *n.borrow().kind() == NodeKind::Function && n.borrow().name() == node.borrow().name()
-    });
-    match func {
-      Some(f) => {}
+  /// Load, parse and compile every script that hasn't been yet.
+  pub fn compile(&mut self) -> Result<()> {
+    for idx in 0..self.scripts.len() {
+      self.compile_script(idx)?;
+    }
+    Ok(())
+  }
+
+  fn compile_script(&mut self, idx: usize) -> Result<()> {
+    let script = &mut self.scripts[idx];
+    if *script.state() == ScriptState::INITIAL {
+      println!("Load Script: {}", script.name());
+      script.load_with(self.loader.as_ref())?;
+      *script.state_mut() = ScriptState::LOADED;
+    }
+    if *script.state() == ScriptState::LOADED {
+      println!("Parse Script: {}", script.name());
+      let ast = Parser::default().parse(script)?;
+      let chunk = Rc::new(Compiler::compile(&ast, script.name())?);
+      self.chunks.insert(script.normalized_path(), chunk);
+      *script.state_mut() = ScriptState::PARSED;
+    }
+    Ok(())
+  }
+
+  /// Run every script that didn't run yet, in order, unless a previous one imported it.
+  pub fn run(&mut self) -> Result<()> {
+    self.compile()?;
+    for idx in 0..self.scripts.len() {
+      if *self.scripts[idx].state() == ScriptState::PARSED {
+        self.run_module(idx)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Run the compiled script at `idx` in a namespace of its own, enclosed by the globals.
+  ///
+  /// When its top-level code fails, the script is left to run again and its namespace is
+  /// dropped.
+  fn run_module(&mut self, idx: usize) -> Result<()> {
+    let script = &self.scripts[idx];
+    let key = script.normalized_path();
+    let chunk = self
+      .chunks
+      .get(&key)
+      .cloned()
+      .ok_or_else(|| Error::Module(format!("'{}' is not compiled", script.name()), None))?;
+    let env = Environment::new(Some(self.globals.clone()));
+    self.heap.track_env(&env);
+    let module = Module::new(script.name(), script.path(), env.clone());
+    self.modules.insert(key.clone(), module);
+    *self.scripts[idx].state_mut() = ScriptState::RUNNING;
+    self.running.push(key);
+    let caller_env = std::mem::replace(&mut self.env, env);
+    let ret = self.execute(chunk);
+    self.env = caller_env;
+    let key = self.running.pop().unwrap();
+    if let Err(e) = ret {
+      self.modules.remove(&key);
+      *self.scripts[idx].state_mut() = ScriptState::PARSED;
+      return Err(e);
+    }
+    *self.scripts[idx].state_mut() = ScriptState::FINISHED;
+    Ok(())
+  }
+
+  /// Whether the script at `idx` is running, importing others maybe.
+  fn is_running(&self, idx: usize) -> bool {
+    self.running.contains(&self.scripts[idx].normalized_path())
+  }
+
+  /// The namespace of the module `specifier` imported from the running script, running
+  /// it first unless it already ran.
+  ///
+  /// Relative specifiers resolve against the path of the importing script, other ones
+  /// name a script first. Scripts that weren't added yet are found by the loader.
+  ///
+  /// Unlike ES modules, importers get the values exported at the time of the import
+  /// rather than live bindings: a variable the exporting script assigns later keeps its
+  /// old value in the importers, where functions reading it see the new one.
+  fn import(&mut self, specifier: &str) -> Result<Value> {
+    let importer = self
+      .running
+      .last()
+      .and_then(|path| self.find_script(path, None))
+      .map(|idx| &self.scripts[idx])
+      .ok_or_else(|| Error::Module("imports are only allowed in scripts".into(), None))?;
+    let path = Module::resolve(importer.path(), specifier);
+    let by_name = match Module::is_relative(specifier) {
+      true => None,
+      false => self.scripts.iter().position(|s| s.name() == specifier),
+    };
+    let idx = match by_name.or_else(|| self.find_script(&path, importer.path().extension())) {
+      Some(idx) => idx,
       None => {
-        // check native funcs
-        if node.borrow().name().is_some() {
-          let native_func = self
-            .native_funcs
-            .get(node.borrow().name().as_ref().unwrap());
-          if (native_func.is_some()) {
-            // transform FunctionParam nodes into list of values
-            let args = node
-              .borrow()
-              .children_by_kind(NodeKind::FunctionParam)
-              .iter()
-              .map(|n| {
-                n.borrow()
-                  .value()
-                  .clone()
-                  .or_else(|| Some(Value::None))
-                  .unwrap()
-              })
-              .collect();
-            return native_func.unwrap()(args);
+        let found = self
+          .loader
+          .resolve(specifier, importer.path())
+          .ok_or_else(|| Error::Module(format!("cannot find module '{}'", specifier), None))?;
+        match self.find_script(&found, None) {
+          Some(idx) => idx,
+          None => {
+            self.load(found, None::<&str>)?;
+            self.scripts.len() - 1
           }
         }
-        return Err(Error::Unknown(
-          format!(
-            "Unknown function {}",
-            match node.borrow().name() {
-              Some(n) => format!("'{}'", n),
-              None => "<unnamed>".into(),
-            }
-          ),
-          Some(node.borrow().location().clone()),
-        ));
       }
+    };
+    let key = self.scripts[idx].normalized_path();
+    if let Some(pos) = self.running.iter().position(|p| *p == key) {
+      let mut cycle: Vec<&str> = self.running[pos..]
+        .iter()
+        .map(|p| self.modules[p].name().as_str())
+        .collect();
+      cycle.push(self.scripts[idx].name());
+      return Err(Error::Module(
+        format!("import cycle: {}", cycle.join(" -> ")),
+        None,
+      ));
     }
-    Ok(Value::None)
+    if *self.scripts[idx].state() != ScriptState::FINISHED {
+      self.compile_script(idx)?;
+      self.run_module(idx)?;
+    }
+    let ns = self.modules[&key].namespace();
+    self.imported = Some(key);
+    ns
+  }
+
+  /// Read script `name` again and swap its functions for their new definitions, keeping
+  /// the values of its variables. Returns whether its source changed.
+  ///
+  /// Top-level code doesn't run again. The bindings of the replaced functions, in the
+  /// script and in the scripts importing them, get the new ones, while other references
+  /// keep the old code. When the new source doesn't compile, the script is left as it was.
+  pub fn reload<S: AsRef<str>>(&mut self, name: S) -> Result<bool> {
+    let idx = self.script_index(name.as_ref())?;
+    let script = &self.scripts[idx];
+    let mut fresh = Script::new(script.path(), Some(script.name()), None);
+    fresh.load_with(self.loader.as_ref())?;
+    let unchanged = fresh.content() == script.content();
+    let state = *script.state();
+    *self.scripts[idx].modified_mut() = fresh.modified();
+    if unchanged {
+      return Ok(false);
+    }
+    if self.is_running(idx) {
+      return Err(Error::Module(
+        format!("cannot reload '{}' while it runs", name.as_ref()),
+        None,
+      ));
+    }
+    let ast = Parser::default().parse(&mut fresh)?;
+    let chunk = Rc::new(Compiler::compile(&ast, fresh.name())?);
+    let key = fresh.normalized_path();
+    *fresh.state_mut() = match state {
+      ScriptState::FINISHED => {
+        self.swap_functions(&key, &chunk);
+        ScriptState::FINISHED
+      }
+      _ => ScriptState::PARSED,
+    };
+    self.scripts[idx] = fresh;
+    self.chunks.insert(key, chunk);
+    Ok(true)
+  }
+
+  /// Reload the scripts whose source changed since they were read, as far as the loader
+  /// can tell, returning their names.
+  pub fn reload_changed(&mut self) -> Result<Vec<String>> {
+    let mut reloaded = vec![];
+    for idx in 0..self.scripts.len() {
+      let script = &self.scripts[idx];
+      let Some(modified) = script.modified() else {
+        continue;
+      };
+      let current = self.loader.modified(script.path());
+      if current.is_none() || current == Some(modified) {
+        continue;
+      }
+      // even when the script can't be read, only report it once
+      *self.scripts[idx].modified_mut() = current;
+      let name = self.scripts[idx].name().clone();
+      if self.reload(&name)? {
+        reloaded.push(name);
+      }
+    }
+    Ok(reloaded)
+  }
+
+  /// Check the scripts for changes every `interval`, reloading the ones that changed, until
+  /// `on_poll` returns false. It is given the names of the reloaded scripts, or the error
+  /// that stopped reloading them.
+  pub fn watch<F: FnMut(&mut Vm, Result<Vec<String>>) -> bool>(
+    &mut self,
+    interval: Duration,
+    mut on_poll: F,
+  ) {
+    loop {
+      std::thread::sleep(interval);
+      let reloaded = self.reload_changed();
+      if !on_poll(self, reloaded) {
+        break;
+      }
+    }
+  }
+
+  /// Declare the top-level functions of `chunk` in the namespace of the script at `path`,
+  /// and point the bindings of the functions they replace to them.
+  fn swap_functions(&mut self, path: &Path, chunk: &Chunk) {
+    let Some(module) = self.modules.get_mut(path) else {
+      return;
+    };
+    let env = module.env().clone();
+    let mut swapped: Vec<(Rc<Function>, Value)> = vec![];
+    for proto in chunk.hoisted_functions() {
+      let old = env
+        .borrow()
+        .vars()
+        .get(proto.name())
+        .map(|v| v.value().clone());
+      let f = Environment::declare_function(&env, proto);
+      self.heap.track(&f);
+      if let Some(Value::Function(old)) = old {
+        swapped.push((old, f));
+      }
+    }
+    *module.exports_mut() = chunk
+      .code()
+      .iter()
+      .filter_map(|op| match op {
+        OpCode::Export(name) if env.borrow().vars().contains_key(name) => Some(name.clone()),
+        _ => None,
+      })
+      .collect();
+
+    let swap = |v: &mut Value| {
+      if let Value::Function(f) = v {
+        if let Some((_, new)) = swapped.iter().find(|(old, _)| Rc::ptr_eq(old, f)) {
+          *v = new.clone();
+        }
+      }
+    };
+    let envs = self.modules.values().map(|m| m.env().clone());
+    for env in envs.chain(std::iter::once(self.globals.clone())) {
+      for var in env.borrow_mut().vars_mut().values_mut() {
+        match var.value_mut() {
+          // namespaces imported with `* as`
+          Value::Object(o) => o.borrow_mut().values_mut().for_each(swap),
+          v => swap(v),
+        }
+      }
+    }
+  }
+
+  fn script_index(&self, name: &str) -> Result<usize> {
+    self
+      .scripts
+      .iter()
+      .position(|scr| scr.name() == name)
+      .ok_or_else(|| Error::Unknown(format!("unknown script '{}'", name), None))
+  }
+
+  /// The script at `path`, its extension being optional when it has the one of the importer.
+  fn find_script(&self, path: &Path, ext: Option<&std::ffi::OsStr>) -> Option<usize> {
+    self.scripts.iter().position(|s| {
+      let candidate = Module::normalize(s.path());
+      candidate == path
+        || (path.extension().is_none()
+          && candidate.extension() == ext
+          && candidate.with_extension("") == path)
+    })
   }
 
-  fn execute_node(&self, node: NodePtr) -> Result<()> {
-    println!("Execute node: {}", node.borrow());
-    match node.borrow().kind() {
-      NodeKind::Call => {
-        self.execute_function_call(node.clone())?;
+  /// Serialize the compiled chunk of script `name`, compiling it first if needed.
+  pub fn precompile<S: AsRef<str>>(&mut self, name: S) -> Result<Vec<u8>> {
+    let idx = self.script_index(name.as_ref())?;
+    self.compile_script(idx)?;
+    let script = &self.scripts[idx];
+    let chunk = self
+      .chunks
+      .get(&script.normalized_path())
+      .ok_or_else(|| Error::Unknown(format!("script '{}' is not compiled", script.name()), None))?;
+    let source = script.content().map(String::as_str).unwrap_or_default();
+    Precompiled::new(Chunk::clone(chunk), source).to_bytes()
+  }
+
+  pub fn save_precompiled<S: AsRef<str>, P: AsRef<Path>>(&mut self, name: S, path: P) -> Result<()> {
+    let bytes = self.precompile(name)?;
+    std::fs::write(path, bytes).map_err(Error::IO)
+  }
+
+  /// Add a script from precompiled data, skipping parsing.
+  pub fn add_precompiled<S: AsRef<str>>(&mut self, bytes: &[u8], name: Option<S>) -> Result<&mut Script> {
+    let chunk = Precompiled::from_bytes(bytes)?.into_chunk();
+    let name = name.map_or_else(|| chunk.name().clone(), |n| String::from(n.as_ref()));
+    self.add_compiled(Script::new(&name, Some(&name), None), chunk)
+  }
+
+  pub fn load_precompiled<S: AsRef<str>, P: AsRef<Path>>(&mut self, path: P, name: Option<S>) -> Result<&mut Script> {
+    let bytes = std::fs::read(path).map_err(Error::IO)?;
+    self.add_precompiled(&bytes, name)
+  }
+
+  /// Load the script at `path`, reusing the chunk cached at `cache` when it was
+  /// compiled from the same source by this version, and refreshing it otherwise.
+  pub fn load_cached<S: AsRef<str>, P: AsRef<Path>, C: AsRef<Path>>(
+    &mut self,
+    path: P,
+    cache: C,
+    name: Option<S>,
+  ) -> Result<&mut Script> {
+    let mut script = Script::new(path, name, None);
+    script.load_with(self.loader.as_ref())?;
+    let source = script.content().cloned().unwrap_or_default();
+    let cached = std::fs::read(cache.as_ref())
+      .ok()
+      .and_then(|bytes| Precompiled::from_bytes(&bytes).ok())
+      .filter(|p| p.is_compiled_from(&source));
+    if let Some(p) = cached {
+      return self.add_compiled(script, p.into_chunk());
+    }
+    *script.state_mut() = ScriptState::LOADED;
+    let name = script.name().clone();
+    self.scripts.push(script);
+    self.save_precompiled(&name, cache)?;
+    Ok(self.scripts.last_mut().unwrap())
+  }
+
+  fn add_compiled(&mut self, mut script: Script, chunk: Chunk) -> Result<&mut Script> {
+    *script.state_mut() = ScriptState::PARSED;
+    self.chunks.insert(script.normalized_path(), Rc::new(chunk));
+    Ok(self.add_script(script))
+  }
+
+  /// Run `chunk` in the current environment until it returns.
+  pub fn execute(&mut self, chunk: Rc<Chunk>) -> Result<Value> {
+    let depth = self.frames.len();
+    let stack_len = self.stack.len();
+    self.frames.push(Frame {
+      chunk,
+      ip: 0,
+      stack_base: stack_len,
+      caller_env: self.env.clone(),
+      construct: None,
+      class: None,
+    });
+    self.finish(depth, stack_len)
+  }
+
+  /// Call the function value `f` from Rust, running it to completion.
+  pub fn call_value(&mut self, f: &Value, args: Vec<Value>) -> Result<Value> {
+    let depth = self.frames.len();
+    let stack_len = self.stack.len();
+    self.apply(f.clone(), args)?;
+    // native functions are done already
+    if self.frames.len() == depth {
+      return self.pop();
+    }
+    self.finish(depth, stack_len)
+  }
+
+  /// Call the top-level function `name` of `script`, running the script first unless it
+  /// already ran.
+  pub fn call<S: AsRef<str>, N: AsRef<str>>(
+    &mut self,
+    script: S,
+    name: N,
+    args: Vec<Value>,
+  ) -> Result<Value> {
+    let idx = self.script_index(script.as_ref())?;
+    // a script calling back into itself from its top level doesn't run again
+    if !self.is_running(idx) && *self.scripts[idx].state() != ScriptState::FINISHED {
+      self.compile_script(idx)?;
+      self.run_module(idx)?;
+    }
+    let f = self
+      .modules
+      .get(&self.scripts[idx].normalized_path())
+      .and_then(|m| m.get(name.as_ref()).ok())
+      .filter(|f| matches!(f, Value::Function(_)))
+      .ok_or_else(|| {
+        Error::Unknown(
+          format!("'{}' has no function '{}'", script.as_ref(), name.as_ref()),
+          None,
+        )
+      })?;
+    self.call_value(&f, args)
+  }
+
+  /// `call` with arguments and result converted from and to Rust types.
+  pub fn call_typed<S: AsRef<str>, N: AsRef<str>, A: IntoArgs, R: FromValue>(
+    &mut self,
+    script: S,
+    name: N,
+    args: A,
+  ) -> Result<R> {
+    let ret = self.call(script, name, args.into_args())?;
+    R::from_value(ret)
+  }
+
+  /// Run the frame pushed at `depth` until it returns, unwinding it on errors.
+  fn finish(&mut self, depth: usize, stack_len: usize) -> Result<Value> {
+    let ret = self.run_frames(depth);
+    if ret.is_err() {
+      // unwind everything this call pushed
+      if let Some(frame) = self.frames.get(depth) {
+        self.env = frame.caller_env.clone();
+      }
+      self.frames.truncate(depth);
+      self.stack.truncate(stack_len);
+      self.drop_handlers();
+    }
+    ret
+  }
+
+  /// Interpret instructions until the frame at `depth` returns.
+  fn run_frames(&mut self, depth: usize) -> Result<Value> {
+    loop {
+      if self.heap.should_collect() {
+        self.gc();
+      }
+      let frame = self.frames.last_mut().unwrap();
+      let chunk = frame.chunk.clone();
+      let ip = frame.ip;
+      frame.ip += 1;
+      let op = match chunk.code().get(ip) {
+        Some(op) => op,
+        None => return Err(Error::Runtime(format!("{}: instruction {} out of bounds", chunk.name(), ip), None)),
+      };
+      let ret = match self.step(&chunk, op) {
+        Ok(ret) => ret,
+        Err(e) => {
+          let e = match chunk.location(ip) {
+            Some(loc) => e.at(loc),
+            None => e,
+          };
+          if let Err(e) = self.catch(e, depth) {
+            return Err(e.with_trace(self.trace()));
+          }
+          continue;
+        }
+      };
+      if let Some(v) = ret {
+        if self.frames.len() == depth {
+          return Ok(v);
+        }
+        self.stack.push(v);
       }
-      _ => {}
     }
-    for child in node.borrow().children().iter() {
-      self.execute_node(child.clone())?;
+  }
+
+  /// Resume at the innermost handler installed since the frame at `depth`, passing it
+  /// the thrown value, or the error as an `Error` instance.
+  fn catch(&mut self, e: Error, depth: usize) -> Result<()> {
+    if self.handlers.last().is_none_or(|h| h.frame < depth) {
+      return Err(e);
     }
+    let v = match e.into_root() {
+      Error::Thrown(v, _) => v,
+      e => {
+        let message = match &e {
+          Error::Runtime(msg, _) | Error::Unknown(msg, _) | Error::Module(msg, _) => msg.clone(),
+          e => e.to_string(),
+        };
+        let class = self.error_class.clone().ok_or(e)?;
+        let mut instance = Instance::new(class);
+        instance.fields_mut().insert("message".into(), Value::String(message));
+        instance
+          .fields_mut()
+          .insert("stack".into(), Value::String(self.trace().to_string()));
+        let v = Value::Instance(Rc::new(RefCell::new(instance)));
+        self.heap.track(&v);
+        v
+      }
+    };
+    let h = self.handlers.pop().unwrap();
+    self.frames.truncate(h.frame + 1);
+    self.stack.truncate(h.stack_len);
+    self.env = h.env;
+    self.jump(h.target);
+    self.stack.push(v);
     Ok(())
   }
 
-  pub fn run(&mut self) -> Result<()> {
-    let mut p = Parser::default();
-    for mut script in self.scripts.iter_mut() {
-      if *script.state() == ScriptState::INITIAL {
-        println!("Load Script: {}", script.name());
-        script.load()?;
+  /// Drop the handlers of frames that are gone.
+  fn drop_handlers(&mut self) {
+    while self.handlers.last().is_some_and(|h| h.frame >= self.frames.len()) {
+      self.handlers.pop();
+    }
+  }
+
+  /// The calls being run, innermost first.
+  pub fn trace(&self) -> StackTrace {
+    StackTrace::new(
+      self
+        .frames
+        .iter()
+        .rev()
+        .map(|f| StackFrame::new(f.chunk.name(), f.chunk.location(f.ip.saturating_sub(1)).cloned()))
+        .collect(),
+    )
+  }
+
+  fn pop(&mut self) -> Result<Value> {
+    self
+      .stack
+      .pop()
+      .ok_or_else(|| Error::Runtime("stack underflow".into(), None))
+  }
+
+  fn peek(&self) -> Result<&Value> {
+    self
+      .stack
+      .last()
+      .ok_or_else(|| Error::Runtime("stack underflow".into(), None))
+  }
+
+  fn jump(&mut self, target: usize) {
+    self.frames.last_mut().unwrap().ip = target;
+  }
+
+  /// Execute a single instruction, returning a value when the current frame returns.
+  fn step(&mut self, chunk: &Chunk, op: &OpCode) -> Result<Option<Value>> {
+    match op {
+      OpCode::Constant(idx) => {
+        let v = chunk.constants().get(*idx).cloned().ok_or_else(|| {
+          Error::Runtime(format!("{}: constant {} out of bounds", chunk.name(), idx), None)
+        })?;
+        self.stack.push(v);
       }
-      if *script.state() == ScriptState::LOADED {
-        println!("Parse Script: {}", script.name());
-        self.asts.push(p.parse(&mut script)?);
+      OpCode::Pop => {
+        self.pop()?;
+      }
+      OpCode::Dup => {
+        let v = self.peek()?.clone();
+        self.stack.push(v);
+      }
+      OpCode::Dup2 => {
+        let top = self.pop_many(2)?;
+        self.stack.extend(top.iter().cloned());
+        self.stack.extend(top);
+      }
+      OpCode::DeclareVariable(name) => {
+        self.env.borrow_mut().declare(Variable::uninitialized(name.clone(), false))?;
+      }
+      OpCode::DeclareConstant(name) => {
+        self.env.borrow_mut().declare(Variable::uninitialized(name.clone(), true))?;
+      }
+      OpCode::InitVariable(name) => {
+        let v = self.pop()?;
+        self.env.borrow_mut().initialize(name, v)?;
+      }
+      OpCode::LoadVariable(name) => {
+        let v = Environment::get(&self.env, name)?;
+        self.stack.push(v);
+      }
+      OpCode::AssignVariable(name) => {
+        let v = self.peek()?.clone();
+        Environment::assign(&self.env, name, v)?;
+      }
+      OpCode::PushScope => {
+        self.env = Environment::new(Some(self.env.clone()));
+        self.heap.track_env(&self.env);
+      }
+      OpCode::PopScope => {
+        let parent = self.env.borrow().parent().clone();
+        self.env = parent.ok_or_else(|| Error::Runtime("no scope to pop".into(), None))?;
+      }
+      OpCode::Negate => {
+        let v = self.pop()?.neg()?;
+        self.stack.push(v);
+      }
+      OpCode::Not => {
+        let v = self.pop()?;
+        self.stack.push(Value::Boolean(!v.is_truthy()));
+      }
+      OpCode::Jump(target) => self.jump(*target),
+      OpCode::JumpIfFalse(target) => {
+        if !self.pop()?.is_truthy() {
+          self.jump(*target);
+        }
+      }
+      OpCode::JumpIfTrue(target) => {
+        if self.pop()?.is_truthy() {
+          self.jump(*target);
+        }
+      }
+      OpCode::DeclareFunction(idx) => {
+        let f = chunk.functions().get(*idx).cloned().ok_or_else(|| {
+          Error::Runtime(format!("{}: function {} out of bounds", chunk.name(), idx), None)
+        })?;
+        let f = Environment::declare_function(&self.env, f);
+        self.heap.track(&f);
+      }
+      OpCode::CallFunction(name, argc) => self.call_function(name, *argc)?,
+      OpCode::Call(argc) => {
+        let (f, args) = self.pop_call(*argc)?;
+        self.apply(f, args)?;
+      }
+      OpCode::Closure(idx) => {
+        let proto = chunk.functions().get(*idx).cloned().ok_or_else(|| {
+          Error::Runtime(format!("{}: function {} out of bounds", chunk.name(), idx), None)
+        })?;
+        let f = Value::Function(Rc::new(Function::Closure {
+          proto,
+          env: self.env.clone(),
+          class: self.frames.last().and_then(|f| f.class.clone()),
+        }));
+        self.heap.track(&f);
+        self.stack.push(f);
+      }
+      OpCode::ReturnValue => {
+        let v = self.pop()?;
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.stack_base);
+        self.env = frame.caller_env;
+        self.drop_handlers();
+        return Ok(Some(frame.construct.unwrap_or(v)));
+      }
+      OpCode::Class(idx) => {
+        let proto = chunk.classes().get(*idx).cloned().ok_or_else(|| {
+          Error::Runtime(format!("{}: class {} out of bounds", chunk.name(), idx), None)
+        })?;
+        let superclass = match proto.superclass() {
+          Some(_) => match self.pop()? {
+            Value::Class(c) => Some(c),
+            v => {
+              return Err(Error::Runtime(
+                format!("class {} cannot extend {}", proto.name(), v.type_name()),
+                None,
+              ))
+            }
+          },
+          None => None,
+        };
+        let class = Value::Class(Rc::new(Class::new(proto, superclass, self.env.clone())));
+        self.heap.track(&class);
+        self.stack.push(class);
+      }
+      OpCode::GetProperty(name) => {
+        let v = match self.pop()? {
+          Value::Instance(i) => {
+            let i = i.borrow();
+            self.check_access(i.class(), name)?;
+            i.fields().get(name).cloned().ok_or_else(|| {
+              Error::Runtime(
+                format!("undefined property '{}' on {}", name, i.class().name()),
+                None,
+              )
+            })?
+          }
+          Value::Enum(e) => e.variant(name).map(Value::Integer).ok_or_else(|| {
+            Error::Runtime(format!("undefined variant '{}' on {}", name, e.name()), None)
+          })?,
+          Value::Object(o) => o.borrow().get(name).cloned().unwrap_or(Value::None),
+          Value::Array(a) if name == "length" => Value::Integer(a.borrow().len() as i64),
+          v => {
+            return Err(Error::Runtime(
+              format!("cannot read property '{}' of {}", name, v.type_name()),
+              None,
+            ))
+          }
+        };
+        self.stack.push(v);
+      }
+      OpCode::SetProperty(name) => {
+        let v = self.pop()?;
+        match self.pop()? {
+          Value::Instance(i) => {
+            self.check_access(i.borrow().class(), name)?;
+            i.borrow_mut().fields_mut().insert(name.clone(), v.clone());
+          }
+          Value::Object(o) => {
+            o.borrow_mut().insert(name.clone(), v.clone());
+          }
+          target => {
+            return Err(Error::Runtime(
+              format!("cannot set property '{}' of {}", name, target.type_name()),
+              None,
+            ))
+          }
+        }
+        self.stack.push(v);
+      }
+      OpCode::Invoke(name, argc) => self.invoke(name, *argc)?,
+      OpCode::InvokeSuper(name, argc) => self.invoke_super(name, *argc)?,
+      OpCode::ForwardSuper => {
+        let this = Environment::get(&self.env, Keyword::This.to_string())?;
+        let args = match Environment::get(&self.env, FunctionProto::ARGUMENTS)? {
+          Value::Array(a) => a.borrow().clone(),
+          v => return Err(Error::Runtime(format!("{} is not an array", v.type_name()), None)),
+        };
+        self.call_super(ClassProto::CONSTRUCTOR, this, args)?;
+      }
+      OpCode::New(argc) => self.construct(*argc)?,
+      OpCode::EnterTry(target) => self.handlers.push(Handler {
+        frame: self.frames.len() - 1,
+        target: *target,
+        stack_len: self.stack.len(),
+        env: self.env.clone(),
+      }),
+      OpCode::LeaveTry => {
+        self.handlers.pop();
+      }
+      OpCode::Throw => return Err(Error::Thrown(self.pop()?, None)),
+      OpCode::Array(len) => {
+        let elements = self.pop_many(*len)?;
+        let array = Value::array(elements);
+        self.heap.track(&array);
+        self.stack.push(array);
+      }
+      OpCode::Object(len) => {
+        let mut entries = HashMap::new();
+        let mut pairs = self.pop_many(len * 2)?.into_iter();
+        while let (Some(key), Some(v)) = (pairs.next(), pairs.next()) {
+          entries.insert(key.to_display_string(), v);
+        }
+        let object = Value::object(entries);
+        self.heap.track(&object);
+        self.stack.push(object);
+      }
+      OpCode::Import(idx) => {
+        let ns = match chunk.constants().get(*idx) {
+          Some(Value::String(path)) => self.import(path)?,
+          _ => {
+            return Err(Error::Runtime(
+              format!("{}: constant {} is not a module path", chunk.name(), idx),
+              None,
+            ))
+          }
+        };
+        self.stack.push(ns);
+      }
+      OpCode::ImportName(name) => {
+        self.pop()?;
+        let module = self
+          .imported
+          .as_ref()
+          .and_then(|path| self.modules.get(path))
+          .ok_or_else(|| Error::Module(format!("no module to import '{}' from", name), None))?;
+        let v = module.export(name)?;
+        self.stack.push(v);
+      }
+      OpCode::Export(name) => {
+        let module = self
+          .running
+          .last()
+          .and_then(|m| self.modules.get_mut(m))
+          .ok_or_else(|| Error::Module("exports are only allowed in scripts".into(), None))?;
+        if !module.exports().contains(name) {
+          module.exports_mut().push(name.clone());
+        }
+      }
+      OpCode::GetIndex => {
+        let index = self.pop()?;
+        let target = self.pop()?;
+        let v = match (&target, &index) {
+          (Value::Array(a), Value::Integer(i)) => usize::try_from(*i)
+            .ok()
+            .and_then(|i| a.borrow().get(i).cloned())
+            .unwrap_or(Value::None),
+          (Value::Object(o), Value::String(key)) => {
+            o.borrow().get(key).cloned().unwrap_or(Value::None)
+          }
+          _ => return Err(Self::invalid_index(&target, &index)),
+        };
+        self.stack.push(v);
+      }
+      OpCode::SetIndex => {
+        let v = self.pop()?;
+        let index = self.pop()?;
+        let target = self.pop()?;
+        match (&target, &index) {
+          (Value::Array(a), Value::Integer(i)) => {
+            let mut a = a.borrow_mut();
+            match usize::try_from(*i) {
+              Ok(i) if i < a.len() => a[i] = v.clone(),
+              // assigning right past the end appends
+              Ok(i) if i == a.len() => a.push(v.clone()),
+              _ => {
+                return Err(Error::Runtime(
+                  format!("index {} out of bounds for array of length {}", i, a.len()),
+                  None,
+                ))
+              }
+            }
+          }
+          (Value::Object(o), Value::String(key)) => {
+            o.borrow_mut().insert(key.clone(), v.clone());
+          }
+          _ => return Err(Self::invalid_index(&target, &index)),
+        }
+        self.stack.push(v);
+      }
+      op => {
+        let rhs = self.pop()?;
+        let lhs = self.pop()?;
+        let v = Self::binary_op(op, &lhs, &rhs)?;
+        self.stack.push(v);
       }
     }
+    Ok(None)
+  }
 
-    for ast in self.asts.iter() {
-      println!("Execute AST: {}", ast.root().borrow().location().file());
-      self.execute_node(ast.root().clone())?;
+  /// Pop the `len` topmost values, in the order they were pushed.
+  fn pop_many(&mut self, len: usize) -> Result<Vec<Value>> {
+    if self.stack.len() < len {
+      return Err(Error::Runtime("stack underflow".into(), None));
+    }
+    Ok(self.stack.split_off(self.stack.len() - len))
+  }
+
+  fn invalid_index(target: &Value, index: &Value) -> Error {
+    Error::Runtime(
+      format!("cannot index {} with {}", target.type_name(), index.type_name()),
+      None,
+    )
+  }
+
+  /// Pop the `argc` arguments of a call along with the callee pushed before them.
+  fn pop_call(&mut self, argc: usize) -> Result<(Value, Vec<Value>)> {
+    let args = self.pop_many(argc)?;
+    Ok((self.pop()?, args))
+  }
+
+  /// Call a method of the instance pushed before the arguments, or a function held by
+  /// one of its fields or by an object entry.
+  fn invoke(&mut self, name: &String, argc: usize) -> Result<()> {
+    let (target, args) = self.pop_call(argc)?;
+    let class = match &target {
+      Value::Instance(i) => i.borrow().class().clone(),
+      Value::Object(o) => {
+        let f = o.borrow().get(name).cloned().ok_or_else(|| {
+          Error::Runtime(format!("undefined method '{}' on object", name), None)
+        })?;
+        return self.apply(f, args);
+      }
+      Value::Enum(e) => {
+        let v = Self::enum_method(e, name, &args)?;
+        self.stack.push(v);
+        return Ok(());
+      }
+      v => {
+        return Err(Error::Runtime(
+          format!("cannot call method '{}' of {}", name, v.type_name()),
+          None,
+        ))
+      }
+    };
+    self.check_access(&class, name)?;
+    if let Some((owner, method)) = Class::method(&class, name) {
+      let env = owner.env().clone();
+      return self.push_frame(&method, &env, args, Some((target, owner)), None);
+    }
+    let field = match &target {
+      Value::Instance(i) => i.borrow().fields().get(name).cloned(),
+      _ => None,
+    };
+    match field {
+      Some(f @ Value::Function(_)) => self.apply(f, args),
+      _ => Err(Error::Runtime(
+        format!("undefined method '{}' on {}", name, class.name()),
+        None,
+      )),
+    }
+  }
+
+  /// `name(value)` looks a variant's name up, `count()` and `at(index)` iterate over them.
+  fn enum_method(e: &Enum, name: &str, args: &[Value]) -> Result<Value> {
+    let variants = e.variants();
+    match (name, args) {
+      ("name", [Value::Integer(v)]) => Ok(e.name_of(*v).cloned().map_or(Value::None, Value::String)),
+      ("name", [_]) => Ok(Value::None),
+      ("count", []) => Ok(Value::Integer(variants.len() as i64)),
+      ("at", [Value::Integer(idx)]) => Ok(
+        usize::try_from(*idx)
+          .ok()
+          .and_then(|idx| variants.get(idx))
+          .map_or(Value::None, |(_, v)| Value::Integer(*v)),
+      ),
+      ("name" | "count" | "at", _) => Err(Error::Runtime(
+        format!("invalid arguments for {}.{}", e.name(), name),
+        None,
+      )),
+      _ => Err(Error::Runtime(format!("undefined method '{}' on {}", name, e.name()), None)),
+    }
+  }
+
+  /// Call a method of the superclass of the running method's class on `this`.
+  fn invoke_super(&mut self, name: &str, argc: usize) -> Result<()> {
+    let (this, args) = self.pop_call(argc)?;
+    self.call_super(name, this, args)
+  }
+
+  /// Call the method `name` of the running method's superclass on `this`.
+  fn call_super(&mut self, name: &str, this: Value, args: Vec<Value>) -> Result<()> {
+    let superclass = match Environment::get(&self.env, Keyword::Super.to_string())? {
+      Value::Class(c) => c,
+      v => return Err(Error::Runtime(format!("{} is not a class", v.type_name()), None)),
+    };
+    self.check_access(&superclass, name)?;
+    match Class::method(&superclass, name) {
+      Some((owner, method)) => {
+        let env = owner.env().clone();
+        self.push_frame(&method, &env, args, Some((this, owner)), None)
+      }
+      // classes without a constructor are built by `new` alone
+      None if name == ClassProto::CONSTRUCTOR => {
+        self.stack.push(Value::None);
+        Ok(())
+      }
+      None => Err(Error::Runtime(
+        format!("undefined method '{}' on {}", name, superclass.name()),
+        None,
+      )),
+    }
+  }
+
+  /// Instantiate the class pushed before the arguments, running its constructor if any.
+  fn construct(&mut self, argc: usize) -> Result<()> {
+    let (class, args) = match self.pop_call(argc)? {
+      (Value::Class(c), args) => (c, args),
+      (v, _) => return Err(Error::Runtime(format!("{} is not a class", v.type_name()), None)),
+    };
+    let mut instance = Instance::new(class.clone());
+    if let Some(error_class) = &self.error_class {
+      if class.derives_from(error_class) {
+        instance
+          .fields_mut()
+          .insert("stack".into(), Value::String(self.trace().to_string()));
+      }
+    }
+    let instance = Value::Instance(Rc::new(RefCell::new(instance)));
+    self.heap.track(&instance);
+    self.check_access(&class, ClassProto::CONSTRUCTOR)?;
+    match Class::method(&class, ClassProto::CONSTRUCTOR) {
+      Some((owner, ctor)) => {
+        let env = owner.env().clone();
+        self.push_frame(&ctor, &env, args, Some((instance.clone(), owner)), Some(instance))
+      }
+      None => {
+        self.stack.push(instance);
+        Ok(())
+      }
+    }
+  }
+
+  /// Fail unless the running method may access `name` on instances of `class`.
+  fn check_access(&self, class: &Class, name: &str) -> Result<()> {
+    let (owner, visibility) = match class.member(name) {
+      Some(m) => m,
+      None => return Ok(()),
+    };
+    let accessor = self.frames.last().and_then(|f| f.class.as_deref());
+    let allowed = match visibility {
+      // every script shares the same package for now
+      Visibility::Public | Visibility::Package => true,
+      Visibility::Private => accessor.is_some_and(|a| std::ptr::eq(a, owner)),
+      Visibility::Protected => accessor.is_some_and(|a| a.derives_from(owner)),
+    };
+    if !allowed {
+      return Err(Error::Runtime(
+        format!("'{}' has {} access in {}", name, visibility, owner.name()),
+        None,
+      ));
     }
     Ok(())
   }
 
+  /// Start running `f`, binding its parameters in a fresh environment enclosed by `env`.
+  ///
+  /// Methods also get `this` and, when their class has one, `super`.
+  fn push_frame(
+    &mut self,
+    f: &FunctionProto,
+    env: &EnvPtr,
+    args: Vec<Value>,
+    this: Option<(Value, Rc<Class>)>,
+    construct: Option<Value>,
+  ) -> Result<()> {
+    // the top frame runs the script itself
+    if self.frames.len() > self.max_call_depth {
+      return Err(Error::Runtime(
+        format!("maximum call depth of {} exceeded", self.max_call_depth),
+        None,
+      ));
+    }
+    let env = Environment::new(Some(env.clone()));
+    self.heap.track_env(&env);
+    if let Some((this, class)) = &this {
+      env
+        .borrow_mut()
+        .declare(Variable::constant(Keyword::This.to_string(), this.clone()))?;
+      if let Some(superclass) = class.superclass() {
+        env.borrow_mut().declare(Variable::constant(
+          Keyword::Super.to_string(),
+          Value::Class(superclass.clone()),
+        ))?;
+      }
+    }
+    let mut args = args.into_iter();
+    for param in f.params() {
+      let v = match param.as_str() {
+        FunctionProto::ARGUMENTS => {
+          let rest = Value::array(args.by_ref().collect());
+          self.heap.track(&rest);
+          rest
+        }
+        _ => args.next().unwrap_or(Value::None),
+      };
+      env.borrow_mut().declare(Variable::new(param.clone(), v))?;
+    }
+    self.frames.push(Frame {
+      chunk: f.chunk().clone(),
+      ip: 0,
+      stack_base: self.stack.len(),
+      caller_env: std::mem::replace(&mut self.env, env),
+      construct,
+      class: this.map(|(_, class)| class),
+    });
+    Ok(())
+  }
+
+  /// Call the function held by the variable `name`.
+  fn call_function(&mut self, name: &String, argc: usize) -> Result<()> {
+    let args = self.pop_many(argc)?;
+    if Environment::lookup(&self.env, name).is_none() {
+      return Err(Error::Unknown(format!("Unknown function '{}'", name), None));
+    }
+    let f = Environment::get(&self.env, name)?;
+    self.apply(f, args)
+  }
+
+  /// Call a function value: script functions get a frame, native ones run right away.
+  fn apply(&mut self, f: Value, args: Vec<Value>) -> Result<()> {
+    let f = match f {
+      Value::Function(f) => f,
+      v => return Err(Error::Runtime(format!("{} is not a function", v.type_name()), None)),
+    };
+    match &*f {
+      Function::Closure { proto, env, class } => {
+        self.push_frame(proto, env, args, None, None)?;
+        self.frames.last_mut().unwrap().class = class.clone();
+        Ok(())
+      }
+      Function::Native { f, .. } => {
+        let v = f(args)?;
+        self.stack.push(v);
+        Ok(())
+      }
+    }
+  }
+
+  fn binary_op(op: &OpCode, lhs: &Value, rhs: &Value) -> Result<Value> {
+    match op {
+      OpCode::Add => lhs.add(rhs),
+      OpCode::Subtract => lhs.sub(rhs),
+      OpCode::Multiply => lhs.mul(rhs),
+      OpCode::Divide => lhs.div(rhs),
+      OpCode::Modulo => lhs.rem(rhs),
+      OpCode::Equal => Ok(Value::Boolean(lhs.loose_eq(rhs))),
+      OpCode::NotEqual => Ok(Value::Boolean(!lhs.loose_eq(rhs))),
+      OpCode::Less => Ok(Value::Boolean(lhs.compare(rhs, "<")? == Some(Ordering::Less))),
+      OpCode::LessEqual => Ok(Value::Boolean(matches!(
+        lhs.compare(rhs, "<=")?,
+        Some(Ordering::Less | Ordering::Equal)
+      ))),
+      OpCode::Greater => Ok(Value::Boolean(lhs.compare(rhs, ">")? == Some(Ordering::Greater))),
+      OpCode::GreaterEqual => Ok(Value::Boolean(matches!(
+        lhs.compare(rhs, ">=")?,
+        Some(Ordering::Greater | Ordering::Equal)
+      ))),
+      OpCode::InstanceOf => match (lhs, rhs) {
+        (Value::Instance(i), Value::Class(c)) => {
+          Ok(Value::Boolean(i.borrow().class().derives_from(c)))
+        }
+        (_, Value::Class(_)) => Ok(Value::Boolean(false)),
+        (_, v) => Err(Error::Runtime(
+          format!("right-hand side of 'instanceof' is {}, not a class", v.type_name()),
+          None,
+        )),
+      },
+      op => Err(Error::Runtime(format!("{} is not a binary operator", op), None)),
+    }
+  }
+
   fn native_println(args: Vec<Value>) -> Result<Value> {
     let ret = Self::native_print(args);
     println!();
@@ -187,8 +1405,1249 @@ impl Vm {
     use std::io::Write;
     let mut stdout = std::io::stdout();
     for a in args {
-      write!(&mut stdout, "{}", a).map_err(|e| Error::IO(e))?;
+      write!(&mut stdout, "{}", a.to_display_string()).map_err(Error::IO)?;
     }
     Ok(Value::None)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, rc::Rc};
+
+  use super::*;
+  use crate::loader::{MemoryLoader, SearchPathLoader};
+
+  /// Run `src` in a fresh `Vm`, returning the values passed to `out(...)`.
+  fn run_capturing(vm: &mut Vm, src: &str) -> Result<Vec<Value>> {
+    let out = Rc::new(RefCell::new(vec![]));
+    let sink = out.clone();
+    vm.add_native_func("out", move |args| {
+      sink.borrow_mut().extend(args);
+      Ok(Value::None)
+    })?;
+    vm.add_script(Script::new("virtual://test", Some("test"), Some(src)));
+    vm.run()?;
+    let ret = out.borrow().clone();
+    Ok(ret)
+  }
+
+  #[test]
+  fn expressions_are_evaluated() {
+    let mut vm = Vm::default();
+    vm.set_global("answer", Value::Integer(42));
+    let out = run_capturing(
+      &mut vm,
+      "out(1 + 2 * 3, 7 / 2, 7 % 4, -answer, \"a\" + 1, 1 < 2 && !false, null);",
+    )
+    .unwrap();
+    assert_eq!(
+      out,
+      vec![
+        Value::Integer(7),
+        Value::Double(3.5),
+        Value::Integer(3),
+        Value::Integer(-42),
+        Value::String("a1".into()),
+        Value::Boolean(true),
+        Value::None,
+      ]
+    );
+  }
+
+  #[test]
+  fn undefined_identifiers_are_reported() {
+    match run_capturing(&mut Vm::default(), "out(\n  missing);").map_err(Error::into_root) {
+      Err(Error::Runtime(msg, Some(loc))) => {
+        assert_eq!(msg, "undefined identifier 'missing'");
+        assert_eq!((*loc.line(), *loc.column()), (2, 3));
+      }
+      r => panic!("expected a runtime error, got {:?}", r),
+    }
+    // assignments are reported at the name assigned to
+    match run_capturing(&mut Vm::default(), "let a = 1;\n  missing = a;").map_err(Error::into_root) {
+      Err(Error::Runtime(msg, Some(loc))) => {
+        assert_eq!(msg, "undefined identifier 'missing'");
+        assert_eq!((*loc.line(), *loc.column()), (2, 3));
+      }
+      r => panic!("expected a runtime error, got {:?}", r),
+    }
+  }
+
+  #[test]
+  fn functions_bind_params_and_return() {
+    let out = run_capturing(
+      &mut Vm::default(),
+      "function add(a, b) {
+        return a + b;
+      }
+      function countdown(n) {
+        return n <= 0 || countdown(n - 1);
+      }
+      function missing(a, b) {
+        return b;
+      }
+      out(add(1, add(2, 3)), missing(1), add(\"a\", \"b\"), countdown(10));",
+    );
+    assert_eq!(
+      out.unwrap(),
+      vec![
+        Value::Integer(6),
+        Value::None,
+        Value::String("ab".into()),
+        Value::Boolean(true)
+      ]
+    );
+  }
+
+  #[test]
+  fn recursion_is_capped() {
+    let mut vm = Vm::default();
+    *vm.max_call_depth_mut() = 16;
+    let e = run_capturing(&mut vm, "function f(n) {\n  return f(n + 1);\n}\nf(0);").unwrap_err();
+    // the repeated frames are summed up
+    let trace: Vec<String> = e.to_string().lines().skip(1).map(String::from).collect();
+    assert_eq!(trace, [
+      "  at f (test:2:10)",
+      "  at f (test:2:10)",
+      "  at f (test:2:10)",
+      "  ... 13 more frames in f",
+      "  at test (test:4:1)",
+    ]);
+    assert_eq!(vm.render_error(&e).matches("= note:").count(), 5);
+    // and long traces are cut anyway
+    let mut deep = Vm::default();
+    *deep.max_call_depth_mut() = 64;
+    let src = "function g(n) { return h(n); }\nfunction h(n) { return g(n); }\ng(0);";
+    let trace = run_capturing(&mut deep, src).unwrap_err().to_string();
+    assert_eq!(trace.lines().count(), 34);
+    assert_eq!(trace.lines().last(), Some("  ... 33 more frames"));
+    match e.into_root() {
+      Error::Runtime(msg, Some(loc)) => {
+        assert_eq!(msg, "maximum call depth of 16 exceeded");
+        assert_eq!(*loc.line(), 2);
+      }
+      r => panic!("expected a runtime error, got {:?}", r),
+    }
+  }
+
+  #[test]
+  fn variables_are_block_scoped() {
+    let out = run_capturing(
+      &mut Vm::default(),
+      "let x = 1 + 2 * 3;
+      const y = x;
+      let z;
+      {
+        let x = 10;
+        x += 5;
+        z = x;
+      }
+      function f(a) {
+        let b = a * 2;
+        x = b;
+        return b;
+      }
+      out(x, y, z, f(4), x);",
+    );
+    assert_eq!(
+      out.unwrap(),
+      vec![
+        Value::Integer(7),
+        Value::Integer(7),
+        Value::Integer(15),
+        Value::Integer(8),
+        Value::Integer(8)
+      ]
+    );
+  }
+
+  #[test]
+  fn constants_cannot_be_reassigned() {
+    match run_capturing(&mut Vm::default(), "const c = 1;\nc = 2;").map_err(Error::into_root) {
+      Err(Error::Runtime(msg, Some(loc))) => {
+        assert_eq!(msg, "cannot assign to constant 'c'");
+        assert_eq!((*loc.line(), *loc.column()), (2, 1));
+      }
+      r => panic!("expected a runtime error, got {:?}", r),
+    }
+  }
+
+  #[test]
+  fn temporal_dead_zone_is_enforced() {
+    match run_capturing(&mut Vm::default(), "{\n  out(v);\n  let v = 1;\n}").map_err(Error::into_root) {
+      Err(Error::Runtime(msg, Some(loc))) => {
+        assert_eq!(msg, "cannot access 'v' before initialization");
+        assert_eq!((*loc.line(), *loc.column()), (2, 7));
+      }
+      r => panic!("expected a runtime error, got {:?}", r),
+    }
+  }
+
+  #[test]
+  fn control_flow_works() {
+    let out = run_capturing(
+      &mut Vm::default(),
+      "function fib(n) {
+        if (n < 2) {
+          return n;
+        } else {
+          return fib(n - 1) + fib(n - 2);
+        }
+      }
+      let sum = 0;
+      for (let i = 0; i < 10; i += 1) {
+        if (i % 2 == 0) continue;
+        if (i > 7) break;
+        sum += i;
+      }
+      let n = 0;
+      while (true) {
+        n += 1;
+        if (n >= 5) break;
+      }
+      out(fib(10), sum, n);
+      out(!0, !\"\", 0 || \"default\", 1 && null, !!\"s\");",
+    );
+    assert_eq!(
+      out.unwrap(),
+      vec![
+        Value::Integer(55),
+        Value::Integer(16),
+        Value::Integer(5),
+        Value::Boolean(true),
+        Value::Boolean(true),
+        Value::String("default".into()),
+        Value::None,
+        Value::Boolean(true),
+      ]
+    );
+  }
+
+  #[test]
+  fn scripts_import_what_others_export() {
+    let mut vm = Vm::default();
+    vm.add_script(Script::new(
+      "virtual://lib/util",
+      Some("util"),
+      Some(
+        "export function twice(a) {\n  return helper(a) * 2;\n}
+        function helper(a) {\n  return a;\n}
+        export const base = 5;
+        out(\"loaded\");",
+      ),
+    ));
+    let main = vm.scripts().len();
+    vm.add_script(Script::new(
+      "virtual://main",
+      Some("main"),
+      Some(
+        "import { twice, base as b } from \"./lib/util\";
+        import * as util from \"./lib/util\";
+        out(twice(21), b, util.twice(b), util.helper);",
+      ),
+    ));
+    vm.add_script(Script::new(
+      "virtual://other",
+      Some("other"),
+      Some("import { base } from \"./lib/../lib/./util\"; out(base + 1);"),
+    ));
+    let out = run_capturing(&mut vm, "import { base } from \"util\"; out(base + 2);").unwrap();
+    assert_eq!(out, [
+      Value::String("loaded".into()),
+      Value::Integer(42),
+      Value::Integer(5),
+      Value::Integer(10),
+      Value::None,
+      Value::Integer(6),
+      Value::Integer(7),
+    ]);
+    assert_eq!(*vm.scripts()[main].state(), ScriptState::FINISHED);
+    let util = vm.module("util").unwrap();
+    assert_eq!(util.exports(), &["twice", "base"]);
+    assert!(util.get("helper").is_ok());
+    assert!(util.export("helper").is_err());
+    assert!(vm.module("main").unwrap().get("helper").is_err());
+  }
+
+  #[test]
+  fn imports_copy_exported_values() {
+    let mut vm = Vm::default();
+    vm.add_script(Script::new(
+      "virtual://counter",
+      Some("counter"),
+      Some("export let n = 0;\nexport function inc() { n += 1; return n; }"),
+    ));
+    let out = run_capturing(
+      &mut vm,
+      "import { n, inc } from \"./counter\";\nimport * as counter from \"./counter\";\nout(inc(), n, counter.n);",
+    )
+    .unwrap();
+    assert_eq!(out, [Value::Integer(1), Value::Integer(0), Value::Integer(0)]);
+  }
+
+  #[test]
+  fn module_errors_are_reported() {
+    let mut vm = Vm::default();
+    vm.add_script(Script::new("virtual://a", Some("a"), Some("import { b } from \"./b\";")));
+    vm.add_script(Script::new(
+      "virtual://b",
+      Some("b"),
+      Some("export const b = 1;\nimport { a } from \"./a\";"),
+    ));
+    let e = vm.run().unwrap_err();
+    assert_eq!(e.to_string().lines().next().unwrap(), "Module: import cycle: a -> b -> a at b:2:1");
+    assert!(vm.render_error(&e).contains("error[E0007]: import cycle: a -> b -> a"));
+
+    let errors = [
+      ("import { x } from \"./missing\";", "cannot find module './missing'"),
+      ("import { helper } from \"lib\";", "'lib' does not export 'helper'"),
+      ("function f() { import { x } from \"lib\"; }", "'import' is only allowed at the top level of a script"),
+      ("{ export let x = 1; }", "'export' is only allowed at the top level of a script"),
+      ("export out(1);", "expected declaration but found 'out'"),
+    ];
+    for (src, msg) in errors {
+      let mut vm = Vm::default();
+      vm.add_script(Script::new("virtual://lib", Some("lib"), Some("function helper() {}")));
+      match run_capturing(&mut vm, src).map_err(Error::into_root) {
+        Err(Error::Module(m, _)) | Err(Error::Syntax(m, _)) => assert_eq!(m, msg),
+        r => panic!("unexpected {:?} for {}", r, src),
+      }
+    }
+  }
+
+  #[test]
+  fn modules_are_served_by_the_loader() {
+    let mut vm = Vm::default();
+    let scripts = MemoryLoader::new()
+      .with(
+        "virtual://app/main.vm",
+        "import { greet } from \"./greet\";
+        import { pad } from \"strings\";
+        out(greet(pad(\"world\")));",
+      )
+      .with("virtual://app/greet.vm", "export function greet(name) { return \"hello\" + name; }")
+      .with("virtual://vendor/strings.vm", "export function pad(s) { return \" \" + s; }");
+    vm.set_loader(SearchPathLoader::new(&["virtual://vendor"], scripts));
+    assert!(vm.load("virtual://app/missing.vm", None::<&str>).is_err());
+    vm.load("virtual://app/main.vm", None::<&str>).unwrap();
+    let out = run_capturing(&mut vm, "").unwrap();
+    assert_eq!(out, [Value::String("hello world".into())]);
+    assert_eq!(vm.scripts().len(), 4);
+    assert!(vm.loader().exists(Path::new("virtual://app/greet.vm")));
+  }
+
+  #[test]
+  fn modules_with_the_same_name_are_told_apart() {
+    let mut vm = Vm::default();
+    vm.set_loader(
+      MemoryLoader::new()
+        .with("virtual://app/main.vm", "import { a } from \"./a/util\"; out(a);")
+        .with(
+          "virtual://app/a/util.vm",
+          "import { b } from \"../b/util\"; export const a = \"a\" + b;",
+        )
+        .with("virtual://app/b/util.vm", "export const b = \"b\";")
+        .with("virtual://app/c/util.vm", "export const c = 1;\nexport const d = nope;"),
+    );
+    vm.load("virtual://app/main.vm", None::<&str>).unwrap();
+    let out = run_capturing(&mut vm, "").unwrap();
+    assert_eq!(out, [Value::String("ab".into())]);
+    let paths = ["virtual://app/a/util.vm", "virtual://app/b/util.vm"].map(Module::normalize);
+    assert!(paths.iter().all(|p| vm.modules().contains_key(p)));
+    let b = paths[1].display().to_string();
+    assert_eq!(vm.module("util").unwrap().get("a").unwrap(), Value::String("ab".into()));
+    assert_eq!(vm.module(&b).unwrap().get("b").unwrap(), Value::String("b".into()));
+
+    // errors point at the script they happened in
+    vm.add_script(Script::new(
+      "virtual://app/other.vm",
+      Some("other"),
+      Some("import { c } from \"./c/util\";"),
+    ));
+    let e = vm.run().map_err(Error::into_root).unwrap_err();
+    let rendered = vm.render_error(&e);
+    let c = Module::normalize("virtual://app/c/util.vm").display().to_string();
+    assert!(rendered.contains(&format!("--> {}:2:", c)), "{}", rendered);
+    assert!(rendered.contains("export const d = nope;"), "{}", rendered);
+  }
+
+  #[test]
+  fn modules_are_loaded_relative_to_their_script() {
+    let dir = std::env::temp_dir().join(format!("rs-vm-modules-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(dir.join("main.vm"), "import { answer } from \"./lib/answer\"; out(answer());").unwrap();
+    std::fs::write(
+      dir.join("lib/answer.vm"),
+      "import { half } from \"../half.vm\";\nexport function answer() { return half * 2; }",
+    )
+    .unwrap();
+    std::fs::write(dir.join("half.vm"), "export const half = 21;").unwrap();
+
+    let mut vm = Vm::default();
+    vm.load(dir.join("main.vm"), None::<&str>).unwrap();
+    let out = run_capturing(&mut vm, "").unwrap();
+    assert_eq!(out, [Value::Integer(42)]);
+    assert!(vm.module("answer").is_some());
+    assert!(vm.module("half").is_some());
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn hosts_call_script_functions() {
+    let mut vm = Vm::default();
+    vm.add_script(Script::new(
+      "virtual://calc",
+      Some("calc"),
+      Some(
+        "let calls = 0;
+        function sum(a, b) { calls += 1; return a + b; }
+        function describe(name, n) { return name + \"=\" + n; }
+        function fail() { throw \"boom\"; }",
+      ),
+    ));
+    let n: i64 = vm.call_typed("calc", "sum", (1, 2)).unwrap();
+    assert_eq!(n, 3);
+    assert_eq!(*vm.script("calc").unwrap().state(), ScriptState::FINISHED);
+    let x: f64 = vm.call_typed("calc", "sum", (0.5, 2)).unwrap();
+    assert_eq!(x, 2.5);
+    let s: String = vm.call_typed("calc", "describe", ("n", 4)).unwrap();
+    assert_eq!(s, "n=4");
+    assert_eq!(
+      vm.call("calc", "sum", vec![Value::Integer(2), Value::Integer(3)]).unwrap(),
+      Value::Integer(5)
+    );
+    assert_eq!(vm.module("calc").unwrap().get("calls").unwrap(), Value::Integer(3));
+
+    assert!(vm.call_typed::<_, _, _, bool>("calc", "sum", (1, 2)).is_err());
+    assert!(vm.call("calc", "fail", vec![]).is_err());
+    assert_eq!(
+      format!("{}", vm.call("calc", "calls", vec![]).unwrap_err()),
+      "Unknown: 'calc' has no function 'calls'"
+    );
+    assert!(vm.call("nope", "sum", vec![]).is_err());
+    // the vm is still usable after failed calls
+    assert_eq!(vm.call_typed::<_, _, _, i64>("calc", "sum", (4, 4)).unwrap(), 8);
+
+    // scripts whose top level failed run again rather than serve half-defined functions
+    vm.add_script(Script::new(
+      "virtual://broken",
+      Some("broken"),
+      Some("function f() { return 1; }\nthrow \"boom\";"),
+    ));
+    assert!(vm.run().is_err());
+    assert!(vm.call("broken", "f", vec![]).is_err());
+    assert_eq!(*vm.script("broken").unwrap().state(), ScriptState::PARSED);
+  }
+
+  #[test]
+  fn typed_natives_convert_their_arguments() {
+    let mut vm = Vm::default();
+    vm.add_typed_func("add", |a: i64, b: i64| a + b).unwrap();
+    vm.add_typed_func("scale", |v: Vec<f64>, by: Option<f64>| {
+      v.into_iter().map(|x| x * by.unwrap_or(2.0)).collect::<Vec<_>>()
+    })
+    .unwrap();
+    vm.add_typed_func("keys", |o: HashMap<String, Value>| {
+      let mut keys: Vec<String> = o.into_keys().collect();
+      keys.sort();
+      keys
+    })
+    .unwrap();
+    vm.add_typed_func("swap", |(a, b): (String, bool)| (b, a)).unwrap();
+    vm.add_typed_func("checked", |n: u8| -> Result<u8> {
+      n.checked_mul(2)
+        .ok_or_else(|| Error::Runtime("too large".into(), None))
+    })
+    .unwrap();
+    let out = run_capturing(
+      &mut vm,
+      "out(add(1, 2), scale([1, 2.5]), scale([1], 3.0), keys({ b: 1, a: 2 }),
+        swap([\"x\", true]), checked(4));",
+    )
+    .unwrap();
+    let show: Vec<String> = out.iter().map(|v| v.to_string()).collect();
+    assert_eq!(
+      show,
+      ["3", "[2, 5]", "[3]", "[\"a\", \"b\"]", "[true, \"x\"]", "8"]
+    );
+
+    let error = |src: &str| {
+      let mut vm = Vm::default();
+      vm.add_typed_func("add", |a: i64, b: i64| a + b).unwrap();
+      vm.add_typed_func("checked", |n: u8| n).unwrap();
+      let e = run_capturing(&mut vm, src).unwrap_err();
+      format!("{}", e.into_root())
+    };
+    assert_eq!(
+      error("add(1);"),
+      "Runtime: add: expected 2 arguments, got 1 at test:1:1"
+    );
+    assert_eq!(
+      error("add(1, \"2\");"),
+      "Runtime: add: argument 2: expected integer, got string at test:1:1"
+    );
+    assert_eq!(
+      error("checked(300);"),
+      "Runtime: checked: argument 1: integer 300 is out of range for u8 at test:1:1"
+    );
+  }
+
+  #[test]
+  fn reloads_swap_functions_and_keep_state() {
+    let dir = std::env::temp_dir().join(format!("rs-vm-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let counter = dir.join("counter.vm");
+    std::fs::write(
+      &counter,
+      "let count = 0;\nexport function bump() { count += 1; return count; }",
+    )
+    .unwrap();
+    std::fs::write(dir.join("main.vm"), "import { bump } from \"./counter\"; bump();").unwrap();
+    let mut vm = Vm::default();
+    vm.load(dir.join("main.vm"), None::<&str>).unwrap();
+    vm.run().unwrap();
+    let call = |vm: &mut Vm, module: &str, name: &str| {
+      let f = vm.module(module).unwrap().get(name).unwrap();
+      vm.call_value(&f, vec![])
+    };
+    assert_eq!(call(&mut vm, "main", "bump").unwrap(), Value::Integer(2));
+    assert!(!vm.reload("counter").unwrap());
+
+    std::fs::write(
+      &counter,
+      "let count = 0;
+      export function bump() { count += 10; return count; }
+      export function twice() { return count * 2; }",
+    )
+    .unwrap();
+    assert!(vm.reload("counter").unwrap());
+    assert_eq!(call(&mut vm, "main", "bump").unwrap(), Value::Integer(12));
+    assert_eq!(call(&mut vm, "counter", "twice").unwrap(), Value::Integer(24));
+    assert_eq!(vm.module("counter").unwrap().exports(), &["bump", "twice"]);
+
+    std::fs::write(&counter, "export function bump( {").unwrap();
+    assert!(vm.reload("counter").is_err());
+    assert_eq!(call(&mut vm, "main", "bump").unwrap(), Value::Integer(22));
+    assert!(vm.reload("missing").is_err());
+
+    // the watcher picks up changes by modification time
+    let later = std::time::SystemTime::now() + Duration::from_secs(60);
+    let mut polls = 0;
+    vm.watch(Duration::ZERO, |vm, reloaded| {
+      polls += 1;
+      if polls == 1 {
+        assert!(reloaded.unwrap().is_empty());
+        std::fs::write(&counter, "let count = 0;\nexport function bump() { return -count; }").unwrap();
+        let file = std::fs::File::options().write(true).open(&counter).unwrap();
+        file.set_modified(later).unwrap();
+        return true;
+      }
+      assert_eq!(reloaded.unwrap(), ["counter"]);
+      assert_eq!(call(vm, "main", "bump").unwrap(), Value::Integer(-22));
+      false
+    });
+    assert_eq!(polls, 2);
+    assert_eq!(vm.script("counter").unwrap().modified(), Some(later));
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn failed_runs_can_be_reloaded() {
+    let dir = std::env::temp_dir().join(format!("rs-vm-failed-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("a.vm");
+    std::fs::write(&path, "export function f() { return 1; }\nthrow \"boom\";").unwrap();
+    let mut vm = Vm::default();
+    vm.load(&path, None::<&str>).unwrap();
+    assert!(vm.run().is_err());
+    assert_eq!(*vm.script("a").unwrap().state(), ScriptState::PARSED);
+    assert!(vm.module("a").is_none());
+
+    std::fs::write(&path, "export function f() { return 2; }").unwrap();
+    assert!(vm.reload("a").unwrap());
+    vm.run().unwrap();
+    let f = vm.module("a").unwrap().get("f").unwrap();
+    assert_eq!(vm.call_value(&f, vec![]).unwrap(), Value::Integer(2));
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn precompiled_scripts_skip_parsing() {
+    let mut vm = Vm::default();
+    vm.add_script(Script::new("virtual://lib", Some("lib"), Some("export function twice(a) { return a * 2; }")));
+    let bytes = vm.precompile("lib").unwrap();
+    assert_eq!(*vm.script("lib").unwrap().state(), ScriptState::PARSED);
+
+    let mut vm = Vm::default();
+    vm.add_precompiled(&bytes, None::<&str>).unwrap();
+    assert!(vm.script("lib").unwrap().content().is_none());
+    let out = run_capturing(&mut vm, "import { twice } from \"lib\"; out(twice(21));");
+    assert_eq!(out.unwrap(), vec![Value::Integer(42)]);
+    assert_eq!(*vm.script("lib").unwrap().state(), ScriptState::FINISHED);
+  }
+
+  #[test]
+  fn stale_caches_are_refreshed() {
+    let dir = std::env::temp_dir().join(format!("rs-vm-cache-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (src, cache) = (dir.join("main.vm"), dir.join("main.vmc"));
+
+    std::fs::write(&src, "let a = 1;").unwrap();
+    let mut vm = Vm::default();
+    assert_eq!(*vm.load_cached(&src, &cache, None::<&str>).unwrap().state(), ScriptState::PARSED);
+    let first = std::fs::read(&cache).unwrap();
+    assert!(Precompiled::from_bytes(&first).unwrap().is_compiled_from("let a = 1;"));
+
+    std::fs::write(&src, "let a = 2;").unwrap();
+    let mut vm = Vm::default();
+    vm.load_cached(&src, &cache, None::<&str>).unwrap();
+    vm.run().unwrap();
+    assert_eq!(vm.module("main").unwrap().get("a").unwrap(), Value::Integer(2));
+    assert_ne!(std::fs::read(&cache).unwrap(), first);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn errors_are_rendered_with_their_source() {
+    let mut vm = Vm::default();
+    let e = run_capturing(&mut vm, "let a = 1;\nout(a +\n  missing);").unwrap_err();
+    assert_eq!(
+      vm.render_error(&e),
+      "error[E0004]: undefined identifier 'missing'\n --> test:3:3\n  |\n3 |   missing);\n  |   ^\n  \
+       |\n  = note: at test (test:3:3)\n"
+    );
+  }
+
+  #[test]
+  fn classes_bind_this_in_methods() {
+    let mut vm = Vm::default();
+    let out = run_capturing(
+      &mut vm,
+      "class Counter {
+        count = 0;
+        step;
+        constructor(step) { this.step = step; }
+        add() { this.count += this.step; return this; }
+        get() { return this.count; }
+      }
+      const c = new Counter(2);
+      const alias = c;
+      c.add().add();
+      alias.step = 10;
+      alias.add();
+      class Empty {}
+      out(c.get(), c.count, alias == c, new Counter(1) == c, new Empty());",
+    )
+    .unwrap();
+    assert_eq!(out[..4], [
+      Value::Integer(14),
+      Value::Integer(14),
+      Value::Boolean(true),
+      Value::Boolean(false),
+    ]);
+    assert_eq!(out[4].to_string(), "<Empty instance>");
+  }
+
+  #[test]
+  fn missing_members_are_reported() {
+    for (src, msg) in [
+      ("class A {} new A().nope();", "undefined method 'nope' on A"),
+      ("class A {} out(new A().x);", "undefined property 'x' on A"),
+      ("let a = 1; a.x = 2;", "cannot set property 'x' of integer"),
+      ("let A = 1; new A();", "integer is not a class"),
+    ] {
+      match run_capturing(&mut Vm::default(), src).map_err(Error::into_root) {
+        Err(Error::Runtime(m, Some(_))) => assert_eq!(m, msg),
+        other => panic!("expected a runtime error, got {:?}", other),
+      }
+    }
+  }
+
+  #[test]
+  fn subclasses_inherit_and_override_methods() {
+    let mut vm = Vm::default();
+    let out = run_capturing(
+      &mut vm,
+      "class Shape {
+        sides = 0;
+        constructor(name) { this.name = name; }
+        describe() { return this.name + \" has \" + this.sides + \" sides\"; }
+        area() { return 0; }
+      }
+      class Rect extends Shape {
+        sides = 4;
+        constructor(w, h) { super(\"rect\"); this.w = w; this.h = h; }
+        area() { return this.w * this.h; }
+      }
+      class Square extends Rect {
+        constructor(s) { super(s, s); }
+        area() { return super.area() + 0; }
+        describe() { return \"square: \" + super.describe(); }
+      }
+      class Tagged { tag = \"tagged\"; }
+      class Unit extends Tagged {}
+      const s = new Square(3);
+      out(s.area(), s.describe(), new Rect(2, 5).area(), new Shape(\"blob\").describe());
+      out(s instanceof Square, s instanceof Shape, new Rect(1, 1) instanceof Square, 1 instanceof Shape);
+      out(new Unit().tag, new Unit() instanceof Tagged);",
+    )
+    .unwrap();
+    assert_eq!(out, [
+      Value::Integer(9),
+      Value::String("square: rect has 4 sides".into()),
+      Value::Integer(10),
+      Value::String("blob has 0 sides".into()),
+      Value::Boolean(true),
+      Value::Boolean(true),
+      Value::Boolean(false),
+      Value::Boolean(false),
+      Value::String("tagged".into()),
+      Value::Boolean(true),
+    ]);
+  }
+
+  #[test]
+  fn implicit_constructors_forward_their_arguments() {
+    let out = run_capturing(
+      &mut Vm::default(),
+      "class A { constructor(x, y) { this.x = x; this.y = y; } }
+      class B extends A {}
+      class C extends B { z = 3; }
+      out(new B(5).x, new C(1, 2).y, new C(1, 2).z, new B().x);",
+    )
+    .unwrap();
+    assert_eq!(out, [
+      Value::Integer(5),
+      Value::Integer(2),
+      Value::Integer(3),
+      Value::None,
+    ]);
+  }
+
+  #[test]
+  fn super_is_checked() {
+    for (src, msg) in [
+      ("class A { constructor() { super(); } }", "'super' can only be used in methods of a subclass"),
+      ("class A {} class B extends A { m() { super(); } }", "'super(...)' must be the first statement of a constructor"),
+      ("class A { m() { return super.m(); } }", "'super' can only be used in methods of a subclass"),
+    ] {
+      match run_capturing(&mut Vm::default(), src).map_err(Error::into_root) {
+        Err(Error::Syntax(m, _)) => assert_eq!(m, msg),
+        other => panic!("expected a syntax error, got {:?}", other),
+      }
+    }
+    for (src, msg) in [
+      ("let A = 1; class B extends A {}", "class B cannot extend integer"),
+      ("class A {} class B extends A { m() { super.m(); } } new B().m();", "undefined method 'm' on A"),
+      ("class A {} out(new A() instanceof 2);", "right-hand side of 'instanceof' is integer, not a class"),
+    ] {
+      match run_capturing(&mut Vm::default(), src).map_err(Error::into_root) {
+        Err(Error::Runtime(m, Some(_))) => assert_eq!(m, msg),
+        other => panic!("expected a runtime error, got {:?}", other),
+      }
+    }
+  }
+
+  #[test]
+  fn member_visibility_is_enforced() {
+    let classes = "class Account {
+        private balance = 0;
+        protected owner;
+        constructor(owner) { this.owner = owner; }
+        deposit(n) { this.balance = this.check(n) + this.balance; return this; }
+        total() { return this.balance; }
+        private check(n) { return n; }
+      }
+      class Savings extends Account {
+        constructor(owner) { super(owner); }
+        name() { return this.owner; }
+      }
+      function peek(a) { return a.balance; }
+      function rename(a) { a.owner = 1; }";
+    let mut vm = Vm::default();
+    let out = run_capturing(
+      &mut vm,
+      &format!(
+        "{} const s = new Savings(\"ann\").deposit(3).deposit(4); out(s.total(), s.name());",
+        classes
+      ),
+    )
+    .unwrap();
+    assert_eq!(out, [Value::Integer(7), Value::String("ann".into())]);
+
+    for (src, msg) in [
+      ("new Account(1).balance;", "'balance' has private access in Account"),
+      ("new Savings(1).check(2);", "'check' has private access in Account"),
+      ("new Account(1).owner = 2;", "'owner' has protected access in Account"),
+      ("class Spy extends Account { peek() { return this.balance; } }", "'balance' has private access in Account"),
+    ] {
+      match run_capturing(&mut Vm::default(), &format!("{} {}", classes, src)).map_err(Error::into_root) {
+        Err(Error::Syntax(m, span)) => {
+          assert_eq!(m, msg);
+          assert_eq!(*span.start().line(), 14);
+        }
+        other => panic!("expected a compile error, got {:?}", other),
+      }
+    }
+    for (src, msg) in [
+      ("peek(new Account(1));", "'balance' has private access in Account"),
+      ("peek(new Savings(1));", "'balance' has private access in Account"),
+      ("rename(new Savings(1));", "'owner' has protected access in Account"),
+    ] {
+      match run_capturing(&mut Vm::default(), &format!("{} {}", classes, src)).map_err(Error::into_root) {
+        Err(Error::Runtime(m, Some(_))) => assert_eq!(m, msg),
+        other => panic!("expected a runtime error, got {:?}", other),
+      }
+    }
+  }
+
+  #[test]
+  fn enums_have_valued_variants() {
+    let mut vm = Vm::default();
+    let out = run_capturing(
+      &mut vm,
+      "enum Color { Red, Green = 5, Blue, Black = -2, White, }
+      out(Color.Red, Color.Green, Color.Blue, Color.White, Color.name(6), Color.name(7));
+      let names = \"\";
+      for (let i = 0; i < Color.count(); i += 1) {
+        names += Color.name(Color.at(i)) + \" \";
+      }
+      out(names, Color.at(5), Color);",
+    )
+    .unwrap();
+    assert_eq!(out, [
+      Value::Integer(0),
+      Value::Integer(5),
+      Value::Integer(6),
+      Value::Integer(-1),
+      Value::String("Blue".into()),
+      Value::None,
+      Value::String("Red Green Blue Black White ".into()),
+      Value::None,
+      out[8].clone(),
+    ]);
+    assert_eq!(out[8].to_string(), "<enum Color>");
+
+    let color = match vm.module("test").unwrap().get("Color").unwrap() {
+      Value::Enum(e) => e,
+      v => panic!("unexpected {}", v),
+    };
+    vm.set_global("Color", Value::Enum(color.clone()));
+    assert_eq!(vm.enumeration("Color").unwrap(), color);
+    assert_eq!(color.name_of(-2), Some(&"Black".to_string()));
+    assert_eq!(color.variant("Green"), Some(5));
+    assert_eq!(
+      color.variants().iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(),
+      ["Red", "Green", "Blue", "Black", "White"]
+    );
+    assert!(vm.enumeration("out").is_err());
+  }
+
+  #[test]
+  fn enum_misuse_is_reported() {
+    match run_capturing(&mut Vm::default(), "enum E { A, B, A }").map_err(Error::into_root) {
+      Err(Error::Syntax(m, _)) => assert_eq!(m, "duplicate variant 'A' in E"),
+      other => panic!("expected a syntax error, got {:?}", other),
+    }
+    for (src, msg) in [
+      ("enum E { A } E.B;", "undefined variant 'B' on E"),
+      ("enum E { A } E.A = 2;", "cannot set property 'A' of enum"),
+      ("enum E { A } E.at();", "invalid arguments for E.at"),
+    ] {
+      match run_capturing(&mut Vm::default(), src).map_err(Error::into_root) {
+        Err(Error::Runtime(m, Some(_))) => assert_eq!(m, msg),
+        other => panic!("expected a runtime error, got {:?}", other),
+      }
+    }
+  }
+
+  #[test]
+  fn thrown_values_are_caught() {
+    let out = run_capturing(
+      &mut Vm::default(),
+      "function check(n) {
+        if (n > 2) { throw \"too big: \" + n; }
+        return n;
+      }
+      function sum(n) {
+        let total = 0;
+        for (let i = 1; i <= n; i += 1) { total += check(i); }
+        return total;
+      }
+      try {
+        out(\"try\", sum(2));
+        out(sum(5));
+        out(\"unreachable\");
+      } catch (e) {
+        out(e);
+      } finally {
+        out(\"finally\");
+      }
+      try { throw 1; } catch { out(\"caught\"); }
+      try {
+        try { throw 2; } finally { out(\"inner\"); }
+      } catch (e) {
+        out(e);
+      }
+      try {
+        try { throw 3; } catch (e) { throw e + 1; } finally { out(\"cleanup\"); }
+      } catch (e) {
+        out(e);
+      }",
+    )
+    .unwrap();
+    assert_eq!(out, [
+      Value::String("try".into()),
+      Value::Integer(3),
+      Value::String("too big: 3".into()),
+      Value::String("finally".into()),
+      Value::String("caught".into()),
+      Value::String("inner".into()),
+      Value::Integer(2),
+      Value::String("cleanup".into()),
+      Value::Integer(4),
+    ]);
+  }
+
+  #[test]
+  fn finally_runs_when_leaving_early() {
+    let out = run_capturing(
+      &mut Vm::default(),
+      "function first() {
+        try { return 1; } finally { out(\"returning\"); }
+      }
+      out(first());
+      for (let i = 0; i < 3; i += 1) {
+        try {
+          if (i == 1) { continue; }
+          if (i == 2) { break; }
+          out(i);
+        } finally {
+          out(\"after \" + i);
+        }
+      }",
+    )
+    .unwrap();
+    assert_eq!(out, [
+      Value::String("returning".into()),
+      Value::Integer(1),
+      Value::Integer(0),
+      Value::String("after 0".into()),
+      Value::String("after 1".into()),
+      Value::String("after 2".into()),
+    ]);
+  }
+
+  #[test]
+  fn runtime_errors_become_error_objects() {
+    let mut vm = Vm::default();
+    vm.add_native_func("fail", |_| Err(Error::Runtime("native failure".into(), None)))
+      .unwrap();
+    let out = run_capturing(
+      &mut vm,
+      "class NotFound extends Error {
+        constructor(what) { super(what + \" not found\"); }
+      }
+      function lookup() {
+        return missing;
+      }
+      try {
+        lookup();
+      } catch (e) {
+        out(e.message, e.stack, e instanceof Error);
+      }
+      try {
+        throw new NotFound(\"key\");
+      } catch (e) {
+        out(e.message, e instanceof NotFound, e instanceof Error, e.stack);
+      }
+      try { fail(); } catch (e) { out(e.message); }",
+    )
+    .unwrap();
+    assert_eq!(out, [
+      Value::String("undefined identifier 'missing'".into()),
+      Value::String("at lookup (test:5:16)\nat test (test:8:9)".into()),
+      Value::Boolean(true),
+      Value::String("key not found".into()),
+      Value::Boolean(true),
+      Value::Boolean(true),
+      Value::String("at test (test:13:15)".into()),
+      Value::String("native failure".into()),
+    ]);
+  }
+
+  #[test]
+  fn uncaught_values_are_reported() {
+    let mut vm = Vm::default();
+    let src = "try { out(1); } finally { out(2); }\n  throw new Error(\"boom\");";
+    match run_capturing(&mut vm, src).map_err(Error::into_root) {
+      Err(e @ Error::Thrown(_, Some(_))) => {
+        assert_eq!(e.to_string(), "Uncaught: Error: boom at test:2:3");
+      }
+      other => panic!("expected an uncaught value, got {:?}", other),
+    }
+    match run_capturing(&mut Vm::default(), "throw 42;").map_err(Error::into_root) {
+      Err(Error::Thrown(Value::Integer(42), _)) => {}
+      other => panic!("expected an uncaught value, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn runtime_errors_carry_a_stack_trace() {
+    let mut vm = Vm::default();
+    let e = run_capturing(
+      &mut vm,
+      "function inner(n) {
+        return n / 0;
+      }
+      function outer() {
+        return inner(1) + 1;
+      }
+      out(outer());",
+    )
+    .unwrap_err();
+    let trace = e.trace().unwrap();
+    assert_eq!(
+      trace
+        .frames()
+        .iter()
+        .map(|f| (f.function().as_str(), *f.location().unwrap().line()))
+        .collect::<Vec<_>>(),
+      [("inner", 2), ("outer", 5), ("test", 7)]
+    );
+    assert_eq!(trace.frames()[0].script(), Some(&"test".to_string()));
+    assert_eq!(
+      e.to_string(),
+      "Runtime: division by zero at test:2:18\n  at inner (test:2:18)\n  at outer (test:5:16)\n  \
+       at test (test:7:11)"
+    );
+    assert!(matches!(e.into_root(), Error::Runtime(..)));
+  }
+
+  #[test]
+  fn arrays_and_objects_are_shared() {
+    let out = run_capturing(
+      &mut Vm::default(),
+      "let point = { x: 1, \"y\": 2, };
+      let points = [point, { x: 3, y: 4 }];
+      let alias = points;
+      alias[1].x = 5;
+      points[0][\"y\"] += 10;
+      alias[alias.length] = [];
+      out(points[1].x, point.y, points.length, alias == points, [] == [], points[9], point.z);
+      let cycle = [1];
+      cycle[1] = cycle;
+      out(points, { b: 1, a: [true, \"s\"] }, cycle);",
+    )
+    .unwrap();
+    assert_eq!(out[..7], [
+      Value::Integer(5),
+      Value::Integer(12),
+      Value::Integer(3),
+      Value::Boolean(true),
+      Value::Boolean(false),
+      Value::None,
+      Value::None,
+    ]);
+    assert_eq!(out[7].to_string(), "[{x: 1, y: 12}, {x: 5, y: 4}, []]");
+    assert_eq!(out[8].to_string(), "{a: [true, \"s\"], b: 1}");
+    assert_eq!(out[9].to_string(), "[1, [...]]");
+    assert_eq!(format!("{:?}", out[9]), "Array([Integer(1), [...]])");
+    assert_eq!(out[9], out[9].clone());
+    assert_ne!(out[9], Value::array(vec![Value::Integer(1)]));
+
+    // errors holding cycles can be shown
+    let e = run_capturing(&mut Vm::default(), "let o = {}; o.self = o; throw o;").unwrap_err();
+    assert!(format!("{:?}", e).contains("Object({\"self\": {...}})"));
+  }
+
+  #[test]
+  fn compound_assignments_evaluate_their_target_once() {
+    let out = run_capturing(
+      &mut Vm::default(),
+      "let objs = [{ n: 1 }, { n: 100 }];
+      let calls = 0;
+      function next() { calls += 1; return objs[calls - 1]; }
+      function at() { calls += 1; return calls - 2; }
+      next().n += 1;
+      out(objs[0].n, objs[1].n, calls);
+      let arr = [10, 20];
+      arr[at()] *= 3;
+      arr[at()] -= 1;
+      let s = \"a\";
+      out(s += \"b\", arr[0], arr[1], calls);",
+    )
+    .unwrap();
+    assert_eq!(out, [
+      Value::Integer(2),
+      Value::Integer(100),
+      Value::Integer(1),
+      Value::String("ab".into()),
+      Value::Integer(30),
+      Value::Integer(19),
+      Value::Integer(3),
+    ]);
+  }
+
+  #[test]
+  fn invalid_indexing_is_reported() {
+    for (src, msg) in [
+      ("let a = [1]; a[\"x\"];", "cannot index array with string"),
+      ("let o = {}; o[0] = 1;", "cannot index object with integer"),
+      ("let a = [1]; a[2] = 1;", "index 2 out of bounds for array of length 1"),
+      ("let a = [1]; a[-1] = 1;", "index -1 out of bounds for array of length 1"),
+      ("let n = 1; n[0];", "cannot index integer with integer"),
+    ] {
+      match run_capturing(&mut Vm::default(), src).map_err(Error::into_root) {
+        Err(Error::Runtime(m, Some(_))) => assert_eq!(m, msg),
+        other => panic!("expected a runtime error, got {:?}", other),
+      }
+    }
+  }
+
+  #[test]
+  fn closures_capture_their_environment() {
+    let mut vm = Vm::default();
+    let out = run_capturing(
+      &mut vm,
+      "function counter() {
+        let count = 0;
+        return () => { count += 1; return count; };
+      }
+      const a = counter();
+      const b = counter();
+      a();
+      a();
+      out(a(), b());
+      const add = function (x, y) { return x + y; };
+      const twice = f => x => f(f(x));
+      function square(n) { return n * n; }
+      function apply(f, v) { return f(v); }
+      out(add(1, 2), twice(x => x * 3)(2), (x => x + 1)(1), apply(square, 4));
+      const ops = { inc: x => x + 1, all: [add] };
+      out(ops.inc(1), ops.all[0](2, 3));
+      class Scaler {
+        private factor;
+        constructor(factor) { this.factor = factor; }
+        scale() { return (x) => x * this.factor; }
+      }
+      out(apply(new Scaler(10).scale(), 5));
+      const emit = out;
+      emit(emit, add, square);",
+    )
+    .unwrap();
+    assert_eq!(out[..9], [
+      Value::Integer(3),
+      Value::Integer(1),
+      Value::Integer(3),
+      Value::Integer(18),
+      Value::Integer(2),
+      Value::Integer(16),
+      Value::Integer(2),
+      Value::Integer(5),
+      Value::Integer(50),
+    ]);
+    let names: Vec<String> = out[9..].iter().map(Value::to_string).collect();
+    assert_eq!(names, [
+      "<native function out>",
+      "<function anonymous>",
+      "<function square>",
+    ]);
+
+    let square = vm.module("test").unwrap().get("square").unwrap();
+    assert_eq!(
+      vm.call_value(&square, vec![Value::Integer(7)]).unwrap(),
+      Value::Integer(49)
+    );
+    let out = vm.global("out").unwrap();
+    assert_eq!(vm.call_value(&out, vec![]).unwrap(), Value::None);
+    match vm.call_value(&Value::Integer(1), vec![]) {
+      Err(Error::Runtime(m, _)) => assert_eq!(m, "integer is not a function"),
+      other => panic!("expected a runtime error, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn loop_closures_capture_each_iteration() {
+    let out = run_capturing(
+      &mut Vm::default(),
+      "let fs = [0, 0, 0];
+      for (let i = 0; i < 3; i = i + 1) { fs[i] = () => i; }
+      out(fs[0](), fs[1](), fs[2]());
+      let gs = [0, 0, 0];
+      let n = 0;
+      for (let i = 0; i < 10; i += 1) {
+        if (i == 1) { i = 3; continue; }
+        if (i == 6) { break; }
+        gs[n] = () => i;
+        n += 1;
+      }
+      out(n, gs[0](), gs[1](), gs[2]());",
+    )
+    .unwrap();
+    assert_eq!(out, [
+      Value::Integer(0),
+      Value::Integer(1),
+      Value::Integer(2),
+      Value::Integer(3),
+      Value::Integer(0),
+      Value::Integer(4),
+      Value::Integer(5),
+    ]);
+  }
+
+  #[test]
+  fn unreachable_cycles_are_collected() {
+    let mut vm = Vm::default();
+    let out = run_capturing(
+      &mut vm,
+      "function garbage() {
+        const a = [];
+        const o = { items: a };
+        a[0] = o;
+        function again() { return again; }
+        return 0;
+      }
+      function counter() {
+        let count = 0;
+        return () => { count += 1; return count; };
+      }
+      for (let i = 0; i < 5; i += 1) garbage();
+      const kept = [];
+      kept[0] = kept;
+      const held = counter();
+      out(counter());",
+    )
+    .unwrap();
+    let tracked = vm.heap().len();
+    let stats = vm.gc();
+    assert_eq!(stats.tracked(), tracked);
+    // each call left its scope, an array, an object and a function behind
+    assert_eq!(stats.freed(), 20);
+    assert_eq!(vm.heap().len(), stats.live());
+    assert_eq!(vm.gc().freed(), 0);
+    assert_eq!(vm.heap().collections(), 2);
+
+    // values held by globals or by the host survive
+    let module = vm.module("test").unwrap();
+    assert_eq!(module.get("kept").unwrap().to_string(), "[[...]]");
+    let held = module.get("held").unwrap();
+    assert_eq!(vm.call_value(&held, vec![]).unwrap(), Value::Integer(1));
+    assert_eq!(vm.call_value(&out[0], vec![]).unwrap(), Value::Integer(1));
+
+    let mut vm = Vm::default();
+    *vm.heap_mut().threshold_mut() = 50;
+    run_capturing(
+      &mut vm,
+      "function garbage() {
+        const a = [];
+        a[0] = a;
+      }
+      for (let i = 0; i < 200; i += 1) garbage();",
+    )
+    .unwrap();
+    assert!(vm.heap().collections() > 0);
+    assert!(vm.heap().len() < 50);
+  }
+}